@@ -0,0 +1,28 @@
+//! Run with `cargo fuzz run parse_stylesheet` from the repository root.
+#![no_main]
+#![allow(dead_code, unused)]
+
+use libfuzzer_sys::fuzz_target;
+
+// The browser is a binary crate, so the parser's modules are compiled in here.
+#[path = "../../src/css.rs"]
+mod css;
+#[path = "../../src/css_parser.rs"]
+mod css_parser;
+#[path = "../../src/cssom.rs"]
+mod cssom;
+
+/// `css` sizes the default viewport after the window.
+mod render {
+    pub const WIDTH: u32 = 600;
+    pub const HEIGHT: u32 = 600;
+}
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(css) = std::str::from_utf8(data) {
+        let stylesheet = css_parser::CssParser::new(css).parse_stylesheet();
+        // what the serializer writes has to parse too
+        css_parser::CssParser::new(&stylesheet.to_css()).parse_stylesheet();
+        css_parser::CssParser::new(css).parse_inline_style();
+    }
+});
@@ -1,7 +1,10 @@
+use std::cell::RefCell;
 use std::env;
 use std::fs::File;
 use std::io::{BufReader, Read};
-use crate::css::Stylesheet;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::css::{MediaQueryList, Stylesheet, Viewport};
 use crate::css_parser::CssParser;
 use crate::dom::Node;
 use crate::dom::NodeType::{Element, Text};
@@ -10,9 +13,13 @@ use crate::layout;
 use crate::render::render;
 
 pub static mut NODES: Vec<Node> = Vec::new();
+/// The sheets styling the page, shared with scripts that edit them through
+/// `document.styleSheets`.
+pub type Stylesheets = Rc<RefCell<Vec<Stylesheet>>>;
 pub struct Browser {
     html: String,
-    stylesheet: Stylesheet,
+    /// Author stylesheets in cascade order, imported sheets before the sheet that imports them.
+    stylesheets: Stylesheets,
     js: String,
     nodes: Vec<Node>,
     title: String,
@@ -22,7 +29,7 @@ impl Browser {
     pub fn new(html: String) -> Self {
         Browser {
             html,
-            stylesheet: Stylesheet::default(),
+            stylesheets: Stylesheets::default(),
             js: String::new(),
             title: String::from("Browser"),
             nodes: Vec::new(),
@@ -31,23 +38,13 @@ impl Browser {
     pub fn run(&mut self) {
         let mut parser = HtmlParser::new(self.html.as_str());
         let nodes = parser.parse_nodes();
-        let head = nodes[0].children[0].clone();
-        for child in head.children {
+        for child in nodes[0].child_element("head").iter().flat_map(|head| &head.children) {
             if let Element(element_data) = &child.node_type {
                 if element_data.tag_name == "title" {
                     if let Text(text) = &child.children[0].node_type {
                         self.title = text.clone();
                     }
                 }
-                if element_data.tag_name == "link" {
-                    if let Some(rel) = element_data.attributes.get("rel") {
-                        if rel == "stylesheet" {
-                            if let Some(path) = element_data.attributes.get("href") {
-                                self.parse_css(path);
-                            }
-                        }
-                    }
-                }
                 if element_data.tag_name == "script" {
                     if !child.children.is_empty() {
                         if let Text(script) = &child.children[0].node_type {
@@ -62,25 +59,64 @@ impl Browser {
                 }
             }
         }
-        let mut body = nodes[0].children[1].clone();
-        body.add_styles(&self.stylesheet);
+        self.stylesheets.borrow_mut().push(CssParser::user_agent_stylesheet());
+        self.collect_stylesheets(&nodes[0]);
+        let unstyled_body = nodes[0].child_element("body").expect("the document has no body").clone();
+        let stylesheets = Rc::clone(&self.stylesheets);
+        let restyle = move |viewport: &Viewport| {
+            let mut body = unstyled_body.clone();
+            let stylesheets = stylesheets.borrow();
+            body.add_styles_from(&stylesheets.iter().collect::<Vec<_>>(), viewport);
+            body
+        };
+        let mut body = restyle(&Viewport::default());
         // for js read
         let body_for_js = body.clone();
         unsafe {
             NODES = vec![body_for_js];
         }
         if !self.js.is_empty() {
-            body.add_js(self.js.clone().as_str());
+            body.add_js(self.js.clone().as_str(), &self.stylesheets);
+            // scripts may have changed the stylesheets
+            body = restyle(&Viewport::default());
         }
         let boxes = layout::LayoutBox::build_layout_tree(&body);
-        render(boxes, &self.title);
+        render(boxes, &self.title, |viewport| {
+            let body = restyle(viewport);
+            layout::LayoutBox::build_layout_tree_with_viewport(&body, viewport)
+        });
     }
+    /// Loads `<link rel="stylesheet">` and `<style>` sheets in document order.
+    fn collect_stylesheets(&mut self, node: &Node) {
+        if let Element(element_data) = &node.node_type {
+            if element_data.tag_name == "link" {
+                if let Some(rel) = element_data.attributes.get("rel") {
+                    if rel == "stylesheet" {
+                        if let Some(path) = element_data.attributes.get("href") {
+                            self.parse_css(path);
+                        }
+                    }
+                }
+            }
+            if element_data.tag_name == "style" {
+                let css: String = node.children.iter().filter_map(|child| match &child.node_type {
+                    Text(text) => Some(text.as_str()),
+                    _ => None,
+                }).collect();
+                let stylesheet = CssParser::new(&css).parse_stylesheet();
+                add_stylesheet(stylesheet, &env::current_dir().unwrap(), &[], &mut Vec::new(), &mut self.stylesheets.borrow_mut());
+                return;
+            }
+        }
+        for child in &node.children {
+            self.collect_stylesheets(child);
+        }
+    }
+
     fn parse_css(&mut self, css_path: &str) {
         let mut path = env::current_dir().unwrap();
         path.push(css_path);
-        let css = std::fs::read_to_string(path).unwrap();
-        let mut parser = CssParser::new(&css);
-        self.stylesheet = parser.parse_stylesheet();
+        load_stylesheet(&path, &[], &mut Vec::new(), &mut self.stylesheets.borrow_mut());
     }
 
     fn read_js(&mut self, js_path: &String) {
@@ -94,4 +130,75 @@ impl Browser {
         file_reader.read_to_string(&mut js).unwrap();
         self.js += js.as_str();
     }
-}
\ No newline at end of file
+}
+/// Loads the stylesheet at `path` and, before it, everything it `@import`s,
+/// resolved relative to the importing file. `media` are the conditions of
+/// the imports that led here, `loading` the files being loaded, used to
+/// break import cycles.
+fn load_stylesheet(path: &Path, media: &[MediaQueryList], loading: &mut Vec<PathBuf>, stylesheets: &mut Vec<Stylesheet>) {
+    let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if loading.contains(&canonical) {
+        eprintln!("Import cycle: {}", path.display());
+        return;
+    }
+    let css = match std::fs::read_to_string(path) {
+        Ok(css) => css,
+        Err(e) => {
+            eprintln!("file: {}, error: {}", path.display(), e);
+            return;
+        }
+    };
+    let stylesheet = CssParser::new(&css).parse_stylesheet();
+    loading.push(canonical);
+    add_stylesheet(stylesheet, path.parent().unwrap_or_else(|| Path::new("")), media, loading, stylesheets);
+    loading.pop();
+}
+
+/// Adds a parsed stylesheet after loading its imports, which are resolved
+/// against `base`.
+fn add_stylesheet(mut stylesheet: Stylesheet, base: &Path, media: &[MediaQueryList], loading: &mut Vec<PathBuf>,
+                  stylesheets: &mut Vec<Stylesheet>) {
+    for import in &stylesheet.imports {
+        let mut import_media = media.to_vec();
+        if !import.media.queries.is_empty() {
+            import_media.push(import.media.clone());
+        }
+        load_stylesheet(&base.join(&import.url), &import_media, loading, stylesheets);
+    }
+
+    for rule in &mut stylesheet.rules {
+        rule.media.splice(0..0, media.iter().cloned());
+    }
+    stylesheets.push(stylesheet);
+}
+
+#[test]
+fn test_load_stylesheet_imports() {
+    let dir = env::temp_dir().join(format!("browser-imports-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("theme")).unwrap();
+    std::fs::write(dir.join("main.css"), "@import 'theme/colors.css' print; @import url(missing.css); body { color: red; }").unwrap();
+    // imports resolve relative to the importing file, and the cycle back to main.css is dropped
+    std::fs::write(dir.join("theme/colors.css"), "@import '../main.css'; @import 'fonts.css'; div { color: blue; }").unwrap();
+    std::fs::write(dir.join("theme/fonts.css"), "div { font-size: 20px; }").unwrap();
+
+    let mut stylesheets = Vec::new();
+    load_stylesheet(&dir.join("main.css"), &[], &mut Vec::new(), &mut stylesheets);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let tags: Vec<Option<String>> = stylesheets.iter().map(|sheet| sheet.rules[0].selector.tag_name.clone()).collect();
+    assert_eq!(tags, vec![Some("div".to_string()), Some("div".to_string()), Some("body".to_string())]);
+    // the media of the import applies to everything below it
+    assert_eq!(stylesheets[0].rules[0].media.len(), 1);
+    assert_eq!(stylesheets[1].rules[0].media.len(), 1);
+    assert!(stylesheets[2].rules[0].media.is_empty());
+}
+
+#[test]
+fn test_collect_stylesheets_in_document_order() {
+    let html = r#"<html><head><style>p { color: red; }</style></head><body><div></div><style>div { color: blue; }</style></body></html>"#;
+    let nodes = HtmlParser::new(html).parse_nodes();
+    let mut browser = Browser::new(html.to_string());
+    browser.collect_stylesheets(&nodes[0]);
+    let tags: Vec<Option<String>> = browser.stylesheets.borrow().iter().map(|sheet| sheet.rules[0].selector.tag_name.clone()).collect();
+    assert_eq!(tags, vec![Some("p".to_string()), Some("div".to_string())]);
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::default::Default;
 use std::fmt::{Debug, Formatter};
@@ -8,23 +8,223 @@ use std::iter::Map;
 #[derive(Debug, Default)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>,
+    pub origin: Origin,
+    /// `@import`s at the top of the sheet, loaded by whoever fetched it.
+    pub imports: Vec<Import>,
+    /// Id of the next rule added to the sheet, see `Rule::id`.
+    pub next_rule_id: usize,
 }
 
-#[derive(Debug, Default, PartialEq, Eq)]
+impl Stylesheet {
+    pub fn with_origin(mut self, origin: Origin) -> Stylesheet {
+        self.origin = origin;
+        self
+    }
+}
+
+/// `@import url(...) media;`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    pub url: String,
+    pub media: MediaQueryList,
+}
+
+/// Where a stylesheet comes from. Together with `!important` it decides
+/// which declarations win before specificity is even looked at.
+#[derive(PartialEq, Eq, Debug, Default, Clone, Copy, Hash)]
+pub enum Origin {
+    UserAgent,
+    User,
+    #[default]
+    Author,
+}
+
+impl Origin {
+    /// Cascade precedence of a declaration from this origin, higher wins.
+    /// Important declarations reverse the origin order.
+    pub fn precedence(&self, important: bool) -> u8 {
+        match (self, important) {
+            (Origin::UserAgent, false) => 0,
+            (Origin::User, false) => 1,
+            (Origin::Author, false) => 2,
+            (Origin::Author, true) => 3,
+            (Origin::User, true) => 4,
+            (Origin::UserAgent, true) => 5,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Rule {
+    // identifies the rule in its sheet, inserting and deleting other rules doesn't change it
+    pub id: usize,
     pub selector: Selector,
     pub properties: HashMap<PropertyName, PropertyValue>,
+    // properties declared with `!important`
+    pub important: HashSet<PropertyName>,
+    // conditions of the enclosing `@media` blocks, innermost last
+    pub media: Vec<MediaQueryList>,
 }
 
 impl Rule {
     pub fn new(name: Selector, properties: HashMap<PropertyName, PropertyValue>) -> Rule {
         Rule {
+            id: 0,
             selector: name,
             properties,
+            important: HashSet::new(),
+            media: Vec::new(),
+        }
+    }
+
+    pub fn is_important(&self, name: &PropertyName) -> bool {
+        self.important.contains(name)
+    }
+
+    /// Whether every enclosing `@media` block applies to `viewport`.
+    pub fn applies_to(&self, viewport: &Viewport) -> bool {
+        self.media.iter().all(|media| media.matches(viewport))
+    }
+}
+
+/// Comma separated media queries, matches when any of them does.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MediaQueryList {
+    pub queries: Vec<MediaQuery>,
+}
+
+impl MediaQueryList {
+    pub fn matches(&self, viewport: &Viewport) -> bool {
+        self.queries.is_empty() || self.queries.iter().any(|query| query.matches(viewport))
+    }
+}
+
+/// `[not | only]? media-type [and (feature)]*` or `(feature) [and (feature)]*`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaQuery {
+    pub negated: bool,
+    pub media_type: MediaType,
+    pub features: Vec<MediaFeature>,
+}
+
+impl MediaQuery {
+    /// Invalid queries are treated as `not all`.
+    pub fn never() -> MediaQuery {
+        MediaQuery {
+            negated: true,
+            media_type: MediaType::All,
+            features: Vec::new(),
+        }
+    }
+
+    pub fn matches(&self, viewport: &Viewport) -> bool {
+        let matches = self.media_type.matches() && self.features.iter().all(|feature| feature.matches(viewport));
+        matches != self.negated
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaType {
+    All,
+    Screen,
+    Print,
+    Other(String),
+}
+
+impl MediaType {
+    /// We only ever render to a screen.
+    fn matches(&self) -> bool {
+        matches!(self, MediaType::All | MediaType::Screen)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaFeature {
+    // `feature <comparison> value`, e.g. `(min-width: 600px)` is `Width >= 600`
+    Range(RangeFeature, Comparison, f32),
+    Orientation(Orientation),
+    PrefersColorScheme(ColorScheme),
+    // features we don't support never match
+    Unknown(String),
+}
+
+impl MediaFeature {
+    fn matches(&self, viewport: &Viewport) -> bool {
+        match self {
+            MediaFeature::Range(feature, comparison, value) => {
+                let actual = match feature {
+                    RangeFeature::Width => viewport.width,
+                    RangeFeature::Height => viewport.height,
+                    RangeFeature::AspectRatio => viewport.width / viewport.height,
+                    RangeFeature::Resolution => viewport.resolution,
+                };
+                comparison.compare(actual, *value)
+            }
+            MediaFeature::Orientation(orientation) => {
+                // a square viewport is portrait
+                let landscape = viewport.width > viewport.height;
+                (*orientation == Orientation::Landscape) == landscape
+            }
+            MediaFeature::PrefersColorScheme(scheme) => *scheme == viewport.color_scheme,
+            MediaFeature::Unknown(_) => false,
         }
     }
 }
 
+/// Width and height are in px, resolution in dppx.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeFeature {
+    Width,
+    Height,
+    AspectRatio,
+    Resolution,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    fn compare(&self, left: f32, right: f32) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Equal => left == right,
+            Comparison::GreaterOrEqual => left >= right,
+            Comparison::Greater => left > right,
+        }
+    }
+
+    /// The same comparison with its operands swapped: `a < b` is `b > a`.
+    pub fn flip(&self) -> Comparison {
+        match self {
+            Comparison::Less => Comparison::Greater,
+            Comparison::LessOrEqual => Comparison::GreaterOrEqual,
+            Comparison::Equal => Comparison::Equal,
+            Comparison::GreaterOrEqual => Comparison::LessOrEqual,
+            Comparison::Greater => Comparison::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorScheme {
+    #[default]
+    Light,
+    Dark,
+}
+
 
 
 #[derive(PartialEq, Debug, Default, Clone, Eq, Hash)]
@@ -34,39 +234,368 @@ pub enum PropertyName {
     BackgroundColor,
     Width,
     Height,
-    Margin,
     MarginTop,
     MarginBottom,
     MarginLeft,
     MarginRight,
-    Padding,
     PaddingTop,
     PaddingBottom,
     PaddingLeft,
     PaddingRight,
+    BorderTopWidth,
+    BorderRightWidth,
+    BorderBottomWidth,
+    BorderLeftWidth,
+    BorderTopStyle,
+    BorderRightStyle,
+    BorderBottomStyle,
+    BorderLeftStyle,
+    BorderTopColor,
+    BorderRightColor,
+    BorderBottomColor,
+    BorderLeftColor,
+    FontStyle,
+    FontWeight,
+    FontSize,
+    LineHeight,
+    FontFamily,
     Display,
-    Other,
+    ListStyleType,
+    TextAlign,
+    Visibility,
+    WhiteSpace,
+    FlexDirection,
+    FlexWrap,
+    FlexGrow,
+    FlexShrink,
+    FlexBasis,
+    Order,
+    JustifyContent,
+    AlignItems,
+    AlignSelf,
+    AlignContent,
+    RowGap,
+    ColumnGap,
+    GridTemplateColumns,
+    GridTemplateRows,
+    GridTemplateAreas,
+    GridAutoColumns,
+    GridAutoRows,
+    GridAutoFlow,
+    GridRowStart,
+    GridRowEnd,
+    GridColumnStart,
+    GridColumnEnd,
+    JustifyItems,
+    JustifySelf,
+    /// `--name`, a custom property
+    Custom(String),
+    /// a property we don't support, kept under its declared name
+    Unknown(String),
 }
 
 impl PropertyName {
-    pub fn to_str(&self) -> &'static str {
+    pub fn to_str(&self) -> &str {
         match self {
             PropertyName::Color => "color",
             PropertyName::BackgroundColor => "backgroundColor",
             PropertyName::Width => "width",
             PropertyName::Height => "height",
-            PropertyName::Margin => "margin",
             PropertyName::MarginTop => "marginTop",
             PropertyName::MarginBottom => "marginBottom",
             PropertyName::MarginLeft => "marginLeft",
             PropertyName::MarginRight => "marginRight",
-            PropertyName::Padding => "padding",
             PropertyName::PaddingTop => "paddingTop",
             PropertyName::PaddingBottom => "paddingBottom",
             PropertyName::PaddingLeft => "paddingLeft",
             PropertyName::PaddingRight => "paddingRight",
+            PropertyName::BorderTopWidth => "borderTopWidth",
+            PropertyName::BorderRightWidth => "borderRightWidth",
+            PropertyName::BorderBottomWidth => "borderBottomWidth",
+            PropertyName::BorderLeftWidth => "borderLeftWidth",
+            PropertyName::BorderTopStyle => "borderTopStyle",
+            PropertyName::BorderRightStyle => "borderRightStyle",
+            PropertyName::BorderBottomStyle => "borderBottomStyle",
+            PropertyName::BorderLeftStyle => "borderLeftStyle",
+            PropertyName::BorderTopColor => "borderTopColor",
+            PropertyName::BorderRightColor => "borderRightColor",
+            PropertyName::BorderBottomColor => "borderBottomColor",
+            PropertyName::BorderLeftColor => "borderLeftColor",
+            PropertyName::FontStyle => "fontStyle",
+            PropertyName::FontWeight => "fontWeight",
+            PropertyName::FontSize => "fontSize",
+            PropertyName::LineHeight => "lineHeight",
+            PropertyName::FontFamily => "fontFamily",
+            PropertyName::ListStyleType => "listStyleType",
+            PropertyName::Display => "display",
+            PropertyName::TextAlign => "textAlign",
+            PropertyName::Visibility => "visibility",
+            PropertyName::WhiteSpace => "whiteSpace",
+            PropertyName::FlexDirection => "flexDirection",
+            PropertyName::FlexWrap => "flexWrap",
+            PropertyName::FlexGrow => "flexGrow",
+            PropertyName::FlexShrink => "flexShrink",
+            PropertyName::FlexBasis => "flexBasis",
+            PropertyName::Order => "order",
+            PropertyName::JustifyContent => "justifyContent",
+            PropertyName::AlignItems => "alignItems",
+            PropertyName::AlignSelf => "alignSelf",
+            PropertyName::AlignContent => "alignContent",
+            PropertyName::RowGap => "rowGap",
+            PropertyName::ColumnGap => "columnGap",
+            PropertyName::GridTemplateColumns => "gridTemplateColumns",
+            PropertyName::GridTemplateRows => "gridTemplateRows",
+            PropertyName::GridTemplateAreas => "gridTemplateAreas",
+            PropertyName::GridAutoColumns => "gridAutoColumns",
+            PropertyName::GridAutoRows => "gridAutoRows",
+            PropertyName::GridAutoFlow => "gridAutoFlow",
+            PropertyName::GridRowStart => "gridRowStart",
+            PropertyName::GridRowEnd => "gridRowEnd",
+            PropertyName::GridColumnStart => "gridColumnStart",
+            PropertyName::GridColumnEnd => "gridColumnEnd",
+            PropertyName::JustifyItems => "justifyItems",
+            PropertyName::JustifySelf => "justifySelf",
+            PropertyName::Custom(name) | PropertyName::Unknown(name) => name,
+        }
+    }
+
+    /// Every property with an entry in the registry.
+    pub const ALL: [PropertyName; 58] = [
+        PropertyName::Color,
+        PropertyName::BackgroundColor,
+        PropertyName::Width,
+        PropertyName::Height,
+        PropertyName::MarginTop,
+        PropertyName::MarginBottom,
+        PropertyName::MarginLeft,
+        PropertyName::MarginRight,
+        PropertyName::PaddingTop,
+        PropertyName::PaddingBottom,
+        PropertyName::PaddingLeft,
+        PropertyName::PaddingRight,
+        PropertyName::BorderTopWidth,
+        PropertyName::BorderRightWidth,
+        PropertyName::BorderBottomWidth,
+        PropertyName::BorderLeftWidth,
+        PropertyName::BorderTopStyle,
+        PropertyName::BorderRightStyle,
+        PropertyName::BorderBottomStyle,
+        PropertyName::BorderLeftStyle,
+        PropertyName::BorderTopColor,
+        PropertyName::BorderRightColor,
+        PropertyName::BorderBottomColor,
+        PropertyName::BorderLeftColor,
+        PropertyName::FontStyle,
+        PropertyName::FontWeight,
+        PropertyName::FontSize,
+        PropertyName::LineHeight,
+        PropertyName::FontFamily,
+        PropertyName::Display,
+        PropertyName::ListStyleType,
+        PropertyName::TextAlign,
+        PropertyName::Visibility,
+        PropertyName::WhiteSpace,
+        PropertyName::FlexDirection,
+        PropertyName::FlexWrap,
+        PropertyName::FlexGrow,
+        PropertyName::FlexShrink,
+        PropertyName::FlexBasis,
+        PropertyName::Order,
+        PropertyName::JustifyContent,
+        PropertyName::AlignItems,
+        PropertyName::AlignSelf,
+        PropertyName::AlignContent,
+        PropertyName::RowGap,
+        PropertyName::ColumnGap,
+        PropertyName::GridTemplateColumns,
+        PropertyName::GridTemplateRows,
+        PropertyName::GridTemplateAreas,
+        PropertyName::GridAutoColumns,
+        PropertyName::GridAutoRows,
+        PropertyName::GridAutoFlow,
+        PropertyName::GridRowStart,
+        PropertyName::GridRowEnd,
+        PropertyName::GridColumnStart,
+        PropertyName::GridColumnEnd,
+        PropertyName::JustifyItems,
+        PropertyName::JustifySelf,
+    ];
+
+    /// The name used in stylesheets, e.g. `background-color`.
+    pub fn css_name(&self) -> &str {
+        match self {
+            PropertyName::Color => "color",
+            PropertyName::BackgroundColor => "background-color",
+            PropertyName::Width => "width",
+            PropertyName::Height => "height",
+            PropertyName::MarginTop => "margin-top",
+            PropertyName::MarginBottom => "margin-bottom",
+            PropertyName::MarginLeft => "margin-left",
+            PropertyName::MarginRight => "margin-right",
+            PropertyName::PaddingTop => "padding-top",
+            PropertyName::PaddingBottom => "padding-bottom",
+            PropertyName::PaddingLeft => "padding-left",
+            PropertyName::PaddingRight => "padding-right",
+            PropertyName::BorderTopWidth => "border-top-width",
+            PropertyName::BorderRightWidth => "border-right-width",
+            PropertyName::BorderBottomWidth => "border-bottom-width",
+            PropertyName::BorderLeftWidth => "border-left-width",
+            PropertyName::BorderTopStyle => "border-top-style",
+            PropertyName::BorderRightStyle => "border-right-style",
+            PropertyName::BorderBottomStyle => "border-bottom-style",
+            PropertyName::BorderLeftStyle => "border-left-style",
+            PropertyName::BorderTopColor => "border-top-color",
+            PropertyName::BorderRightColor => "border-right-color",
+            PropertyName::BorderBottomColor => "border-bottom-color",
+            PropertyName::BorderLeftColor => "border-left-color",
+            PropertyName::FontStyle => "font-style",
+            PropertyName::FontWeight => "font-weight",
+            PropertyName::FontSize => "font-size",
+            PropertyName::LineHeight => "line-height",
+            PropertyName::FontFamily => "font-family",
             PropertyName::Display => "display",
-            PropertyName::Other => "other",
+            PropertyName::ListStyleType => "list-style-type",
+            PropertyName::TextAlign => "text-align",
+            PropertyName::Visibility => "visibility",
+            PropertyName::WhiteSpace => "white-space",
+            PropertyName::FlexDirection => "flex-direction",
+            PropertyName::FlexWrap => "flex-wrap",
+            PropertyName::FlexGrow => "flex-grow",
+            PropertyName::FlexShrink => "flex-shrink",
+            PropertyName::FlexBasis => "flex-basis",
+            PropertyName::Order => "order",
+            PropertyName::JustifyContent => "justify-content",
+            PropertyName::AlignItems => "align-items",
+            PropertyName::AlignSelf => "align-self",
+            PropertyName::AlignContent => "align-content",
+            PropertyName::RowGap => "row-gap",
+            PropertyName::ColumnGap => "column-gap",
+            PropertyName::GridTemplateColumns => "grid-template-columns",
+            PropertyName::GridTemplateRows => "grid-template-rows",
+            PropertyName::GridTemplateAreas => "grid-template-areas",
+            PropertyName::GridAutoColumns => "grid-auto-columns",
+            PropertyName::GridAutoRows => "grid-auto-rows",
+            PropertyName::GridAutoFlow => "grid-auto-flow",
+            PropertyName::GridRowStart => "grid-row-start",
+            PropertyName::GridRowEnd => "grid-row-end",
+            PropertyName::GridColumnStart => "grid-column-start",
+            PropertyName::GridColumnEnd => "grid-column-end",
+            PropertyName::JustifyItems => "justify-items",
+            PropertyName::JustifySelf => "justify-self",
+            PropertyName::Custom(name) | PropertyName::Unknown(name) => name,
+        }
+    }
+
+    pub fn from_css_name(name: &str) -> Option<PropertyName> {
+        PropertyName::ALL.iter().find(|property| property.css_name() == name).cloned()
+    }
+
+    /// Registry entry: inheritance, initial value and how the computed value is derived.
+    pub fn info(&self) -> PropertyInfo {
+        let keyword = |keyword: &str| PropertyValue::Keyword(keyword.to_string());
+        let (inherited, initial, computed) = match self {
+            PropertyName::Color => (true, PropertyValue::Color(Color::Named("black".to_string())), ComputedAs::Color),
+            PropertyName::BackgroundColor => (false, PropertyValue::Color(Color::Named("transparent".to_string())), ComputedAs::Specified),
+            PropertyName::Width | PropertyName::Height => (false, keyword("auto"), ComputedAs::Length),
+            PropertyName::MarginTop | PropertyName::MarginBottom | PropertyName::MarginLeft | PropertyName::MarginRight
+            | PropertyName::PaddingTop | PropertyName::PaddingBottom | PropertyName::PaddingLeft | PropertyName::PaddingRight =>
+                (false, PropertyValue::Length(Length::Px(0.0)), ComputedAs::Length),
+            PropertyName::BorderTopWidth => (false, PropertyValue::Length(Length::Px(3.0)), ComputedAs::BorderWidth(PropertyName::BorderTopStyle)),
+            PropertyName::BorderRightWidth => (false, PropertyValue::Length(Length::Px(3.0)), ComputedAs::BorderWidth(PropertyName::BorderRightStyle)),
+            PropertyName::BorderBottomWidth => (false, PropertyValue::Length(Length::Px(3.0)), ComputedAs::BorderWidth(PropertyName::BorderBottomStyle)),
+            PropertyName::BorderLeftWidth => (false, PropertyValue::Length(Length::Px(3.0)), ComputedAs::BorderWidth(PropertyName::BorderLeftStyle)),
+            PropertyName::BorderTopStyle | PropertyName::BorderRightStyle | PropertyName::BorderBottomStyle
+            | PropertyName::BorderLeftStyle => (false, PropertyValue::BorderStyle(BorderStyle::None), ComputedAs::Specified),
+            PropertyName::BorderTopColor | PropertyName::BorderRightColor | PropertyName::BorderBottomColor
+            | PropertyName::BorderLeftColor => (false, PropertyValue::Color(Color::Current), ComputedAs::Specified),
+            PropertyName::FontStyle => (true, keyword("normal"), ComputedAs::Specified),
+            PropertyName::FontWeight => (true, PropertyValue::Number(400.0), ComputedAs::FontWeight),
+            PropertyName::FontSize => (true, PropertyValue::Length(Length::Px(DEFAULT_FONT_SIZE)), ComputedAs::FontSize),
+            PropertyName::LineHeight => (true, keyword("normal"), ComputedAs::LineHeight),
+            PropertyName::FontFamily => (true, PropertyValue::FontFamily(vec!["serif".to_string()]), ComputedAs::Specified),
+            PropertyName::Display => (false, PropertyValue::Display(DisplayType::Inline), ComputedAs::Specified),
+            PropertyName::ListStyleType => (true, keyword("disc"), ComputedAs::Specified),
+            PropertyName::TextAlign => (true, keyword("start"), ComputedAs::Specified),
+            PropertyName::Visibility => (true, keyword("visible"), ComputedAs::Specified),
+            PropertyName::WhiteSpace => (true, keyword("normal"), ComputedAs::Specified),
+            PropertyName::FlexDirection => (false, keyword("row"), ComputedAs::Specified),
+            PropertyName::FlexWrap => (false, keyword("nowrap"), ComputedAs::Specified),
+            PropertyName::FlexGrow | PropertyName::Order => (false, PropertyValue::Number(0.0), ComputedAs::Specified),
+            PropertyName::FlexShrink => (false, PropertyValue::Number(1.0), ComputedAs::Specified),
+            PropertyName::FlexBasis => (false, keyword("auto"), ComputedAs::Length),
+            PropertyName::JustifyContent | PropertyName::AlignItems | PropertyName::AlignContent =>
+                (false, keyword("normal"), ComputedAs::Specified),
+            PropertyName::AlignSelf => (false, keyword("auto"), ComputedAs::Specified),
+            PropertyName::RowGap | PropertyName::ColumnGap => (false, keyword("normal"), ComputedAs::Length),
+            PropertyName::GridTemplateColumns | PropertyName::GridTemplateRows => (false, keyword("none"), ComputedAs::Length),
+            PropertyName::GridTemplateAreas => (false, keyword("none"), ComputedAs::Specified),
+            PropertyName::GridAutoColumns | PropertyName::GridAutoRows => (false, keyword("auto"), ComputedAs::Length),
+            PropertyName::GridAutoFlow => (false, keyword("row"), ComputedAs::Specified),
+            PropertyName::GridRowStart | PropertyName::GridRowEnd | PropertyName::GridColumnStart
+            | PropertyName::GridColumnEnd => (false, PropertyValue::GridLine(GridLine::Auto), ComputedAs::Specified),
+            PropertyName::JustifyItems => (false, keyword("normal"), ComputedAs::Specified),
+            PropertyName::JustifySelf => (false, keyword("auto"), ComputedAs::Specified),
+            // the initial value of a custom property is the guaranteed-invalid value, stored as absence
+            PropertyName::Custom(_) => (true, PropertyValue::default(), ComputedAs::Specified),
+            PropertyName::Unknown(_) => (false, PropertyValue::default(), ComputedAs::Specified),
+        };
+        PropertyInfo { inherited, initial, computed }
+    }
+}
+
+pub struct PropertyInfo {
+    /// takes the parent's computed value when not set
+    pub inherited: bool,
+    pub initial: PropertyValue,
+    pub computed: ComputedAs,
+}
+
+/// How the computed value of a property is derived from its specified value.
+#[derive(PartialEq, Debug, Clone)]
+pub enum ComputedAs {
+    Specified,
+    /// absolute lengths, also in grid track sizes; percentages are left to layout
+    Length,
+    /// absolute length, relative units and percentages refer to the parent font size
+    FontSize,
+    /// `normal`, a number, or an absolute length with percentages of the font size
+    LineHeight,
+    /// a number, `bolder` and `lighter` are relative to the parent weight
+    FontWeight,
+    /// an absolute length, zero when the border style of the side is `none` or `hidden`
+    BorderWidth(PropertyName),
+    /// `currentColor` is the inherited color
+    Color,
+}
+
+/// Keywords every property accepts.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CssWideKeyword {
+    Inherit,
+    Initial,
+    /// `inherit` for inherited properties, `initial` for the rest
+    Unset,
+    /// rolls the cascade back to the previous origin
+    Revert,
+}
+
+impl CssWideKeyword {
+    pub fn from_str(value: &str) -> Option<CssWideKeyword> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "inherit" => Some(CssWideKeyword::Inherit),
+            "initial" => Some(CssWideKeyword::Initial),
+            "unset" => Some(CssWideKeyword::Unset),
+            "revert" => Some(CssWideKeyword::Revert),
+            _ => None,
+        }
+    }
+
+    pub fn to_str(self) -> &'static str {
+        match self {
+            CssWideKeyword::Inherit => "inherit",
+            CssWideKeyword::Initial => "initial",
+            CssWideKeyword::Unset => "unset",
+            CssWideKeyword::Revert => "revert",
         }
     }
 }
@@ -78,23 +607,215 @@ pub enum DisplayType {
     Block,
     Inline,
     InlineBlock,
+    ListItem,
+    Flex,
+    InlineFlex,
+    Grid,
+    InlineGrid,
+    None,
+}
+
+impl DisplayType {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            DisplayType::Block => "block",
+            DisplayType::Inline => "inline",
+            DisplayType::InlineBlock => "inline-block",
+            DisplayType::ListItem => "list-item",
+            DisplayType::Flex => "flex",
+            DisplayType::InlineFlex => "inline-flex",
+            DisplayType::Grid => "grid",
+            DisplayType::InlineGrid => "inline-grid",
+            DisplayType::None => "none",
+        }
+    }
+}
+
+/// One end of a grid track size.
+#[derive(PartialEq, Debug, Clone)]
+pub enum TrackBreadth {
+    Length(Length),
+    Fr(f32),
+    Auto,
+    MinContent,
+    MaxContent,
+}
+
+impl TrackBreadth {
+    fn to_str(&self) -> String {
+        match self {
+            TrackBreadth::Length(length) => length.to_str(),
+            TrackBreadth::Fr(fr) => format!("{}fr", fr),
+            TrackBreadth::Auto => String::from("auto"),
+            TrackBreadth::MinContent => String::from("min-content"),
+            TrackBreadth::MaxContent => String::from("max-content"),
+        }
+    }
+}
+
+/// The size of a grid track, `minmax(min, max)`. A lone `1fr` is
+/// `minmax(auto, 1fr)` and any other lone size is both.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Track {
+    pub min: TrackBreadth,
+    pub max: TrackBreadth,
+}
+
+impl Track {
+    pub fn to_str(&self) -> String {
+        match (&self.min, &self.max) {
+            (min, max) if min == max => min.to_str(),
+            (TrackBreadth::Auto, TrackBreadth::Fr(fr)) => TrackBreadth::Fr(*fr).to_str(),
+            (min, max) => format!("minmax({}, {})", min.to_str(), max.to_str()),
+        }
+    }
+}
+
+/// `grid-template-rows` and `grid-template-columns` with `repeat()` written
+/// out: the tracks, and the names of the lines between them, from the line
+/// before the first track to the one after the last.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct TrackList {
+    pub tracks: Vec<Track>,
+    pub lines: Vec<Vec<String>>,
+}
+
+impl TrackList {
+    pub fn to_str(&self) -> String {
+        if self.tracks.is_empty() {
+            return String::from("none");
+        }
+        let mut parts = Vec::new();
+        for (index, names) in self.lines.iter().enumerate() {
+            if !names.is_empty() {
+                parts.push(format!("[{}]", names.join(" ")));
+            }
+            if let Some(track) = self.tracks.get(index) {
+                parts.push(track.to_str());
+            }
+        }
+        parts.join(" ")
+    }
+}
+
+/// Where a grid item starts or ends: `grid-row-start` and the like.
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub enum GridLine {
+    Auto,
+    /// a line number, negative ones count from the end of the explicit grid
+    Line(i32),
+    Span(u32),
+    /// a named line, or the side of a named area
+    Name(String),
+}
+
+impl GridLine {
+    pub fn to_str(&self) -> String {
+        match self {
+            GridLine::Auto => String::from("auto"),
+            GridLine::Line(line) => line.to_string(),
+            GridLine::Span(span) => format!("span {}", span),
+            GridLine::Name(name) => name.clone(),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Default, Clone, Copy, Hash)]
+pub enum BorderStyle {
+    #[default]
     None,
+    Hidden,
+    Dotted,
+    Dashed,
+    Solid,
+    Double,
+    Groove,
+    Ridge,
+    Inset,
+    Outset,
+}
+
+impl BorderStyle {
+    pub fn from_str(value: &str) -> Option<BorderStyle> {
+        match value {
+            "none" => Some(BorderStyle::None),
+            "hidden" => Some(BorderStyle::Hidden),
+            "dotted" => Some(BorderStyle::Dotted),
+            "dashed" => Some(BorderStyle::Dashed),
+            "solid" => Some(BorderStyle::Solid),
+            "double" => Some(BorderStyle::Double),
+            "groove" => Some(BorderStyle::Groove),
+            "ridge" => Some(BorderStyle::Ridge),
+            "inset" => Some(BorderStyle::Inset),
+            "outset" => Some(BorderStyle::Outset),
+            _ => None,
+        }
+    }
+
+    pub fn to_str(self) -> &'static str {
+        match self {
+            BorderStyle::None => "none",
+            BorderStyle::Hidden => "hidden",
+            BorderStyle::Dotted => "dotted",
+            BorderStyle::Dashed => "dashed",
+            BorderStyle::Solid => "solid",
+            BorderStyle::Double => "double",
+            BorderStyle::Groove => "groove",
+            BorderStyle::Ridge => "ridge",
+            BorderStyle::Inset => "inset",
+            BorderStyle::Outset => "outset",
+        }
+    }
 }
 
-#[derive(PartialEq, Debug, Clone, Eq, Hash)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum PropertyValue {
     Color(Color),
     Length(Length),
     Display(DisplayType),
+    BorderStyle(BorderStyle),
+    // identifiers such as `auto`, `normal` or `italic`
+    Keyword(String),
+    Number(f32),
+    FontFamily(Vec<String>),
+    Tracks(TrackList),
+    /// `grid-template-areas`: the names in each row, `.` for no area
+    Areas(Vec<Vec<String>>),
+    GridLine(GridLine),
+    CssWide(CssWideKeyword),
+    /// A value with `var()` in it, parsed once the variables are known.
+    /// `property` is the declared property, which may be a shorthand.
+    Unresolved { property: String, value: String },
+    /// Raw text, e.g. the value of a custom property
     Other(String),
 }
 
 impl PropertyValue {
    pub fn to_str(&self) -> String {
         match self {
+            PropertyValue::Color(Color::Current) => String::from("currentcolor"),
             PropertyValue::Color(color) => color.get_rgb_str(),
             PropertyValue::Length(length) => length.to_str(),
-            PropertyValue::Display(_display) => String::from("Display"),
+            PropertyValue::Display(display) => display.to_str().to_string(),
+            PropertyValue::BorderStyle(style) => style.to_str().to_string(),
+            PropertyValue::Keyword(keyword) => keyword.clone(),
+            PropertyValue::Number(number) => number.to_string(),
+            PropertyValue::FontFamily(families) => families.iter()
+                .map(|family| if family.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+                    family.clone()
+                } else {
+                    format!("\"{}\"", family)
+                })
+                .collect::<Vec<String>>()
+                .join(", "),
+            PropertyValue::Tracks(list) => list.to_str(),
+            PropertyValue::Areas(rows) => rows.iter()
+                .map(|row| format!("\"{}\"", row.join(" ")))
+                .collect::<Vec<String>>()
+                .join(" "),
+            PropertyValue::GridLine(line) => line.to_str(),
+            PropertyValue::CssWide(keyword) => keyword.to_str().to_string(),
+            PropertyValue::Unresolved { value, .. } => value.clone(),
             PropertyValue::Other(other) => other.to_string()
         }
     }
@@ -110,37 +831,57 @@ impl Default for PropertyValue {
 #[derive(PartialEq, Eq, Clone, Hash)]
 pub enum Color {
     Rgb(u8, u8, u8),
+    Rgba(u8, u8, u8, u8),
+    // one of the named colors or `transparent`, always lowercase
     Named(String),
     Hex(u32),
+    // `currentColor`, resolved against the `color` property of the element
+    Current,
 }
 
 impl Color {
     pub fn get_rgb(&self) -> (u8, u8, u8) {
+        let (r, g, b, _a) = self.get_rgba();
+        (r, g, b)
+    }
+
+    /// `Current` has no value on its own and comes back as opaque black.
+    pub fn get_rgba(&self) -> (u8, u8, u8, u8) {
         match self {
-            Color::Rgb(r, g, b) => (*r, *g, *b),
+            Color::Rgb(r, g, b) => (*r, *g, *b, 255),
+            Color::Rgba(r, g, b, a) => (*r, *g, *b, *a),
+            Color::Named(name) if name == "transparent" => (0, 0, 0, 0),
             Color::Named(name) => {
-                match name.as_str() {
-                    "black" => (0, 0, 0),
-                    "white" => (255, 255, 255),
-                    "red" => (255, 0, 0),
-                    "orange" => (255, 165, 0),
-                    "green" => (0, 255, 0),
-                    "blue" => (0, 0, 255),
-                    _ => (0, 0, 0),
-                }
+                let (r, g, b) = Color::Hex(named_color(name).unwrap_or(0)).get_rgb();
+                (r, g, b, 255)
             }
             Color::Hex(hex) => {
                 let r = (hex >> 16) & 0xFF;
                 let g = (hex >> 8) & 0xFF;
                 let b = hex & 0xFF;
-                (r as u8, g as u8, b as u8)
+                (r as u8, g as u8, b as u8, 255)
             }
+            Color::Current => (0, 0, 0, 255),
+        }
+    }
+
+    /// Replaces `currentColor` with `current`.
+    pub fn resolve(&self, current: &Color) -> Color {
+        match self {
+            Color::Current => current.clone(),
+            color => color.clone(),
         }
     }
 
     pub fn get_rgb_str(&self) -> String {
-        let (r, g, b) = self.get_rgb();
-        format!("rdb({}, {}, {})", r, g, b)
+        let (r, g, b, a) = self.get_rgba();
+        if a == 255 {
+            format!("rgb({}, {}, {})", r, g, b)
+        } else {
+            // alpha is stored as a byte, print it with two decimals like browsers do
+            let alpha = (a as f32 / 255.0 * 100.0).round() / 100.0;
+            format!("rgba({}, {}, {}, {})", r, g, b, alpha)
+        }
     }
 }
 
@@ -155,53 +896,564 @@ impl Debug for Color {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Color::Rgb(r, g, b) => write!(f, "rgb({}, {}, {})", r, g, b),
+            Color::Rgba(r, g, b, a) => write!(f, "rgba({}, {}, {}, {})", r, g, b, a),
             Color::Named(name) => write!(f, "{}", name),
             Color::Hex(hex) => write!(f, "#{:06x}", hex),
+            Color::Current => write!(f, "currentcolor"),
         }
     }
 }
 
+pub fn named_color(name: &str) -> Option<u32> {
+    NAMED_COLORS.iter().find(|(named, _)| *named == name).map(|(_, hex)| *hex)
+}
+
+/// The CSS Color Level 4 named colors.
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
 
-#[derive(PartialEq, Debug, Default, Eq)]
+/// (ids, classes, tags), compared lexicographically.
+pub type Specificity = (usize, usize, usize);
+
+/// Compound selector such as `div#main.card.wide`. No fields set means `*`.
+#[derive(PartialEq, Debug, Default, Clone, Eq)]
 pub struct Selector {
     pub tag_name: Option<String>,
     pub id: Option<String>,
-    pub class: Option<String>,
+    pub classes: Vec<String>,
 }
 
 impl Selector {
-    pub fn new(tag_name: Option<String>, id: Option<String>, classes: Option<String>) -> Selector {
+    pub fn new(tag_name: Option<String>, id: Option<String>, classes: Vec<String>) -> Selector {
         Selector {
             tag_name,
             id,
-            class: classes,
+            classes,
         }
     }
+
+    pub fn specificity(&self) -> Specificity {
+        (
+            self.id.iter().count(),
+            self.classes.len(),
+            self.tag_name.iter().count(),
+        )
+    }
 }
 
 
-#[derive(PartialEq, Debug, Eq, Clone, Hash)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Length {
-    Px(i16),
-    Percent(u8),
+    Px(f32),
+    Percent(f32),
+    // relative to the font size of the element (of the parent for `font-size`)
+    Em(f32),
+    Rem(f32),
+    Ex(f32),
+    Ch(f32),
+    // relative to the viewport
+    Vw(f32),
+    Vh(f32),
+    Vmin(f32),
+    Vmax(f32),
+    // absolute
+    Pt(f32),
+    Pc(f32),
+    In(f32),
+    Cm(f32),
+    Mm(f32),
+    // calc(), min(), max() or clamp() that could not be folded into a single value
+    Calc(Box<Calc>),
+}
+
+/// Everything a relative length can depend on, except the percentage base
+/// which is only known during layout.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LengthContext {
+    pub font_size: f32,
+    pub root_font_size: f32,
+    pub viewport: Viewport,
 }
 
 impl Length {
+    pub fn from_unit(value: f32, unit: &str) -> Option<Length> {
+        let length = match unit {
+            "px" => Length::Px(value),
+            "%" => Length::Percent(value),
+            "em" => Length::Em(value),
+            "rem" => Length::Rem(value),
+            "ex" => Length::Ex(value),
+            "ch" => Length::Ch(value),
+            "vw" => Length::Vw(value),
+            "vh" => Length::Vh(value),
+            "vmin" => Length::Vmin(value),
+            "vmax" => Length::Vmax(value),
+            "pt" => Length::Pt(value),
+            "pc" => Length::Pc(value),
+            "in" => Length::In(value),
+            "cm" => Length::Cm(value),
+            "mm" => Length::Mm(value),
+            _ => return None,
+        };
+        Some(length)
+    }
+
+    /// Number and unit of a single value, `None` for calc expressions.
+    pub fn value_and_unit(&self) -> Option<(f32, &'static str)> {
+        let value_and_unit = match self {
+            Length::Px(value) => (*value, "px"),
+            Length::Percent(value) => (*value, "%"),
+            Length::Em(value) => (*value, "em"),
+            Length::Rem(value) => (*value, "rem"),
+            Length::Ex(value) => (*value, "ex"),
+            Length::Ch(value) => (*value, "ch"),
+            Length::Vw(value) => (*value, "vw"),
+            Length::Vh(value) => (*value, "vh"),
+            Length::Vmin(value) => (*value, "vmin"),
+            Length::Vmax(value) => (*value, "vmax"),
+            Length::Pt(value) => (*value, "pt"),
+            Length::Pc(value) => (*value, "pc"),
+            Length::In(value) => (*value, "in"),
+            Length::Cm(value) => (*value, "cm"),
+            Length::Mm(value) => (*value, "mm"),
+            Length::Calc(_) => return None,
+        };
+        Some(value_and_unit)
+    }
+
+    /// Resolves the length to px. Percentages need the containing block and
+    /// are left to layout, they come back as `None`, as do calc expressions
+    /// that contain percentages.
+    pub fn to_px(&self, context: &LengthContext) -> Option<f32> {
+        // without font metrics ex and ch are approximated as half an em
+        let px = match self {
+            Length::Px(px) => *px,
+            Length::Percent(_) => return None,
+            Length::Em(em) => em * context.font_size,
+            Length::Rem(rem) => rem * context.root_font_size,
+            Length::Ex(ex) => ex * context.font_size * 0.5,
+            Length::Ch(ch) => ch * context.font_size * 0.5,
+            Length::Vw(vw) => vw * context.viewport.width / 100.0,
+            Length::Vh(vh) => vh * context.viewport.height / 100.0,
+            Length::Vmin(vmin) => vmin * context.viewport.width.min(context.viewport.height) / 100.0,
+            Length::Vmax(vmax) => vmax * context.viewport.width.max(context.viewport.height) / 100.0,
+            Length::Pt(pt) => pt * 96.0 / 72.0,
+            Length::Pc(pc) => pc * 16.0,
+            Length::In(inches) => inches * 96.0,
+            Length::Cm(cm) => cm * 96.0 / 2.54,
+            Length::Mm(mm) => mm * 96.0 / 25.4,
+            Length::Calc(calc) => match calc.compute(context) {
+                Calc::Value(Length::Px(px)) => px,
+                _ => return None,
+            },
+        };
+        Some(px)
+    }
+
+    /// Computed value: px, a percentage, or a calc expression over the two.
+    pub fn compute(&self, context: &LengthContext) -> Length {
+        match self {
+            Length::Percent(_) => self.clone(),
+            Length::Calc(calc) => calc.compute(context).into_length(),
+            length => Length::Px(length.to_px(context).unwrap_or(0.0)),
+        }
+    }
+
+    /// Used value in px of a computed length, percentages are taken of `percent_base`.
+    pub fn resolve(&self, percent_base: f32) -> f32 {
+        match self {
+            Length::Px(px) => *px,
+            Length::Percent(percent) => percent_base * percent / 100.0,
+            Length::Calc(calc) => calc.resolve(percent_base),
+            // lengths are computed to px before layout
+            _ => 0.0,
+        }
+    }
+
+    pub(crate) fn to_str(&self) -> String {
+        match self {
+            Length::Calc(calc) => match calc.as_ref() {
+                Calc::Min(_) | Calc::Max(_) => calc.to_str(),
+                calc => format!("calc({})", calc.to_str()),
+            },
+            length => {
+                let (value, unit) = length.value_and_unit().unwrap();
+                format!("{}{}", value, unit)
+            }
+        }
+    }
+}
+
+/// Expression tree of a math function. `clamp(a, b, c)` is stored as
+/// `max(a, min(b, c))`.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Calc {
+    // a single value, never `Length::Calc`
+    Value(Length),
+    Sum(Vec<Calc>),
+    Product(Box<Calc>, f32),
+    Min(Vec<Calc>),
+    Max(Vec<Calc>),
+}
+
+impl Calc {
+    /// Folds everything that does not depend on the element: absolute units
+    /// become px, terms with the same unit are added up, numeric factors are
+    /// distributed and min()/max() over values of one unit are decided.
+    pub fn simplify(self) -> Calc {
+        match self {
+            Calc::Value(length) => match length.value_and_unit() {
+                Some((value, "pt" | "pc" | "in" | "cm" | "mm")) => {
+                    let px = length.to_px(&LengthContext::absolute()).unwrap_or(value);
+                    Calc::Value(Length::Px(px))
+                }
+                _ => Calc::Value(length),
+            },
+            Calc::Product(calc, factor) => calc.simplify().scale(factor),
+            Calc::Sum(terms) => {
+                let mut values: Vec<Length> = Vec::new();
+                let mut others = Vec::new();
+                for term in terms {
+                    match term.simplify() {
+                        Calc::Value(length) => add_term(&mut values, length),
+                        Calc::Sum(inner) => {
+                            for term in inner {
+                                match term {
+                                    Calc::Value(length) => add_term(&mut values, length),
+                                    other => others.push(other),
+                                }
+                            }
+                        }
+                        other => others.push(other),
+                    }
+                }
+                let mut terms: Vec<Calc> = values.into_iter().map(Calc::Value).chain(others).collect();
+                match terms.len() {
+                    0 => Calc::Value(Length::Px(0.0)),
+                    1 => terms.pop().unwrap(),
+                    _ => Calc::Sum(terms),
+                }
+            }
+            Calc::Min(arguments) => Calc::fold_comparison(arguments, true),
+            Calc::Max(arguments) => Calc::fold_comparison(arguments, false),
+        }
+    }
+
+    fn fold_comparison(arguments: Vec<Calc>, is_min: bool) -> Calc {
+        let arguments: Vec<Calc> = arguments.into_iter().map(Calc::simplify).collect();
+        let units: Vec<Option<(f32, &str)>> = arguments.iter().map(|argument| match argument {
+            Calc::Value(length) => length.value_and_unit(),
+            _ => None,
+        }).collect();
+        let same_unit = units.iter().all(|unit| unit.map(|u| u.1) == units[0].map(|u| u.1) && unit.is_some());
+        if same_unit {
+            let pick = if is_min { f32::min } else { f32::max };
+            let (first, unit) = units[0].unwrap();
+            let value = units.iter().skip(1).fold(first, |acc, unit| pick(acc, unit.unwrap().0));
+            return Calc::Value(Length::from_unit(value, unit).unwrap());
+        }
+        if arguments.len() == 1 {
+            return arguments.into_iter().next().unwrap();
+        }
+        if is_min { Calc::Min(arguments) } else { Calc::Max(arguments) }
+    }
+
+    fn scale(self, factor: f32) -> Calc {
+        let scale_all = |arguments: Vec<Calc>| arguments.into_iter().map(|argument| argument.scale(factor)).collect();
+        match self {
+            Calc::Value(length) => match length.value_and_unit() {
+                Some((value, unit)) => Calc::Value(Length::from_unit(value * factor, unit).unwrap()),
+                None => Calc::Product(Box::new(Calc::Value(length)), factor),
+            },
+            Calc::Sum(terms) => Calc::Sum(scale_all(terms)),
+            Calc::Product(calc, inner) => Calc::Product(calc, inner * factor),
+            // a negative factor turns the smallest value into the largest
+            Calc::Min(arguments) if factor < 0.0 => Calc::Max(scale_all(arguments)),
+            Calc::Max(arguments) if factor < 0.0 => Calc::Min(scale_all(arguments)),
+            Calc::Min(arguments) => Calc::Min(scale_all(arguments)),
+            Calc::Max(arguments) => Calc::Max(scale_all(arguments)),
+        }
+    }
+
+    /// Resolves every value except percentages to px and simplifies.
+    pub fn compute(&self, context: &LengthContext) -> Calc {
+        let compute_all = |arguments: &Vec<Calc>| arguments.iter().map(|argument| argument.compute(context)).collect();
+        let calc = match self {
+            Calc::Value(length) => Calc::Value(length.compute(context)),
+            Calc::Sum(terms) => Calc::Sum(compute_all(terms)),
+            Calc::Product(calc, factor) => Calc::Product(Box::new(calc.compute(context)), *factor),
+            Calc::Min(arguments) => Calc::Min(compute_all(arguments)),
+            Calc::Max(arguments) => Calc::Max(compute_all(arguments)),
+        };
+        calc.simplify()
+    }
+
+    /// Used value in px, percentages are taken of `percent_base`.
+    pub fn resolve(&self, percent_base: f32) -> f32 {
+        match self {
+            Calc::Value(length) => length.resolve(percent_base),
+            Calc::Sum(terms) => terms.iter().map(|term| term.resolve(percent_base)).sum(),
+            Calc::Product(calc, factor) => calc.resolve(percent_base) * factor,
+            Calc::Min(arguments) => arguments.iter().map(|a| a.resolve(percent_base)).fold(f32::INFINITY, f32::min),
+            Calc::Max(arguments) => arguments.iter().map(|a| a.resolve(percent_base)).fold(f32::NEG_INFINITY, f32::max),
+        }
+    }
+
+    /// A single value becomes a plain length.
+    pub fn into_length(self) -> Length {
+        match self {
+            Calc::Value(length) => length,
+            calc => Length::Calc(Box::new(calc)),
+        }
+    }
+
     fn to_str(&self) -> String {
+        let join = |arguments: &Vec<Calc>| arguments.iter().map(Calc::to_str).collect::<Vec<_>>().join(", ");
         match self {
-            Length::Px(px) => format!("{}px", px),
-            Length::Percent(pr) => format!("{}%", pr),
+            Calc::Value(length) => length.to_str(),
+            Calc::Sum(terms) => {
+                let mut out = terms[0].to_str();
+                for term in &terms[1..] {
+                    match term {
+                        Calc::Value(length) if length.value_and_unit().is_some_and(|(value, _)| value < 0.0) => {
+                            let (value, unit) = length.value_and_unit().unwrap();
+                            out.push_str(&format!(" - {}{}", -value, unit));
+                        }
+                        term => out.push_str(&format!(" + {}", term.to_str())),
+                    }
+                }
+                out
+            }
+            Calc::Product(calc, factor) => format!("({}) * {}", calc.to_str(), factor),
+            Calc::Min(arguments) => format!("min({})", join(arguments)),
+            Calc::Max(arguments) => format!("max({})", join(arguments)),
         }
+    }
+}
 
+/// Adds `length` to the term with the same unit, or appends it.
+fn add_term(terms: &mut Vec<Length>, length: Length) {
+    let (value, unit) = match length.value_and_unit() {
+        Some(value_and_unit) => value_and_unit,
+        None => return terms.push(length),
+    };
+    for term in terms.iter_mut() {
+        if let Some((existing, existing_unit)) = term.value_and_unit() {
+            if existing_unit == unit {
+                *term = Length::from_unit(existing + value, unit).unwrap();
+                return;
+            }
+        }
+    }
+    terms.push(length);
+}
+
+impl LengthContext {
+    /// Context for lengths that don't depend on fonts or the viewport.
+    fn absolute() -> LengthContext {
+        LengthContext {
+            font_size: DEFAULT_FONT_SIZE,
+            root_font_size: DEFAULT_FONT_SIZE,
+            viewport: Viewport::default(),
+        }
     }
 }
 
 impl Default for Length {
     fn default() -> Self {
-        Length::Px(0)
+        Length::Px(0.0)
     }
 }
 
+/// The area the document is rendered into. Relative lengths and media
+/// queries are evaluated against it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    // in px
+    pub width: f32,
+    pub height: f32,
+    // device pixels per px
+    pub resolution: f32,
+    pub color_scheme: ColorScheme,
+}
 
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport {
+            width: crate::render::WIDTH as f32,
+            height: crate::render::HEIGHT as f32,
+            resolution: 1.0,
+            color_scheme: ColorScheme::default(),
+        }
+    }
+}
 
+/// Initial value of `font-size` (`medium`).
+pub const DEFAULT_FONT_SIZE: f32 = 16.0;
 
+/// `font-size` keywords in px, `smaller` and `larger` are relative to the parent.
+pub fn font_size_keyword(keyword: &str, parent_font_size: f32) -> Option<f32> {
+    let px = match keyword {
+        "xx-small" => 9.0,
+        "x-small" => 10.0,
+        "small" => 13.0,
+        "medium" => DEFAULT_FONT_SIZE,
+        "large" => 18.0,
+        "x-large" => 24.0,
+        "xx-large" => 32.0,
+        "xxx-large" => 48.0,
+        "smaller" => parent_font_size / 1.2,
+        "larger" => parent_font_size * 1.2,
+        _ => return None,
+    };
+    Some(px)
+}
@@ -0,0 +1,253 @@
+//! Conformance cases for the CSS parser, after the css-parsing-tests suite:
+//! stylesheets and rule lists, declaration lists, component values and
+//! colors. Results are compared in their serialized form. The last tests
+//! check that no input makes the parser panic or overflow the stack.
+use crate::css::{Color, PropertyName, PropertyValue};
+use crate::css_parser::CssParser;
+
+fn stylesheet(css: &str) -> String {
+    CssParser::new(css).parse_stylesheet().to_css()
+}
+
+fn declarations(css: &str) -> String {
+    let rule = CssParser::new(css).parse_inline_style();
+    let declarations: Vec<String> = rule.declarations().iter()
+        .map(|(name, value)| {
+            let priority = if rule.is_important(name) { " !important" } else { "" };
+            format!("{}: {}{}", name.css_name(), value.to_str(), priority)
+        })
+        .collect();
+    declarations.join("; ")
+}
+
+/// The value `name: value` sets `longhand` to, `None` when it is invalid.
+fn value(name: &str, value: &str, longhand: PropertyName) -> Option<String> {
+    CssParser::parse_declaration(name, value).into_iter()
+        .find(|(name, _)| *name == longhand)
+        .map(|(_, value)| value.to_str())
+}
+
+fn color(value: &str) -> Option<(u8, u8, u8, u8)> {
+    match CssParser::parse_declaration("color", value).pop() {
+        Some((_, PropertyValue::Color(color))) => Some(color.get_rgba()),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_stylesheet() {
+    let cases = [
+        ("", ""),
+        ("  \n\t ", ""),
+        ("p{color:red}div{color:blue}", "p { color: rgb(255, 0, 0); }\ndiv { color: rgb(0, 0, 255); }\n"),
+        ("p\n{\n\tcolor:\tred;\n}", "p { color: rgb(255, 0, 0); }\n"),
+        ("p.a#b.c { color: red; }", "p#b.a.c { color: rgb(255, 0, 0); }\n"),
+        ("*.a { color: red; }", ".a { color: rgb(255, 0, 0); }\n"),
+        ("p { }", "p { }\n"),
+        // selector lists, one rule per selector
+        ("h1, h2 { color: red; }", "h1 { color: rgb(255, 0, 0); }\nh2 { color: rgb(255, 0, 0); }\n"),
+        // unsupported selectors drop the rule
+        ("a:hover { color: red; } p { color: blue; }", "p { color: rgb(0, 0, 255); }\n"),
+        ("div p { color: red; }", ""),
+        ("h1, h2:first-child { color: red; }", ""),
+        (":root { color: red; }", ""),
+        ("{ color: red; } p { color: blue; }", "p { color: rgb(0, 0, 255); }\n"),
+        // comments
+        ("/* p { color: blue; } */ p { color: red; }", "p { color: rgb(255, 0, 0); }\n"),
+        ("p { color: /* blue */ red; }", "p { color: rgb(255, 0, 0); }\n"),
+        ("p { color: red; } /* unclosed", "p { color: rgb(255, 0, 0); }\n"),
+        ("p { background-image: url(\"/*\"); color: red; }", "p { color: rgb(255, 0, 0); background-image: url(\"/*\"); }\n"),
+        // CDO and CDC are ignored at the top level
+        ("<!-- p { color: red; } -->", "p { color: rgb(255, 0, 0); }\n"),
+        // stray closing braces
+        ("} p { color: red; }", "p { color: rgb(255, 0, 0); }\n"),
+        ("p { color: red; } }", "p { color: rgb(255, 0, 0); }\n"),
+        // unclosed blocks end with the input
+        ("p { color: red", "p { color: rgb(255, 0, 0); }\n"),
+        ("p { color: red; margin-top:", "p { color: rgb(255, 0, 0); }\n"),
+        ("@media screen { p { color: red; }", "@media screen {\n    p { color: rgb(255, 0, 0); }\n}\n"),
+    ];
+    for (css, expected) in cases {
+        assert_eq!(stylesheet(css), expected, "{:?}", css);
+    }
+}
+
+#[test]
+fn test_at_rules() {
+    let cases = [
+        ("@charset \"utf-8\"; p { color: red; }", "p { color: rgb(255, 0, 0); }\n"),
+        ("@font-face { font-family: x; } p { color: red; }", "p { color: rgb(255, 0, 0); }\n"),
+        ("@unknown foo { a { b: c } } p { color: red; }", "p { color: rgb(255, 0, 0); }\n"),
+        ("@unknown foo; p { color: red; }", "p { color: rgb(255, 0, 0); }\n"),
+        ("@media screen { p { color: red; } } q { color: blue; }",
+         "@media screen {\n    p { color: rgb(255, 0, 0); }\n}\nq { color: rgb(0, 0, 255); }\n"),
+        ("@media (min-width: 100px) and (max-width: 200px) { p { color: red; } }",
+         "@media (min-width: 100px) and (max-width: 200px) {\n    p { color: rgb(255, 0, 0); }\n}\n"),
+        ("@media not print { p { color: red; } }", "@media not print {\n    p { color: rgb(255, 0, 0); }\n}\n"),
+        // @import is only valid before other rules
+        ("@import 'a.css'; @import url(b.css) screen; p { } @import 'c.css';",
+         "@import url(\"a.css\");\n@import url(\"b.css\") screen;\np { }\n"),
+        ("@media print { @import 'a.css'; p { } }", "@media print {\n    p { }\n}\n"),
+    ];
+    for (css, expected) in cases {
+        assert_eq!(stylesheet(css), expected, "{:?}", css);
+    }
+}
+
+#[test]
+fn test_declaration_list() {
+    let cases = [
+        ("", ""),
+        ("color: red", "color: rgb(255, 0, 0)"),
+        (";; color: red;;", "color: rgb(255, 0, 0)"),
+        ("COLOR: RED", "color: rgb(255, 0, 0)"),
+        // a declaration without a colon is dropped up to the next `;`
+        ("color red; margin-top: 2px", "margin-top: 2px"),
+        ("margin-top; color: red", "color: rgb(255, 0, 0)"),
+        // invalid values are dropped, the rest of the list is kept
+        ("color: notacolor; margin-top: 2px", "margin-top: 2px"),
+        ("margin-top: 1px 2px; margin-left: 3px", "margin-left: 3px"),
+        // later declarations win, except over !important ones
+        ("color: red; color: blue", "color: rgb(0, 0, 255)"),
+        ("color: red !important; color: blue", "color: rgb(255, 0, 0) !important"),
+        ("color: red ! IMPORTANT", "color: rgb(255, 0, 0) !important"),
+        ("color: red !importantx", ""),
+        // `;` and `}` inside strings and brackets don't end the declaration
+        ("font-family: \"a;}\", serif; color: blue", "color: rgb(0, 0, 255); font-family: \"a;}\", serif"),
+        ("background-image: url(\"a;b\"); color: red", "color: rgb(255, 0, 0); background-image: url(\"a;b\")"),
+        ("--x: { a: b; }; color: red", "color: rgb(255, 0, 0); --x: { a: b; }"),
+        ("--x: [a;b]", "--x: [a;b]"),
+        ("--Mixed-Case: 1", "--Mixed-Case: 1"),
+        ("text-shadow: 1px 1px red", "text-shadow: 1px 1px red"),
+        ("display: Inline-Block", "display: inline-block"),
+        ("display: flux; display: list-item", "display: list-item"),
+    ];
+    for (css, expected) in cases {
+        assert_eq!(declarations(css), expected, "{:?}", css);
+    }
+}
+
+#[test]
+fn test_component_values() {
+    let cases = [
+        ("0", Some("0px")),
+        ("-1px", Some("-1px")),
+        ("+2px", Some("2px")),
+        (".5em", Some("0.5em")),
+        ("1e1px", Some("10px")),
+        ("10%", Some("10%")),
+        ("1PX", Some("1px")),
+        ("auto", Some("auto")),
+        ("1", None),
+        ("px", None),
+        ("1 px", None),
+        ("calc(1px + (2px * 3))", Some("7px")),
+        ("calc( 1px + 2px )", Some("3px")),
+        ("calc(1px +2px)", None),
+        ("calc(1px * 2px)", None),
+        ("calc(1px / 0)", None),
+        ("calc(1px", None),
+        ("min(1px, 2px)", Some("1px")),
+        ("clamp(1px, 2px)", None),
+    ];
+    for (css, expected) in cases {
+        let expected = expected.map(String::from);
+        assert_eq!(value("margin-top", css, PropertyName::MarginTop), expected, "{:?}", css);
+    }
+}
+
+#[test]
+fn test_colors() {
+    let cases = [
+        ("red", Some((255, 0, 0, 255))),
+        ("RED", Some((255, 0, 0, 255))),
+        ("transparent", Some((0, 0, 0, 0))),
+        ("#f00", Some((255, 0, 0, 255))),
+        ("#f008", Some((255, 0, 0, 136))),
+        ("#FF0000", Some((255, 0, 0, 255))),
+        ("#ff000080", Some((255, 0, 0, 128))),
+        ("#ff00", Some((255, 255, 0, 0))),
+        ("#ff", None),
+        ("#ggg", None),
+        ("rgb(1, 2, 3)", Some((1, 2, 3, 255))),
+        ("rgb(1 2 3 / 50%)", Some((1, 2, 3, 128))),
+        ("rgba(1, 2, 3, 0.5)", Some((1, 2, 3, 128))),
+        ("rgb(300, -1, 50%)", Some((255, 0, 128, 255))),
+        ("rgb(1, 2)", None),
+        ("rgb(1, 2, 3", None),
+        ("hsl(120, 100%, 25%)", Some((0, 128, 0, 255))),
+        ("hsl(120deg 100% 25%)", Some((0, 128, 0, 255))),
+        ("notacolor", None),
+    ];
+    for (css, expected) in cases {
+        assert_eq!(color(css), expected, "{:?}", css);
+    }
+    assert_eq!(CssParser::parse_declaration("color", "currentColor").pop().map(|(_, value)| value),
+               Some(PropertyValue::Color(Color::Current)));
+}
+
+#[test]
+fn test_deep_nesting_does_not_overflow() {
+    let depth = 100_000;
+    let inputs = [
+        format!("p {{ width: calc({}1px{}); }}", "(".repeat(depth), ")".repeat(depth)),
+        format!("p {{ width: {}1px{}; }}", "min(".repeat(depth), ")".repeat(depth)),
+        format!("{}p {{ color: red; }}", "@media all {".repeat(depth)),
+        format!("p {{ color: {}; }}", "rgb(".repeat(depth)),
+        format!("p {{ --x: {}; }}", "{[(".repeat(depth)),
+        format!("p {{ grid-template-columns: {}10px{}; }}", "repeat(1, ".repeat(depth), ")".repeat(depth)),
+        "p { grid-template-columns: repeat(1000, repeat(1000, repeat(1000, repeat(1000, [a])))); }".to_string(),
+        format!("p {{ grid-template-rows: repeat(1000, [{}]) 1px; }}", "a ".repeat(1000)),
+        "{".repeat(depth),
+        "/*".repeat(depth),
+        "\"".repeat(depth),
+    ];
+    for css in &inputs {
+        let stylesheet = CssParser::new(css).parse_stylesheet();
+        CssParser::new(&stylesheet.to_css()).parse_stylesheet();
+        CssParser::new(css).parse_inline_style();
+    }
+    for columns in ["repeat(2, repeat(2, 10px))", "repeat(1000, repeat(1000, repeat(1000, [a])))", "repeat(3, [a b])"] {
+        assert_eq!(value("grid-template-columns", columns, PropertyName::GridTemplateColumns), None, "{:?}", columns);
+    }
+    let vars = format!("var(--a, {}red{})", "var(--b, ".repeat(depth), ")".repeat(depth));
+    assert_eq!(CssParser::substitute_vars(&vars, &mut |_| None), None);
+}
+
+/// A cheap stand-in for the fuzz target in `fuzz/`: mutates valid
+/// stylesheets at random and parses the result.
+#[test]
+fn test_mutated_input_does_not_panic() {
+    let seeds = [
+        "div#a.b { color: red; margin: 1px 2px !important; --x: var(--y, 3px); }",
+        "@import url(\"a.css\") print; @media screen and (min-width: 600px) { p { font: bold 12px/1.5 \"A B\", serif; } }",
+        "a { width: calc(100% - 2em); color: rgb(1 2 3 / 50%); border: 1px solid #fff; background: hsl(1, 2%, 3%); }",
+        "b { grid-template-columns: [a] repeat(2, [b] 1fr minmax(10px, auto)) [c]; grid-area: x / 1 / span 2; }",
+    ];
+    let alphabet: Vec<char> = "{}()[];:,!@#.-+*/%\"'\\ \n0123456789abcdefgimprsuvx\u{0}é😀".chars().collect();
+    // xorshift, so that failures reproduce
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut random = |bound: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % bound.max(1) as u64) as usize
+    };
+    for _ in 0..5_000 {
+        let mut chars: Vec<char> = seeds[random(seeds.len())].chars().collect();
+        for _ in 0..random(8) + 1 {
+            let position = random(chars.len() + 1);
+            match random(3) {
+                0 if position < chars.len() => {
+                    chars.remove(position);
+                }
+                1 => chars.insert(position, alphabet[random(alphabet.len())]),
+                _ => chars.truncate(position),
+            }
+        }
+        let css: String = chars.into_iter().collect();
+        let stylesheet = CssParser::new(&css).parse_stylesheet();
+        CssParser::new(&stylesheet.to_css()).parse_stylesheet();
+        CssParser::new(&css).parse_inline_style();
+    }
+}
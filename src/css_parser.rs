@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs::File;
 use std::io::{BufReader, Read};
@@ -6,46 +6,147 @@ use std::iter::Peekable;
 use std::str::Chars;
 use crate::css::*;
 
-pub struct CssParser<'a> {
-    chars: Peekable<Chars<'a>>,
+/// How deep `@media` blocks, math functions and `var()` fallbacks may nest.
+/// Anything deeper is invalid, so that no input can overflow the stack.
+const MAX_NESTING: usize = 32;
+/// Bound on the length of a value after `var()` substitution, which would
+/// otherwise double at every step of `--a: var(--b) var(--b)` chains.
+const MAX_SUBSTITUTED_LENGTH: usize = 1 << 16;
+/// Bounds on `repeat()` counts and on the size of a track list.
+const MAX_REPEAT: usize = 1000;
+const MAX_TRACKS: usize = 1000;
+const MAX_LINE_NAMES: usize = 1000;
+
+pub struct CssParser {
+    chars: Peekable<std::vec::IntoIter<char>>,
+    imports: Vec<Import>,
 }
 
 
-impl<'a> CssParser<'a> {
+impl CssParser {
     pub fn new(full_css: &str) -> CssParser {
         CssParser {
-            chars: full_css.chars().peekable(),
+            chars: strip_comments(full_css).into_iter().peekable(),
+            imports: Vec::new(),
         }
     }
 
-    pub fn parse_stylesheet(&mut self) -> Stylesheet {
-        let mut stylesheet = Stylesheet::default();
+    /// The browser's default styles, the lowest priority origin of the cascade.
+    pub fn user_agent_stylesheet() -> Stylesheet {
+        CssParser::new(include_str!("ua.css")).parse_stylesheet().with_origin(Origin::UserAgent)
+    }
 
-        while self.chars.peek().is_some() {
-            let rule = self.parse_rule();
-            stylesheet.rules.push(rule);
+    pub fn parse_stylesheet(&mut self) -> Stylesheet {
+        let mut rules = self.parse_rules(&[], false);
+        for (id, rule) in rules.iter_mut().enumerate() {
+            rule.id = id;
         }
-        let last = &stylesheet.rules[stylesheet.rules.len() - 1];
-        let default = &Rule::default();
-        let result = last == default;
-        if result {
-            stylesheet.rules.pop();
+        Stylesheet {
+            next_rule_id: rules.len(),
+            rules,
+            imports: std::mem::take(&mut self.imports),
+            ..Stylesheet::default()
         }
-        stylesheet
     }
 
-    fn parse_rule(&mut self) -> Rule {
-        let mut rule = Rule::default();
-        let mut properties = HashMap::new();
+    /// Parses rules up to the end of the input, or up to the `}` that closes
+    /// the enclosing block when `nested`. `media` are the conditions of the
+    /// enclosing `@media` blocks.
+    fn parse_rules(&mut self, media: &[MediaQueryList], nested: bool) -> Vec<Rule> {
+        let mut rules = Vec::new();
+        loop {
+            self.consume_while(char::is_whitespace);
+            match self.chars.peek() {
+                None => break,
+                Some('}') => {
+                    self.chars.next();
+                    if nested {
+                        break;
+                    }
+                }
+                Some('@') => {
+                    self.chars.next();
+                    // @import is only valid before all other rules
+                    let allow_import = !nested && rules.is_empty();
+                    rules.append(&mut self.parse_at_rule(media, allow_import));
+                }
+                Some(_) => {
+                    for mut rule in self.parse_rule() {
+                        rule.media = media.to_vec();
+                        rules.push(rule);
+                    }
+                }
+            }
+        }
+        rules
+    }
 
-        self.consume_while(is_space);
-        self.consume_while(is_not_valid_selector);
+    /// Parses the at-rule after its `@`. Rules inside `@media` are returned
+    /// with the media condition attached, unknown at-rules are skipped.
+    fn parse_at_rule(&mut self, media: &[MediaQueryList], allow_import: bool) -> Vec<Rule> {
+        let name = self.parse_identifier();
+        let prelude = self.consume_while(|c| c != '{' && c != ';');
+        if self.chars.next() != Some('{') {
+            // statement at-rule such as `@charset "utf-8";`
+            if name == "import" && allow_import {
+                if let Some(import) = parse_import(&prelude) {
+                    self.imports.push(import);
+                }
+            }
+            return Vec::new();
+        }
+        // blocks nested deeper than that are dropped rather than recursed into
+        if name == "media" && media.len() < MAX_NESTING {
+            let mut media = media.to_vec();
+            media.push(parse_media_query_list(&prelude));
+            return self.parse_rules(&media, true);
+        }
+        self.skip_block();
+        Vec::new()
+    }
+
+    /// Skips the rest of a `{}` block whose opening brace was consumed.
+    fn skip_block(&mut self) {
+        let mut depth = 1;
+        for c in self.chars.by_ref() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+        }
+    }
 
+    /// Parses a style rule into one `Rule` per selector of its selector list.
+    /// A selector we don't support drops the whole rule, declarations and all.
+    fn parse_rule(&mut self) -> Vec<Rule> {
+        let selectors = self.parse_selectors();
+        // trailing garbage at the end of the input has no declaration block
         if self.chars.peek().is_none() {
-            return rule;
+            return Vec::new();
         }
+        let mut rule = Rule::default();
+        self.parse_declarations(&mut rule);
+        self.chars.next();
+        selectors.unwrap_or_default().into_iter()
+            .map(|selector| Rule { selector, ..rule.clone() })
+            .collect()
+    }
 
-        rule.selector = self.parse_selector();
+    /// Parses the declarations of a `style` attribute. The selector of the
+    /// returned rule is meaningless.
+    pub fn parse_inline_style(&mut self) -> Rule {
+        let mut rule = Rule::default();
+        self.parse_declarations(&mut rule);
+        rule
+    }
+
+    /// Parses declarations into `rule` up to the closing `}` or the end of input.
+    fn parse_declarations(&mut self, rule: &mut Rule) {
+        let mut properties = HashMap::new();
 
         // go until meet property declaration
         self.consume_while(is_not_property_identifier);
@@ -55,122 +156,871 @@ impl<'a> CssParser<'a> {
             if self.chars.peek().map_or(false, |c| *c == '}') {
                 break;
             }
-            let property = self.parse_property();
-            properties.insert(property.0, property.1);
+            let (declarations, important) = self.parse_property();
+            for (name, value) in declarations {
+                if important {
+                    rule.important.insert(name.clone());
+                } else if rule.important.contains(&name) {
+                    // a normal declaration never overrides an important one
+                    continue;
+                }
+                properties.insert(name, value);
+            }
         }
-
-        self.chars.next();
         rule.properties = properties;
-        rule
     }
 
-    fn parse_selector(&mut self) -> Selector {
-        self.consume_while(is_space);
-        self.consume_while(is_not_valid_selector);
-        let mut selector = Selector::default();
-        let mut name = String::new();
-        let mut selector_type: u8 = 0; // 0 - tag, 1 - class. 2 - id
-        if self.chars.peek().map_or(false, |c| *c == '.') {
-            self.chars.next();
-            selector_type = 1;
-        } else if self.chars.peek().map_or(false, |c| *c == '#') {
-            self.chars.next();
-            selector_type = 2;
-        }
-        while self.chars.peek().map_or(false, |c| *c != ' ' && *c != '{') {
-            name.push(self.chars.next().unwrap());
-        }
-        match selector_type {
-            1 => selector.class = Some(name),
-            2 => selector.id = Some(name),
-            _ => selector.tag_name = Some(name),
+    /// Parses the selector list up to the declaration block, `None` if any
+    /// selector in it is invalid or not supported.
+    fn parse_selectors(&mut self) -> Option<Vec<Selector>> {
+        let prelude = self.consume_while(|c| c != '{');
+        let mut prelude = prelude.trim();
+        // CDO and CDC between rules are ignored
+        while let Some(rest) = prelude.strip_prefix("<!--").or_else(|| prelude.strip_prefix("-->")) {
+            prelude = rest.trim_start();
         }
-        selector
+        prelude.split(',').map(|selector| parse_compound_selector(selector.trim())).collect()
     }
 
-    fn parse_property(&mut self) -> (PropertyName, PropertyValue) {
-        let mut property = (PropertyName::default(), PropertyValue::default());
-        let mut name = String::new();
+    /// Parses one declaration. Shorthands come back expanded into their longhands.
+    fn parse_property(&mut self) -> (Vec<(PropertyName, PropertyValue)>, bool) {
         self.consume_while(is_space);
-        while self.chars.peek().map_or(false, |c| *c != ':') {
-            name.push(self.chars.next().unwrap());
+        let name = self.consume_while(|c| c != ':' && c != ';' && c != '}');
+        if self.chars.next_if_eq(&':').is_none() {
+            // a declaration without a value is dropped, the next one starts after the `;`
+            return (Vec::new(), false);
         }
-        self.chars.next(); // skip ':'
         self.consume_while(is_space);
+        let value = self.parse_declaration_value();
+        let (value, important) = CssParser::split_important(value);
+        (CssParser::parse_declaration(&name, &value), important)
+    }
+
+    /// The value of a declaration, up to the `;` or the `}` that ends it.
+    /// These don't count inside strings and brackets, as in `url("a;b")`.
+    fn parse_declaration_value(&mut self) -> String {
         let mut value = String::new();
-        while self.chars.peek().map_or(false, |c| *c != ';') {
-            value.push(self.chars.next().unwrap());
+        let mut depth = 0;
+        let mut quote = None;
+        while let Some(&c) = self.chars.peek() {
+            match (quote, c) {
+                (Some(_), '\\') => {
+                    value.push(c);
+                    self.chars.next();
+                    if let Some(escaped) = self.chars.next() {
+                        value.push(escaped);
+                    }
+                    continue;
+                }
+                (Some(open), _) if open == c => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '(' | '[' | '{') => depth += 1,
+                (None, ')' | ']' | '}') if depth > 0 => depth -= 1,
+                (None, ';' | '}') if depth == 0 => break,
+                _ => {}
+            }
+            value.push(c);
+            self.chars.next();
+        }
+        value
+    }
+
+    /// Parses `name: value` without `!important`. An empty result means the
+    /// declaration is invalid.
+    pub fn parse_declaration(name: &str, value: &str) -> Vec<(PropertyName, PropertyValue)> {
+        // property names are case-insensitive, custom property names are not
+        let name = name.trim();
+        let name = if name.starts_with("--") { name.to_string() } else { name.to_ascii_lowercase() };
+        CssParser::process_property_members(name, value.trim().to_string())
+    }
+
+    /// Strips a trailing `!important` from a declaration value.
+    fn split_important(value: String) -> (String, bool) {
+        let value = value.trim_end();
+        if let Some(bang) = value.rfind('!') {
+            if value[bang + 1..].trim_start().eq_ignore_ascii_case("important") {
+                return (value[..bang].trim_end().to_string(), true);
+            }
         }
-        let (name, value) = CssParser::process_property_members(name, value);
-        property.0 = name;
-        property.1 = value;
-        property
+        (value.to_string(), false)
     }
 
-    fn process_property_members(name: String, value: String) -> (PropertyName, PropertyValue) {
+    /// Maps a declaration to longhand properties. An empty result means the
+    /// value was invalid and the declaration is dropped.
+    fn process_property_members(name: String, value: String) -> Vec<(PropertyName, PropertyValue)> {
+        let longhand = |name: PropertyName, value: Option<PropertyValue>| -> Vec<(PropertyName, PropertyValue)> {
+            value.map(|value| vec![(name, value)]).unwrap_or_default()
+        };
+        if name.starts_with("--") {
+            let value = match CssWideKeyword::from_str(&value) {
+                Some(keyword) => PropertyValue::CssWide(keyword),
+                None => PropertyValue::Other(value.trim().to_string()),
+            };
+            return vec![(PropertyName::Custom(name), value)];
+        }
+        if value.to_ascii_lowercase().contains("var(") {
+            // every longhand waits for the variables, see `substitute_vars`
+            let longhands = CssParser::longhands(&name);
+            if !longhands.is_empty() {
+                return longhands.into_iter()
+                    .map(|longhand| (longhand, PropertyValue::Unresolved { property: name.clone(), value: value.clone() }))
+                    .collect();
+            }
+        }
+        if let Some(keyword) = CssWideKeyword::from_str(&value) {
+            let longhands = CssParser::longhands(&name);
+            if !longhands.is_empty() {
+                return longhands.into_iter().map(|name| (name, PropertyValue::CssWide(keyword))).collect();
+            }
+        }
         match name.as_str() {
-            "color" => (PropertyName::Color, PropertyValue::Color(CssParser::parse_color(value))),
-            "background-color" => (PropertyName::BackgroundColor, PropertyValue::Color(CssParser::parse_color(value))),
-            "margin" => (PropertyName::Margin, PropertyValue::Length(CssParser::parse_length(value))),
-            "margin-top" => (PropertyName::MarginTop, PropertyValue::Length(CssParser::parse_length(value))),
-            "margin-bottom" => (PropertyName::MarginBottom, PropertyValue::Length(CssParser::parse_length(value))),
-            "margin-left" => (PropertyName::MarginLeft, PropertyValue::Length(CssParser::parse_length(value))),
-            "margin-right" => (PropertyName::MarginRight, PropertyValue::Length(CssParser::parse_length(value))),
-            "padding" => (PropertyName::Padding, PropertyValue::Length(CssParser::parse_length(value))),
-            "padding-top" => (PropertyName::PaddingTop, PropertyValue::Length(CssParser::parse_length(value))),
-            "padding-bottom" => (PropertyName::PaddingBottom, PropertyValue::Length(CssParser::parse_length(value))),
-            "padding-left" => (PropertyName::PaddingLeft, PropertyValue::Length(CssParser::parse_length(value))),
-            "padding-right" => (PropertyName::PaddingRight, PropertyValue::Length(CssParser::parse_length(value))),
-            "width" => (PropertyName::Width, PropertyValue::Length(CssParser::parse_length(value))),
-            "height" => (PropertyName::Height, PropertyValue::Length(CssParser::parse_length(value))),
-            "display" => (PropertyName::Display, PropertyValue::Display(CssParser::parse_display(value))),
-            _ => (PropertyName::Other, PropertyValue::Other(value)),
-        }
-    }
-
-
-    fn parse_display(value: String) -> DisplayType {
-        match value.as_str() {
-            "block" => DisplayType::Block,
-            "inline" => DisplayType::Inline,
-            "none" => DisplayType::None,
-            _ => DisplayType::None,
+            "color" => longhand(PropertyName::Color, CssParser::parse_color_value(&value)),
+            "background-color" => longhand(PropertyName::BackgroundColor, CssParser::parse_color_value(&value)),
+            "background" => CssParser::expand_background(&value),
+            "margin" => CssParser::expand_sides(&value, MARGIN, CssParser::parse_length_value),
+            "margin-top" => longhand(PropertyName::MarginTop, CssParser::parse_length_value(&value)),
+            "margin-bottom" => longhand(PropertyName::MarginBottom, CssParser::parse_length_value(&value)),
+            "margin-left" => longhand(PropertyName::MarginLeft, CssParser::parse_length_value(&value)),
+            "margin-right" => longhand(PropertyName::MarginRight, CssParser::parse_length_value(&value)),
+            "padding" => CssParser::expand_sides(&value, PADDING, CssParser::parse_non_negative_length),
+            "padding-top" => longhand(PropertyName::PaddingTop, CssParser::parse_non_negative_length(&value)),
+            "padding-bottom" => longhand(PropertyName::PaddingBottom, CssParser::parse_non_negative_length(&value)),
+            "padding-left" => longhand(PropertyName::PaddingLeft, CssParser::parse_non_negative_length(&value)),
+            "padding-right" => longhand(PropertyName::PaddingRight, CssParser::parse_non_negative_length(&value)),
+            "border" => CssParser::expand_border(&value, &[0, 1, 2, 3]),
+            "border-top" => CssParser::expand_border(&value, &[0]),
+            "border-right" => CssParser::expand_border(&value, &[1]),
+            "border-bottom" => CssParser::expand_border(&value, &[2]),
+            "border-left" => CssParser::expand_border(&value, &[3]),
+            "border-width" => CssParser::expand_sides(&value, BORDER_WIDTH, CssParser::parse_border_width),
+            "border-style" => CssParser::expand_sides(&value, BORDER_STYLE, CssParser::parse_border_style),
+            "border-color" => CssParser::expand_sides(&value, BORDER_COLOR, CssParser::parse_color_value),
+            "border-top-width" => longhand(PropertyName::BorderTopWidth, CssParser::parse_border_width(&value)),
+            "border-right-width" => longhand(PropertyName::BorderRightWidth, CssParser::parse_border_width(&value)),
+            "border-bottom-width" => longhand(PropertyName::BorderBottomWidth, CssParser::parse_border_width(&value)),
+            "border-left-width" => longhand(PropertyName::BorderLeftWidth, CssParser::parse_border_width(&value)),
+            "border-top-style" => longhand(PropertyName::BorderTopStyle, CssParser::parse_border_style(&value)),
+            "border-right-style" => longhand(PropertyName::BorderRightStyle, CssParser::parse_border_style(&value)),
+            "border-bottom-style" => longhand(PropertyName::BorderBottomStyle, CssParser::parse_border_style(&value)),
+            "border-left-style" => longhand(PropertyName::BorderLeftStyle, CssParser::parse_border_style(&value)),
+            "border-top-color" => longhand(PropertyName::BorderTopColor, CssParser::parse_color_value(&value)),
+            "border-right-color" => longhand(PropertyName::BorderRightColor, CssParser::parse_color_value(&value)),
+            "border-bottom-color" => longhand(PropertyName::BorderBottomColor, CssParser::parse_color_value(&value)),
+            "border-left-color" => longhand(PropertyName::BorderLeftColor, CssParser::parse_color_value(&value)),
+            "font" => CssParser::expand_font(&value).unwrap_or_default(),
+            "font-style" => longhand(PropertyName::FontStyle, CssParser::parse_font_style(&value)),
+            "font-weight" => longhand(PropertyName::FontWeight, CssParser::parse_font_weight(&value)),
+            "font-size" => longhand(PropertyName::FontSize, CssParser::parse_font_size(&value)),
+            "line-height" => longhand(PropertyName::LineHeight, CssParser::parse_line_height(&value)),
+            "font-family" => longhand(PropertyName::FontFamily, CssParser::parse_font_family(&value)),
+            "list-style-type" => longhand(PropertyName::ListStyleType, CssParser::parse_list_style_type(&value)),
+            "text-align" => longhand(PropertyName::TextAlign, CssParser::parse_keyword(&value, &["start", "end", "left", "right", "center", "justify"])),
+            "visibility" => longhand(PropertyName::Visibility, CssParser::parse_keyword(&value, &["visible", "hidden", "collapse"])),
+            "white-space" => longhand(PropertyName::WhiteSpace,
+                                      CssParser::parse_keyword(&value, &["normal", "pre", "nowrap", "pre-wrap", "pre-line", "break-spaces"])),
+            "width" => longhand(PropertyName::Width, CssParser::parse_length_value(&value)),
+            "height" => longhand(PropertyName::Height, CssParser::parse_length_value(&value)),
+            "display" => longhand(PropertyName::Display, CssParser::parse_display(&value).map(PropertyValue::Display)),
+            "flex" => CssParser::expand_flex(&value).unwrap_or_default(),
+            "flex-flow" => CssParser::expand_flex_flow(&value).unwrap_or_default(),
+            "flex-direction" => longhand(PropertyName::FlexDirection, CssParser::parse_keyword(&value, &FLEX_DIRECTION)),
+            "flex-wrap" => longhand(PropertyName::FlexWrap, CssParser::parse_keyword(&value, &FLEX_WRAP)),
+            "flex-grow" => longhand(PropertyName::FlexGrow, CssParser::parse_flex_factor(&value)),
+            "flex-shrink" => longhand(PropertyName::FlexShrink, CssParser::parse_flex_factor(&value)),
+            "flex-basis" => longhand(PropertyName::FlexBasis, CssParser::parse_flex_basis(&value)),
+            "order" => longhand(PropertyName::Order, value.parse::<i32>().ok().map(|order| PropertyValue::Number(order as f32))),
+            "justify-content" => longhand(PropertyName::JustifyContent, CssParser::parse_keyword(&value, &CONTENT_ALIGNMENT)),
+            "align-content" => longhand(PropertyName::AlignContent, CssParser::parse_keyword(&value, &CONTENT_ALIGNMENT)),
+            "align-items" => longhand(PropertyName::AlignItems, CssParser::parse_keyword(&value, &ITEM_ALIGNMENT)),
+            "align-self" => longhand(PropertyName::AlignSelf, CssParser::parse_keyword(&value, &["auto"])
+                .or_else(|| CssParser::parse_keyword(&value, &ITEM_ALIGNMENT))),
+            "gap" | "grid-gap" => CssParser::expand_gap(&value).unwrap_or_default(),
+            "row-gap" | "grid-row-gap" => longhand(PropertyName::RowGap, CssParser::parse_gap(&value)),
+            "column-gap" | "grid-column-gap" => longhand(PropertyName::ColumnGap, CssParser::parse_gap(&value)),
+            "justify-items" => longhand(PropertyName::JustifyItems, CssParser::parse_keyword(&value, &ITEM_ALIGNMENT)
+                .or_else(|| CssParser::parse_keyword(&value, &["left", "right"]))),
+            "justify-self" => longhand(PropertyName::JustifySelf, CssParser::parse_keyword(&value, &["auto", "left", "right"])
+                .or_else(|| CssParser::parse_keyword(&value, &ITEM_ALIGNMENT))),
+            "grid-template-columns" => longhand(PropertyName::GridTemplateColumns, CssParser::parse_track_list(&value)),
+            "grid-template-rows" => longhand(PropertyName::GridTemplateRows, CssParser::parse_track_list(&value)),
+            "grid-template-areas" => longhand(PropertyName::GridTemplateAreas, CssParser::parse_areas(&value)),
+            "grid-auto-columns" => longhand(PropertyName::GridAutoColumns, CssParser::parse_auto_tracks(&value)),
+            "grid-auto-rows" => longhand(PropertyName::GridAutoRows, CssParser::parse_auto_tracks(&value)),
+            "grid-auto-flow" => longhand(PropertyName::GridAutoFlow, CssParser::parse_auto_flow(&value)),
+            "grid-row-start" => longhand(PropertyName::GridRowStart, CssParser::parse_grid_line(&value)),
+            "grid-row-end" => longhand(PropertyName::GridRowEnd, CssParser::parse_grid_line(&value)),
+            "grid-column-start" => longhand(PropertyName::GridColumnStart, CssParser::parse_grid_line(&value)),
+            "grid-column-end" => longhand(PropertyName::GridColumnEnd, CssParser::parse_grid_line(&value)),
+            "grid-row" | "grid-column" | "grid-area" => CssParser::expand_grid_placement(&name, &value).unwrap_or_default(),
+            _ => vec![(PropertyName::Unknown(name), PropertyValue::Other(value))],
+        }
+    }
+
+    /// Applies the 1- to 4-value rule: `top [right [bottom [left]]]`, where a
+    /// missing right copies top, bottom copies top and left copies right.
+    fn expand_sides<F>(value: &str, names: [PropertyName; 4], parse: F) -> Vec<(PropertyName, PropertyValue)>
+        where
+            F: Fn(&str) -> Option<PropertyValue>,
+    {
+        let values: Option<Vec<PropertyValue>> = split_values(value).iter().map(|v| parse(v)).collect();
+        let values = match values {
+            Some(values) if (1..=4).contains(&values.len()) => values,
+            _ => return Vec::new(),
+        };
+        let top = values[0].clone();
+        let right = values.get(1).unwrap_or(&top).clone();
+        let bottom = values.get(2).unwrap_or(&top).clone();
+        let left = values.get(3).unwrap_or(&right).clone();
+        names.into_iter().zip([top, right, bottom, left]).collect()
+    }
+
+    /// `border` and `border-<side>`: width, style and color in any order.
+    /// Components that are left out are reset to their initial values.
+    fn expand_border(value: &str, sides: &[usize]) -> Vec<(PropertyName, PropertyValue)> {
+        let mut width = None;
+        let mut style = None;
+        let mut color = None;
+        for token in split_values(value) {
+            if style.is_none() && BorderStyle::from_str(&token).is_some() {
+                style = CssParser::parse_border_style(&token);
+            } else if width.is_none() && CssParser::parse_border_width(&token).is_some() {
+                width = CssParser::parse_border_width(&token);
+            } else if color.is_none() {
+                color = CssParser::parse_color_value(&token);
+                if color.is_none() {
+                    return Vec::new();
+                }
+            } else {
+                return Vec::new();
+            }
+        }
+        let width = width.unwrap_or(PropertyValue::Length(Length::Px(3.0)));
+        let style = style.unwrap_or(PropertyValue::BorderStyle(BorderStyle::None));
+        let color = color.unwrap_or(PropertyValue::Color(Color::Current));
+        let mut declarations = Vec::new();
+        for side in sides {
+            declarations.push((BORDER_WIDTH[*side].clone(), width.clone()));
+            declarations.push((BORDER_STYLE[*side].clone(), style.clone()));
+            declarations.push((BORDER_COLOR[*side].clone(), color.clone()));
+        }
+        declarations
+    }
+
+    /// `background`: only the color layer is modelled, everything else
+    /// (images, repeat, position) is accepted and ignored.
+    fn expand_background(value: &str) -> Vec<(PropertyName, PropertyValue)> {
+        let mut color = PropertyValue::Color(Color::Named("transparent".to_string()));
+        for token in split_values(value) {
+            let is_ignored = token == "none"
+                || token.starts_with("url(")
+                || token.contains("gradient(")
+                || token.contains("repeat")
+                || token == "/"
+                || starts_like_length(&token)
+                || matches!(token.as_str(), "top" | "bottom" | "left" | "right" | "center"
+                    | "scroll" | "fixed" | "local" | "cover" | "contain" | "auto"
+                    | "border-box" | "padding-box" | "content-box" | "text");
+            if !is_ignored {
+                match CssParser::parse_color_value(&token) {
+                    Some(value) => color = value,
+                    None => return Vec::new(),
+                }
+            }
+        }
+        vec![(PropertyName::BackgroundColor, color)]
+    }
+
+    /// `font: [style || weight]? size[/line-height]? family`. Omitted
+    /// components are reset to their initial values.
+    fn expand_font(value: &str) -> Option<Vec<(PropertyName, PropertyValue)>> {
+        let tokens = split_values(value);
+        let mut style = PropertyValue::Keyword("normal".to_string());
+        let mut weight = PropertyValue::Number(400.0);
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = tokens[i].as_str();
+            if token == "normal" || token == "small-caps" {
+                // font-variant is not modelled
+            } else if let Some(font_style) = CssParser::parse_font_style(token) {
+                style = font_style;
+            } else if let Some(font_weight) = CssParser::parse_font_weight(token) {
+                weight = font_weight;
+            } else {
+                break;
+            }
+            i += 1;
+        }
+        let (size, mut line_height) = match tokens.get(i)?.split_once('/') {
+            Some((size, "")) => (size.to_string(), None),
+            Some((size, line_height)) => (size.to_string(), Some(line_height.to_string())),
+            None => (tokens[i].clone(), None),
+        };
+        i += 1;
+        // `16px / 1.5` and `16px /1.5`
+        if line_height.is_none() && tokens.get(i).is_some_and(|t| t.starts_with('/')) {
+            let rest = tokens[i].trim_start_matches('/').to_string();
+            i += 1;
+            line_height = if rest.is_empty() {
+                i += 1;
+                Some(tokens.get(i - 1)?.clone())
+            } else {
+                Some(rest)
+            };
+        }
+        let size = CssParser::parse_font_size(&size)?;
+        let line_height = match line_height {
+            Some(line_height) => CssParser::parse_line_height(&line_height)?,
+            None => PropertyValue::Keyword("normal".to_string()),
+        };
+        let family = CssParser::parse_font_family(&tokens.get(i..)?.join(" "))?;
+        Some(vec![
+            (PropertyName::FontStyle, style),
+            (PropertyName::FontWeight, weight),
+            (PropertyName::FontSize, size),
+            (PropertyName::LineHeight, line_height),
+            (PropertyName::FontFamily, family),
+        ])
+    }
+
+    fn parse_length_value(value: &str) -> Option<PropertyValue> {
+        match value {
+            "auto" => Some(PropertyValue::Keyword("auto".to_string())),
+            value => CssParser::parse_length(value.to_string()).map(PropertyValue::Length),
+        }
+    }
+
+    /// A length that can't be negative, such as a padding. Only a literal
+    /// negative length is rejected, math functions aren't range checked.
+    fn parse_non_negative_length(value: &str) -> Option<PropertyValue> {
+        if split_dimension(value.trim()).is_some_and(|(number, _)| number < 0.0) {
+            return None;
+        }
+        CssParser::parse_length(value.to_string()).map(PropertyValue::Length)
+    }
+
+    fn parse_border_width(value: &str) -> Option<PropertyValue> {
+        match value {
+            "thin" => Some(PropertyValue::Length(Length::Px(1.0))),
+            "medium" => Some(PropertyValue::Length(Length::Px(3.0))),
+            "thick" => Some(PropertyValue::Length(Length::Px(5.0))),
+            value => CssParser::parse_non_negative_length(value),
+        }
+    }
+
+    fn parse_border_style(value: &str) -> Option<PropertyValue> {
+        BorderStyle::from_str(value).map(PropertyValue::BorderStyle)
+    }
+
+    fn parse_font_style(value: &str) -> Option<PropertyValue> {
+        match value {
+            "normal" | "italic" | "oblique" => Some(PropertyValue::Keyword(value.to_string())),
+            _ => None,
+        }
+    }
+
+    fn parse_font_weight(value: &str) -> Option<PropertyValue> {
+        match value {
+            "normal" => Some(PropertyValue::Number(400.0)),
+            "bold" => Some(PropertyValue::Number(700.0)),
+            "bolder" | "lighter" => Some(PropertyValue::Keyword(value.to_string())),
+            value => value.parse::<f32>().ok()
+                .filter(|weight| (1.0..=1000.0).contains(weight))
+                .map(PropertyValue::Number),
+        }
+    }
+
+    fn parse_font_size(value: &str) -> Option<PropertyValue> {
+        match value {
+            "xx-small" | "x-small" | "small" | "medium" | "large" | "x-large" | "xx-large"
+            | "xxx-large" | "smaller" | "larger" => Some(PropertyValue::Keyword(value.to_string())),
+            value => CssParser::parse_non_negative_length(value),
+        }
+    }
+
+    fn parse_line_height(value: &str) -> Option<PropertyValue> {
+        if value == "normal" {
+            return Some(PropertyValue::Keyword("normal".to_string()));
+        }
+        if let Ok(number) = value.parse::<f32>() {
+            return Some(PropertyValue::Number(number));
+        }
+        CssParser::parse_length(value.to_string()).map(PropertyValue::Length)
+    }
+
+    fn parse_font_family(value: &str) -> Option<PropertyValue> {
+        let families: Vec<String> = value
+            .split(',')
+            .map(|family| family.trim().trim_matches(|c| c == '"' || c == '\'').to_string())
+            .collect();
+        if families.iter().any(|family| family.is_empty()) {
+            return None;
         }
+        Some(PropertyValue::FontFamily(families))
     }
 
-    fn parse_color(value: String) -> Color {
+    /// Parses the value of a declaration whose `var()`s have been substituted
+    /// and returns what it sets `longhand` to, `None` if it is invalid.
+    pub fn parse_unresolved(property: &str, value: &str, longhand: &PropertyName) -> Option<PropertyValue> {
+        CssParser::process_property_members(property.to_string(), value.to_string()).into_iter()
+            .find(|(name, _)| name == longhand)
+            .map(|(_, value)| value)
+    }
+
+    /// Replaces every `var(--name, fallback)` in `value` with `lookup(name)`,
+    /// or the fallback when the variable isn't set. `None` if a variable has
+    /// neither, or if the result grows past `MAX_SUBSTITUTED_LENGTH`.
+    pub fn substitute_vars<F>(value: &str, lookup: &mut F) -> Option<String>
+        where F: FnMut(&str) -> Option<String> {
+        CssParser::substitute_vars_nested(value, lookup, 0)
+    }
+
+    fn substitute_vars_nested<F>(value: &str, lookup: &mut F, nesting: usize) -> Option<String>
+        where F: FnMut(&str) -> Option<String> {
+        if nesting == MAX_NESTING {
+            return None;
+        }
+        let lowercase = value.to_ascii_lowercase();
+        let mut result = String::new();
+        let mut position = 0;
+        while let Some(start) = lowercase[position..].find("var(").map(|start| position + start) {
+            result.push_str(&value[position..start]);
+            let arguments = &value[start + 4..];
+            // find the closing parenthesis and the comma before the fallback
+            let mut depth = 0;
+            let mut comma = None;
+            let mut end = None;
+            for (i, c) in arguments.char_indices() {
+                match c {
+                    '(' => depth += 1,
+                    ')' if depth == 0 => {
+                        end = Some(i);
+                        break;
+                    }
+                    ')' => depth -= 1,
+                    ',' if depth == 0 && comma.is_none() => comma = Some(i),
+                    _ => {}
+                }
+            }
+            let end = end?;
+            let name = arguments[..comma.unwrap_or(end)].trim();
+            if !name.starts_with("--") {
+                return None;
+            }
+            let substituted = match lookup(name) {
+                Some(value) => value,
+                None => {
+                    let fallback = &arguments[comma? + 1..end];
+                    CssParser::substitute_vars_nested(fallback.trim(), lookup, nesting + 1)?
+                }
+            };
+            result.push_str(&substituted);
+            if result.len() > MAX_SUBSTITUTED_LENGTH {
+                return None;
+            }
+            position = start + 4 + end + 1;
+        }
+        result.push_str(&value[position..]);
+        Some(result)
+    }
+
+    /// The custom properties `value` references, in fallbacks too.
+    pub fn var_references(value: &str) -> Vec<&str> {
+        value.to_ascii_lowercase().match_indices("var(")
+            .filter_map(|(start, _)| {
+                let arguments = value[start + 4..].trim_start();
+                let name = &arguments[..arguments.find(|c: char| c == ',' || c == ')' || c.is_whitespace())?];
+                name.starts_with("--").then_some(name)
+            })
+            .collect()
+    }
+
+    /// The longhands a declaration of `name` sets.
+    pub fn longhands(name: &str) -> Vec<PropertyName> {
+        match name {
+            "margin" => MARGIN.to_vec(),
+            "padding" => PADDING.to_vec(),
+            "border" => [BORDER_WIDTH, BORDER_STYLE, BORDER_COLOR].concat(),
+            "border-top" | "border-right" | "border-bottom" | "border-left" => {
+                let side = ["border-top", "border-right", "border-bottom", "border-left"].iter()
+                    .position(|side| *side == name).unwrap();
+                vec![BORDER_WIDTH[side].clone(), BORDER_STYLE[side].clone(), BORDER_COLOR[side].clone()]
+            }
+            "border-width" => BORDER_WIDTH.to_vec(),
+            "border-style" => BORDER_STYLE.to_vec(),
+            "border-color" => BORDER_COLOR.to_vec(),
+            "background" => vec![PropertyName::BackgroundColor],
+            "font" => vec![PropertyName::FontStyle, PropertyName::FontWeight, PropertyName::FontSize,
+                           PropertyName::LineHeight, PropertyName::FontFamily],
+            "flex" => vec![PropertyName::FlexGrow, PropertyName::FlexShrink, PropertyName::FlexBasis],
+            "flex-flow" => vec![PropertyName::FlexDirection, PropertyName::FlexWrap],
+            "gap" | "grid-gap" => vec![PropertyName::RowGap, PropertyName::ColumnGap],
+            "grid-row-gap" => vec![PropertyName::RowGap],
+            "grid-column-gap" => vec![PropertyName::ColumnGap],
+            "grid-row" => vec![PropertyName::GridRowStart, PropertyName::GridRowEnd],
+            "grid-column" => vec![PropertyName::GridColumnStart, PropertyName::GridColumnEnd],
+            "grid-area" => GRID_AREA.to_vec(),
+            name => PropertyName::from_css_name(name).into_iter().collect(),
+        }
+    }
+
+    fn parse_keyword(value: &str, keywords: &[&str]) -> Option<PropertyValue> {
+        let value = value.to_ascii_lowercase();
+        keywords.contains(&value.as_str()).then_some(PropertyValue::Keyword(value))
+    }
+
+    fn parse_list_style_type(value: &str) -> Option<PropertyValue> {
         match value {
-            color if color.starts_with('#') => Color::Hex(u32::from_str_radix(&color[1..], 16).unwrap()),
-            color if color.starts_with("rgb") => { Color::Rgb(0, 0, 0) } //todo
-            color => Color::Named(color),
+            "none" | "disc" | "circle" | "square" | "decimal" | "lower-alpha" | "upper-alpha"
+            | "lower-roman" | "upper-roman" => Some(PropertyValue::Keyword(value.to_string())),
+            _ => None,
+        }
+    }
+
+    /// `flex: none | [grow shrink?] || basis`. A factor left out is 1 and a
+    /// basis left out is 0.
+    fn expand_flex(value: &str) -> Option<Vec<(PropertyName, PropertyValue)>> {
+        let tokens = split_values(value);
+        if tokens.len() == 1 && tokens[0].eq_ignore_ascii_case("none") {
+            return Some(vec![
+                (PropertyName::FlexGrow, PropertyValue::Number(0.0)),
+                (PropertyName::FlexShrink, PropertyValue::Number(0.0)),
+                (PropertyName::FlexBasis, PropertyValue::Keyword("auto".to_string())),
+            ]);
+        }
+        let (mut grow, mut shrink, mut basis) = (None, None, None);
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            match parse_number(&token) {
+                Some(number) if grow.is_none() => {
+                    grow = Some(number);
+                    shrink = tokens.next_if(|next| parse_number(next).is_some()).and_then(|next| parse_number(&next));
+                }
+                _ if basis.is_none() => basis = Some(CssParser::parse_flex_basis(&token)?),
+                _ => return None,
+            }
+        }
+        if grow.is_none() && basis.is_none() || grow.unwrap_or(0.0) < 0.0 || shrink.unwrap_or(0.0) < 0.0 {
+            return None;
         }
+        Some(vec![
+            (PropertyName::FlexGrow, PropertyValue::Number(grow.unwrap_or(1.0))),
+            (PropertyName::FlexShrink, PropertyValue::Number(shrink.unwrap_or(1.0))),
+            (PropertyName::FlexBasis, basis.unwrap_or(PropertyValue::Length(Length::Px(0.0)))),
+        ])
     }
 
-    fn parse_length(value: String) -> Length {
-        //todo question
-        if value.ends_with("%") {
-            let value = value[..value.len() - 1].parse::<u16>().unwrap();
-            if value > 100 {
-                panic!("Percentage value should be less than 100");
+    /// `flex-flow: direction || wrap`, what is left out is reset.
+    fn expand_flex_flow(value: &str) -> Option<Vec<(PropertyName, PropertyValue)>> {
+        let (mut direction, mut wrap) = (None, None);
+        for token in split_values(value) {
+            if direction.is_none() && CssParser::parse_keyword(&token, &FLEX_DIRECTION).is_some() {
+                direction = CssParser::parse_keyword(&token, &FLEX_DIRECTION);
+            } else if wrap.is_none() && CssParser::parse_keyword(&token, &FLEX_WRAP).is_some() {
+                wrap = CssParser::parse_keyword(&token, &FLEX_WRAP);
+            } else {
+                return None;
             }
-            return Length::Percent(value as u8);
         }
-        let unit = &value[value.len() - 2..];
-        let value = value[..value.len() - 2].parse::<i16>().unwrap();
-        match unit {
-            "px" => Length::Px(value),
-            "%" => {
-                if value > 100 {
-                    panic!("Percentage value should be less than 100");
+        if direction.is_none() && wrap.is_none() {
+            return None;
+        }
+        Some(vec![
+            (PropertyName::FlexDirection, direction.unwrap_or(PropertyValue::Keyword("row".to_string()))),
+            (PropertyName::FlexWrap, wrap.unwrap_or(PropertyValue::Keyword("nowrap".to_string()))),
+        ])
+    }
+
+    fn parse_flex_factor(value: &str) -> Option<PropertyValue> {
+        parse_number(value).filter(|factor| *factor >= 0.0).map(PropertyValue::Number)
+    }
+
+    fn parse_flex_basis(value: &str) -> Option<PropertyValue> {
+        match value.to_ascii_lowercase().as_str() {
+            "content" => Some(PropertyValue::Keyword("content".to_string())),
+            value => CssParser::parse_length_value(value),
+        }
+    }
+
+    /// `gap: row [column]`, the column gap copies the row gap.
+    fn expand_gap(value: &str) -> Option<Vec<(PropertyName, PropertyValue)>> {
+        let values: Option<Vec<PropertyValue>> = split_values(value).iter().map(|v| CssParser::parse_gap(v)).collect();
+        match values?.as_slice() {
+            [gap] => Some(vec![(PropertyName::RowGap, gap.clone()), (PropertyName::ColumnGap, gap.clone())]),
+            [row, column] => Some(vec![(PropertyName::RowGap, row.clone()), (PropertyName::ColumnGap, column.clone())]),
+            _ => None,
+        }
+    }
+
+    fn parse_gap(value: &str) -> Option<PropertyValue> {
+        match value {
+            "normal" => Some(PropertyValue::Keyword("normal".to_string())),
+            value => CssParser::parse_length(value.to_string()).map(PropertyValue::Length),
+        }
+    }
+
+    /// `none` or track sizes, `repeat(count, ...)` and line names in brackets.
+    fn parse_track_list(value: &str) -> Option<PropertyValue> {
+        if value.eq_ignore_ascii_case("none") {
+            return Some(PropertyValue::Keyword("none".to_string()));
+        }
+        let mut list = TrackList { tracks: Vec::new(), lines: vec![Vec::new()] };
+        CssParser::parse_tracks(value, &mut list, false)?;
+        (!list.tracks.is_empty()).then_some(PropertyValue::Tracks(list))
+    }
+
+    /// Adds the tracks and line names of `value` to `list`. The part a
+    /// `repeat()` repeats has a track in it and no other `repeat()`.
+    fn parse_tracks(value: &str, list: &mut TrackList, repeated: bool) -> Option<()> {
+        let names = |list: &TrackList| list.lines.iter().map(Vec::len).sum::<usize>();
+        for token in split_track_list(value) {
+            let lower = token.to_ascii_lowercase();
+            if let Some(names) = token.strip_prefix('[').and_then(|names| names.strip_suffix(']')) {
+                list.lines.last_mut()?.extend(names.split_whitespace().map(String::from));
+            } else if lower.starts_with("repeat(") && lower.ends_with(')') {
+                if repeated {
+                    return None;
                 }
-                Length::Percent(value as u8)
+                // `auto-fill` and `auto-fit` are not supported
+                let (count, tracks) = token["repeat(".len()..token.len() - 1].split_once(',')?;
+                let count = count.trim().parse::<usize>().ok().filter(|count| (1..=MAX_REPEAT).contains(count))?;
+                let mut part = TrackList { tracks: Vec::new(), lines: vec![Vec::new()] };
+                CssParser::parse_tracks(tracks, &mut part, true)?;
+                if part.tracks.is_empty()
+                    || list.tracks.len() + count * part.tracks.len() > MAX_TRACKS
+                    || names(list) + count * names(&part) > MAX_LINE_NAMES {
+                    return None;
+                }
+                for _ in 0..count {
+                    list.lines.last_mut()?.extend(part.lines[0].iter().cloned());
+                    for (track, names) in part.tracks.iter().zip(&part.lines[1..]) {
+                        list.tracks.push(track.clone());
+                        list.lines.push(names.clone());
+                    }
+                }
+            } else {
+                list.tracks.push(CssParser::parse_track(&lower)?);
+                list.lines.push(Vec::new());
+            }
+            if list.tracks.len() > MAX_TRACKS || names(list) > MAX_LINE_NAMES {
+                return None;
             }
-            _ => Length::Px(value),
         }
+        Some(())
     }
 
-    fn parse_rgb_value(&mut self, value: String, index: usize) {
-        // todo parse rgb to hex
+    fn parse_track(value: &str) -> Option<Track> {
+        if let Some(arguments) = value.strip_prefix("minmax(").and_then(|arguments| arguments.strip_suffix(')')) {
+            let (min, max) = arguments.split_once(',')?;
+            let min = CssParser::parse_track_breadth(min.trim())?;
+            // a flexible minimum is invalid
+            if let TrackBreadth::Fr(_) = min {
+                return None;
+            }
+            return Some(Track { min, max: CssParser::parse_track_breadth(max.trim())? });
+        }
+        match CssParser::parse_track_breadth(value)? {
+            TrackBreadth::Fr(fr) => Some(Track { min: TrackBreadth::Auto, max: TrackBreadth::Fr(fr) }),
+            breadth => Some(Track { min: breadth.clone(), max: breadth }),
+        }
+    }
+
+    fn parse_track_breadth(value: &str) -> Option<TrackBreadth> {
+        match value {
+            "auto" => Some(TrackBreadth::Auto),
+            "min-content" => Some(TrackBreadth::MinContent),
+            "max-content" => Some(TrackBreadth::MaxContent),
+            _ => match value.strip_suffix("fr") {
+                Some(fr) => parse_number(fr).filter(|fr| *fr >= 0.0).map(TrackBreadth::Fr),
+                None => match CssParser::parse_length(value.to_string())? {
+                    Length::Px(px) if px < 0.0 => None,
+                    Length::Percent(percent) if percent < 0.0 => None,
+                    length => Some(TrackBreadth::Length(length)),
+                },
+            },
+        }
+    }
+
+    /// `grid-auto-rows` and `grid-auto-columns`: track sizes without names.
+    fn parse_auto_tracks(value: &str) -> Option<PropertyValue> {
+        match CssParser::parse_track_list(value)? {
+            PropertyValue::Tracks(list) if list.lines.iter().all(Vec::is_empty) => Some(PropertyValue::Tracks(list)),
+            _ => None,
+        }
+    }
+
+    /// `grid-template-areas`: `none` or one string per row, with the same
+    /// number of cells in each. Every named area must be a rectangle.
+    fn parse_areas(value: &str) -> Option<PropertyValue> {
+        if value.eq_ignore_ascii_case("none") {
+            return Some(PropertyValue::Keyword("none".to_string()));
+        }
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        let mut rest = value.trim();
+        while !rest.is_empty() {
+            let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+            let end = rest[1..].find(quote)? + 1;
+            let row: Vec<String> = rest[1..end].split_whitespace()
+                .map(|cell| if cell.chars().all(|c| c == '.') { String::from(".") } else { cell.to_string() })
+                .collect();
+            if row.is_empty() || rows.first().is_some_and(|first| first.len() != row.len()) {
+                return None;
+            }
+            rows.push(row);
+            rest = rest[end + 1..].trim_start();
+        }
+        let names: HashSet<&String> = rows.iter().flatten().filter(|cell| *cell != ".").collect();
+        for name in names {
+            let cells: Vec<(usize, usize)> = rows.iter().enumerate()
+                .flat_map(|(y, row)| row.iter().enumerate().filter(|(_, cell)| *cell == name).map(move |(x, _)| (y, x)))
+                .collect();
+            let (top, left) = (cells.iter().map(|c| c.0).min()?, cells.iter().map(|c| c.1).min()?);
+            let (bottom, right) = (cells.iter().map(|c| c.0).max()?, cells.iter().map(|c| c.1).max()?);
+            if cells.len() != (bottom - top + 1) * (right - left + 1) {
+                return None;
+            }
+        }
+        (!rows.is_empty()).then_some(PropertyValue::Areas(rows))
+    }
+
+    /// `[row | column] || dense`, kept as `row`, `column`, `row dense` or `column dense`.
+    fn parse_auto_flow(value: &str) -> Option<PropertyValue> {
+        let tokens: Vec<String> = split_values(&value.to_ascii_lowercase());
+        let direction = tokens.iter().find(|token| *token == "row" || *token == "column");
+        let dense = tokens.iter().any(|token| token == "dense");
+        if tokens.is_empty() || tokens.len() != direction.is_some() as usize + dense as usize {
+            return None;
+        }
+        let direction = direction.map_or("row", String::as_str);
+        Some(PropertyValue::Keyword(if dense { format!("{} dense", direction) } else { direction.to_string() }))
+    }
+
+    /// `auto`, a line number other than 0, `span n` or a name.
+    fn parse_grid_line(value: &str) -> Option<PropertyValue> {
+        let tokens = split_values(value);
+        let line = match tokens.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+            [auto] if auto.eq_ignore_ascii_case("auto") => GridLine::Auto,
+            [span, count] if span.eq_ignore_ascii_case("span") =>
+                GridLine::Span(count.parse::<u32>().ok().filter(|count| *count > 0)?),
+            [line] => match line.parse::<i32>() {
+                Ok(0) => return None,
+                Ok(line) => GridLine::Line(line),
+                Err(_) if is_identifier(line) && !line.eq_ignore_ascii_case("span") => GridLine::Name(line.to_string()),
+                Err(_) => return None,
+            },
+            _ => return None,
+        };
+        Some(PropertyValue::GridLine(line))
+    }
+
+    /// `grid-row` and `grid-column` are `start [/ end]`, `grid-area` is
+    /// `row-start [/ column-start [/ row-end [/ column-end]]]`. A side left out
+    /// is the same name as the other side of its axis, or `auto`.
+    fn expand_grid_placement(name: &str, value: &str) -> Option<Vec<(PropertyName, PropertyValue)>> {
+        let names: Vec<PropertyName> = match name {
+            "grid-row" => vec![PropertyName::GridRowStart, PropertyName::GridRowEnd],
+            "grid-column" => vec![PropertyName::GridColumnStart, PropertyName::GridColumnEnd],
+            _ => GRID_AREA.to_vec(),
+        };
+        let mut lines: Vec<PropertyValue> = Vec::new();
+        for part in value.split('/') {
+            lines.push(CssParser::parse_grid_line(part.trim())?);
+        }
+        if lines.len() > names.len() {
+            return None;
+        }
+        // the side each missing one copies: the start of its axis
+        let copies: &[usize] = if names.len() == 2 { &[0, 0] } else { &[0, 0, 0, 1] };
+        for index in lines.len()..names.len() {
+            let line = match &lines[copies[index]] {
+                PropertyValue::GridLine(GridLine::Name(name)) => GridLine::Name(name.clone()),
+                _ => GridLine::Auto,
+            };
+            lines.push(PropertyValue::GridLine(line));
+        }
+        Some(names.into_iter().zip(lines).collect())
+    }
+
+    fn parse_display(value: &str) -> Option<DisplayType> {
+        match value.to_ascii_lowercase().as_str() {
+            "block" => Some(DisplayType::Block),
+            "inline" => Some(DisplayType::Inline),
+            "inline-block" => Some(DisplayType::InlineBlock),
+            "list-item" => Some(DisplayType::ListItem),
+            "flex" => Some(DisplayType::Flex),
+            "inline-flex" => Some(DisplayType::InlineFlex),
+            "grid" => Some(DisplayType::Grid),
+            "inline-grid" => Some(DisplayType::InlineGrid),
+            "none" => Some(DisplayType::None),
+            _ => None,
+        }
+    }
+
+    fn parse_color_value(value: &str) -> Option<PropertyValue> {
+        CssParser::parse_color(value.to_string()).map(PropertyValue::Color)
+    }
+
+    /// CSS Color Level 4: hex notations, named colors, `transparent`,
+    /// `currentColor` and the `rgb()`, `rgba()`, `hsl()`, `hsla()` and `hwb()` functions.
+    fn parse_color(value: String) -> Option<Color> {
+        let value = value.trim().to_ascii_lowercase();
+        if let Some(hex) = value.strip_prefix('#') {
+            return parse_hex_color(hex);
+        }
+        if let Some((function, arguments)) = value.strip_suffix(')').and_then(|v| v.split_once('(')) {
+            return CssParser::parse_color_function(function, arguments);
+        }
+        match value.as_str() {
+            "currentcolor" => Some(Color::Current),
+            "transparent" => Some(Color::Named(value)),
+            name if named_color(name).is_some() => Some(Color::Named(value)),
+            _ => None,
+        }
+    }
+
+    fn parse_color_function(function: &str, arguments: &str) -> Option<Color> {
+        let (channels, alpha) = split_color_arguments(arguments)?;
+        let alpha = match alpha {
+            Some(alpha) => parse_alpha(&alpha)?,
+            None => 255,
+        };
+        let (r, g, b) = match function {
+            "rgb" | "rgba" => (
+                parse_rgb_channel(&channels[0])?,
+                parse_rgb_channel(&channels[1])?,
+                parse_rgb_channel(&channels[2])?,
+            ),
+            "hsl" | "hsla" => {
+                let (r, g, b) = hsl_to_rgb(
+                    parse_hue(&channels[0])?,
+                    parse_fraction(&channels[1])?,
+                    parse_fraction(&channels[2])?,
+                );
+                (to_channel(r), to_channel(g), to_channel(b))
+            }
+            "hwb" => {
+                let (r, g, b) = hwb_to_rgb(
+                    parse_hue(&channels[0])?,
+                    parse_fraction(&channels[1])?,
+                    parse_fraction(&channels[2])?,
+                );
+                (to_channel(r), to_channel(g), to_channel(b))
+            }
+            _ => return None,
+        };
+        Some(Color::Rgba(r, g, b, alpha))
+    }
+
+    fn parse_length(value: String) -> Option<Length> {
+        if value.trim_end().ends_with(')') {
+            return MathParser::parse(&value).map(Calc::into_length);
+        }
+        let (number, unit) = split_dimension(value.trim())?;
+        if unit.is_empty() {
+            // unitless lengths are only allowed for zero
+            return if number == 0.0 { Some(Length::Px(0.0)) } else { None };
+        }
+        Length::from_unit(number, &unit.to_ascii_lowercase())
     }
 
     fn parse_identifier(&mut self) -> String {
@@ -199,6 +1049,715 @@ impl<'a> CssParser<'a> {
     }
 }
 
+/// Intermediate result while parsing a math function: plain numbers are
+/// only allowed as factors and divisors.
+enum MathValue {
+    Number(f32),
+    Length(Calc),
+}
+
+/// Recursive descent parser for `calc()`, `min()`, `max()` and `clamp()`.
+struct MathParser<'a> {
+    chars: Peekable<Chars<'a>>,
+    /// Functions and parentheses entered.
+    depth: usize,
+}
+
+impl<'a> MathParser<'a> {
+    /// Parses and simplifies a math function spanning all of `value`.
+    fn parse(value: &str) -> Option<Calc> {
+        let mut parser = MathParser {
+            chars: value.chars().peekable(),
+            depth: 0,
+        };
+        parser.skip_whitespace();
+        let calc = parser.nested(MathParser::parse_function)?;
+        parser.skip_whitespace();
+        if parser.chars.peek().is_some() {
+            return None;
+        }
+        Some(calc.simplify())
+    }
+
+    /// Runs `parse` one level deeper, failing past `MAX_NESTING` levels.
+    fn nested<T>(&mut self, parse: fn(&mut Self) -> Option<T>) -> Option<T> {
+        if self.depth == MAX_NESTING {
+            return None;
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn parse_function(&mut self) -> Option<Calc> {
+        let name = self.consume_while(is_valid_ident).to_ascii_lowercase();
+        if self.chars.next() != Some('(') {
+            return None;
+        }
+        let mut arguments = vec![self.parse_length_argument()?];
+        while self.chars.peek() == Some(&',') {
+            self.chars.next();
+            arguments.push(self.parse_length_argument()?);
+        }
+        if self.chars.next() != Some(')') {
+            return None;
+        }
+        match (name.as_str(), arguments.len()) {
+            ("calc", 1) => arguments.pop(),
+            ("min", _) => Some(Calc::Min(arguments)),
+            ("max", _) => Some(Calc::Max(arguments)),
+            ("clamp", 3) => {
+                let max = arguments.pop().unwrap();
+                let value = arguments.pop().unwrap();
+                let min = arguments.pop().unwrap();
+                Some(Calc::Max(vec![min, Calc::Min(vec![value, max])]))
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_length_argument(&mut self) -> Option<Calc> {
+        let value = self.parse_sum()?;
+        self.skip_whitespace();
+        match value {
+            MathValue::Length(calc) => Some(calc),
+            MathValue::Number(_) => None,
+        }
+    }
+
+    fn parse_sum(&mut self) -> Option<MathValue> {
+        let (mut value, mut had_whitespace) = self.parse_product()?;
+        loop {
+            let sign = match self.chars.peek() {
+                Some('+') => 1.0,
+                Some('-') => -1.0,
+                _ => return Some(value),
+            };
+            self.chars.next();
+            // `+` and `-` must be surrounded by whitespace
+            if !had_whitespace || !self.skip_whitespace() {
+                return None;
+            }
+            let (right, whitespace_after) = self.parse_product()?;
+            had_whitespace = whitespace_after;
+            value = match (value, right) {
+                (MathValue::Number(left), MathValue::Number(right)) => MathValue::Number(left + sign * right),
+                (MathValue::Length(left), MathValue::Length(right)) => {
+                    let right = if sign < 0.0 { Calc::Product(Box::new(right), -1.0) } else { right };
+                    MathValue::Length(Calc::Sum(vec![left, right]))
+                }
+                _ => return None,
+            };
+        }
+    }
+
+    /// Also returns whether whitespace follows the product, which `parse_sum`
+    /// needs to tell a `-` operator from a negative number.
+    fn parse_product(&mut self) -> Option<(MathValue, bool)> {
+        let mut value = self.parse_factor()?;
+        loop {
+            let had_whitespace = self.skip_whitespace();
+            let operator = match self.chars.peek() {
+                Some('*') => '*',
+                Some('/') => '/',
+                _ => return Some((value, had_whitespace)),
+            };
+            self.chars.next();
+            let right = self.parse_factor()?;
+            value = match (operator, value, right) {
+                ('*', MathValue::Number(left), MathValue::Number(right)) => MathValue::Number(left * right),
+                ('*', MathValue::Number(factor), MathValue::Length(calc))
+                | ('*', MathValue::Length(calc), MathValue::Number(factor)) => {
+                    MathValue::Length(Calc::Product(Box::new(calc), factor))
+                }
+                ('/', MathValue::Number(left), MathValue::Number(right)) if right != 0.0 => MathValue::Number(left / right),
+                ('/', MathValue::Length(calc), MathValue::Number(divisor)) if divisor != 0.0 => {
+                    MathValue::Length(Calc::Product(Box::new(calc), 1.0 / divisor))
+                }
+                _ => return None,
+            };
+        }
+    }
+
+    fn parse_factor(&mut self) -> Option<MathValue> {
+        self.skip_whitespace();
+        match *self.chars.peek()? {
+            '(' => self.nested(|parser| {
+                parser.chars.next();
+                let value = parser.parse_sum()?;
+                parser.skip_whitespace();
+                if parser.chars.next() != Some(')') {
+                    return None;
+                }
+                Some(value)
+            }),
+            c if is_valid_start_ident(c) => self.nested(MathParser::parse_function).map(MathValue::Length),
+            _ => {
+                let mut token = String::new();
+                if let Some(sign) = self.chars.next_if(|c| *c == '+' || *c == '-') {
+                    token.push(sign);
+                }
+                token.push_str(&self.consume_while(|c| c.is_ascii_alphanumeric() || c == '.' || c == '%'));
+                if let Some(number) = parse_number(&token) {
+                    return Some(MathValue::Number(number));
+                }
+                CssParser::parse_length(token).map(|length| MathValue::Length(Calc::Value(length)))
+            }
+        }
+    }
+
+    /// Returns whether any whitespace was skipped.
+    fn skip_whitespace(&mut self) -> bool {
+        !self.consume_while(char::is_whitespace).is_empty()
+    }
+
+    fn consume_while<F>(&mut self, condition: F) -> String
+        where
+            F: Fn(char) -> bool,
+    {
+        let mut result = String::new();
+        while self.chars.peek().is_some_and(|c| condition(*c)) {
+            result.push(self.chars.next().unwrap());
+        }
+
+        result
+    }
+}
+
+/// Removes `/* comments */`, except inside strings. An unclosed comment
+/// runs to the end of the input.
+fn strip_comments(css: &str) -> Vec<char> {
+    let mut chars = css.chars().peekable();
+    let mut result = Vec::new();
+    let mut quote = None;
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(_) if c == '\\' => {
+                result.push(c);
+                if let Some(escaped) = chars.next() {
+                    result.push(escaped);
+                }
+                continue;
+            }
+            Some(open) if open == c => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '/' && chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = None;
+                for c in chars.by_ref() {
+                    if previous == Some('*') && c == '/' {
+                        break;
+                    }
+                    previous = Some(c);
+                }
+                continue;
+            }
+            None => {}
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Parses the prelude of `@import`: `"file.css"` or `url(file.css)`,
+/// optionally followed by a media query list.
+fn parse_import(prelude: &str) -> Option<Import> {
+    let prelude = prelude.trim();
+    let (url, rest) = if let Some(rest) = prelude.strip_prefix("url(") {
+        let end = rest.find(')')?;
+        (rest[..end].trim().trim_matches(|c| c == '"' || c == '\''), &rest[end + 1..])
+    } else {
+        let quote = prelude.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let end = prelude[1..].find(quote)? + 1;
+        (&prelude[1..end], &prelude[end + 1..])
+    };
+    if url.is_empty() {
+        return None;
+    }
+    Some(Import { url: url.to_string(), media: parse_media_query_list(rest) })
+}
+
+/// Parses the prelude of `@media`, e.g. `screen and (min-width: 600px), print`.
+fn parse_media_query_list(prelude: &str) -> MediaQueryList {
+    let prelude = prelude.trim();
+    if prelude.is_empty() {
+        return MediaQueryList::default();
+    }
+    MediaQueryList {
+        queries: prelude
+            .split(',')
+            .map(|query| parse_media_query(query).unwrap_or_else(MediaQuery::never))
+            .collect(),
+    }
+}
+
+fn parse_media_query(query: &str) -> Option<MediaQuery> {
+    let query = query.trim().to_ascii_lowercase();
+    let mut media_query = MediaQuery {
+        negated: false,
+        media_type: MediaType::All,
+        features: Vec::new(),
+    };
+    let mut chars = query.chars().peekable();
+    // a feature is expected after `and` and at the start of a type-less query
+    let mut expect_feature = true;
+    let mut seen_type = false;
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.peek() {
+            None => break,
+            Some('(') => {
+                chars.next();
+                let mut depth = 1;
+                let mut feature = String::new();
+                for c in chars.by_ref() {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    feature.push(c);
+                }
+                if !expect_feature || depth != 0 {
+                    return None;
+                }
+                media_query.features.append(&mut parse_media_feature(&feature)?);
+                expect_feature = false;
+            }
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '(') {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "and" if !expect_feature => expect_feature = true,
+                    "not" if !seen_type && media_query.features.is_empty() => media_query.negated = true,
+                    "only" if !seen_type && media_query.features.is_empty() => {}
+                    _ if !seen_type && media_query.features.is_empty() => {
+                        media_query.media_type = match word.as_str() {
+                            "all" => MediaType::All,
+                            "screen" => MediaType::Screen,
+                            "print" => MediaType::Print,
+                            _ => MediaType::Other(word),
+                        };
+                        seen_type = true;
+                        expect_feature = false;
+                    }
+                    _ => return None,
+                }
+            }
+        }
+    }
+    // a dangling `and`, or `not`/`only` without a media type
+    if expect_feature && (!media_query.features.is_empty() || seen_type || media_query.negated) {
+        return None;
+    }
+    Some(media_query)
+}
+
+/// Parses what is inside the parentheses of a media feature. Range syntax
+/// such as `400px <= width < 700px` gives two features.
+fn parse_media_feature(feature: &str) -> Option<Vec<MediaFeature>> {
+    let feature = feature.trim();
+    if let Some((name, value)) = feature.split_once(':') {
+        let (name, value) = (name.trim(), value.trim());
+        let (comparison, name) = if let Some(name) = name.strip_prefix("min-") {
+            (Comparison::GreaterOrEqual, name)
+        } else if let Some(name) = name.strip_prefix("max-") {
+            (Comparison::LessOrEqual, name)
+        } else {
+            (Comparison::Equal, name)
+        };
+        let discrete = match (name, value) {
+            ("orientation", "portrait") => Some(MediaFeature::Orientation(Orientation::Portrait)),
+            ("orientation", "landscape") => Some(MediaFeature::Orientation(Orientation::Landscape)),
+            ("prefers-color-scheme", "light") => Some(MediaFeature::PrefersColorScheme(ColorScheme::Light)),
+            ("prefers-color-scheme", "dark") => Some(MediaFeature::PrefersColorScheme(ColorScheme::Dark)),
+            ("orientation" | "prefers-color-scheme", _) => return None,
+            _ => None,
+        };
+        if let Some(discrete) = discrete {
+            if comparison != Comparison::Equal {
+                return None;
+            }
+            return Some(vec![discrete]);
+        }
+        return Some(vec![match parse_range_feature(name) {
+            Some(range) => MediaFeature::Range(range, comparison, parse_media_value(range, value)?),
+            None => MediaFeature::Unknown(feature.to_string()),
+        }]);
+    }
+
+    // range syntax: split into operands and operators
+    let mut operands = vec![String::new()];
+    let mut comparisons = Vec::new();
+    let mut chars = feature.chars().peekable();
+    while let Some(c) = chars.next() {
+        let comparison = match c {
+            '<' if chars.next_if_eq(&'=').is_some() => Comparison::LessOrEqual,
+            '<' => Comparison::Less,
+            '>' if chars.next_if_eq(&'=').is_some() => Comparison::GreaterOrEqual,
+            '>' => Comparison::Greater,
+            '=' => Comparison::Equal,
+            c => {
+                operands.last_mut().unwrap().push(c);
+                continue;
+            }
+        };
+        comparisons.push(comparison);
+        operands.push(String::new());
+    }
+    let operands: Vec<&str> = operands.iter().map(|operand| operand.trim()).collect();
+    match (operands.as_slice(), comparisons.as_slice()) {
+        // a bare feature name is true when the value is not zero
+        ([name], []) => Some(vec![match parse_range_feature(name) {
+            Some(range) => MediaFeature::Range(range, Comparison::Greater, 0.0),
+            None => MediaFeature::Unknown(feature.to_string()),
+        }]),
+        ([left, right], [comparison]) => {
+            if let Some(range) = parse_range_feature(left) {
+                Some(vec![MediaFeature::Range(range, *comparison, parse_media_value(range, right)?)])
+            } else {
+                let range = parse_range_feature(right)?;
+                Some(vec![MediaFeature::Range(range, comparison.flip(), parse_media_value(range, left)?)])
+            }
+        }
+        ([low, name, high], [first, second]) => {
+            let range = parse_range_feature(name)?;
+            // both comparisons must point the same way
+            let is_less = |c: &Comparison| matches!(c, Comparison::Less | Comparison::LessOrEqual);
+            if is_less(first) != is_less(second) || *first == Comparison::Equal || *second == Comparison::Equal {
+                return None;
+            }
+            Some(vec![
+                MediaFeature::Range(range, first.flip(), parse_media_value(range, low)?),
+                MediaFeature::Range(range, *second, parse_media_value(range, high)?),
+            ])
+        }
+        _ => None,
+    }
+}
+
+fn parse_range_feature(name: &str) -> Option<RangeFeature> {
+    match name {
+        "width" => Some(RangeFeature::Width),
+        "height" => Some(RangeFeature::Height),
+        "aspect-ratio" => Some(RangeFeature::AspectRatio),
+        "resolution" => Some(RangeFeature::Resolution),
+        _ => None,
+    }
+}
+
+/// Value of a range feature: px for sizes, dppx for resolution, a plain
+/// number for aspect ratios.
+fn parse_media_value(feature: RangeFeature, value: &str) -> Option<f32> {
+    match feature {
+        RangeFeature::Width | RangeFeature::Height => {
+            // relative units in media queries use the initial font size
+            let context = LengthContext {
+                font_size: DEFAULT_FONT_SIZE,
+                root_font_size: DEFAULT_FONT_SIZE,
+                viewport: Viewport::default(),
+            };
+            match CssParser::parse_length(value.to_string())? {
+                Length::Percent(_) => None,
+                length => length.to_px(&context),
+            }
+        }
+        RangeFeature::AspectRatio => match value.split_once('/') {
+            Some((width, height)) => {
+                let (width, height) = (parse_number(width.trim())?, parse_number(height.trim())?);
+                if height == 0.0 { None } else { Some(width / height) }
+            }
+            None => parse_number(value),
+        },
+        RangeFeature::Resolution => {
+            let (number, unit) = split_dimension(value)?;
+            match unit {
+                "dppx" | "x" => Some(number),
+                "dpi" => Some(number / 96.0),
+                "dpcm" => Some(number * 2.54 / 96.0),
+                _ => None,
+            }
+        }
+    }
+}
+
+const MARGIN: [PropertyName; 4] = [
+    PropertyName::MarginTop, PropertyName::MarginRight, PropertyName::MarginBottom, PropertyName::MarginLeft,
+];
+const PADDING: [PropertyName; 4] = [
+    PropertyName::PaddingTop, PropertyName::PaddingRight, PropertyName::PaddingBottom, PropertyName::PaddingLeft,
+];
+const BORDER_WIDTH: [PropertyName; 4] = [
+    PropertyName::BorderTopWidth, PropertyName::BorderRightWidth, PropertyName::BorderBottomWidth, PropertyName::BorderLeftWidth,
+];
+const BORDER_STYLE: [PropertyName; 4] = [
+    PropertyName::BorderTopStyle, PropertyName::BorderRightStyle, PropertyName::BorderBottomStyle, PropertyName::BorderLeftStyle,
+];
+const BORDER_COLOR: [PropertyName; 4] = [
+    PropertyName::BorderTopColor, PropertyName::BorderRightColor, PropertyName::BorderBottomColor, PropertyName::BorderLeftColor,
+];
+/// `grid-area: row-start / column-start / row-end / column-end`
+const GRID_AREA: [PropertyName; 4] = [
+    PropertyName::GridRowStart, PropertyName::GridColumnStart, PropertyName::GridRowEnd, PropertyName::GridColumnEnd,
+];
+const FLEX_DIRECTION: [&str; 4] = ["row", "row-reverse", "column", "column-reverse"];
+const FLEX_WRAP: [&str; 3] = ["nowrap", "wrap", "wrap-reverse"];
+/// `justify-content` and `align-content`
+const CONTENT_ALIGNMENT: [&str; 12] = [
+    "normal", "stretch", "start", "end", "flex-start", "flex-end", "center", "left", "right",
+    "space-between", "space-around", "space-evenly",
+];
+/// `align-items` and `align-self`
+const ITEM_ALIGNMENT: [&str; 10] = [
+    "normal", "stretch", "start", "end", "self-start", "self-end", "flex-start", "flex-end", "center", "baseline",
+];
+
+/// Splits a value into its whitespace separated components, keeping
+/// parenthesized groups such as `rgb(1, 2, 3)` together.
+fn split_values(value: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    for c in value.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if c.is_whitespace() && depth <= 0 {
+            if !current.is_empty() {
+                values.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        values.push(current);
+    }
+    values
+}
+
+/// `#rgb`, `#rgba`, `#rrggbb` and `#rrggbbaa`.
+fn parse_hex_color(hex: &str) -> Option<Color> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap() * 17;
+    let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    match hex.len() {
+        3 => Some(Color::Rgb(digit(0), digit(1), digit(2))),
+        4 => Some(Color::Rgba(digit(0), digit(1), digit(2), digit(3))),
+        6 => Some(Color::Hex(u32::from_str_radix(hex, 16).unwrap())),
+        8 => Some(Color::Rgba(byte(0), byte(2), byte(4), byte(6))),
+        _ => None,
+    }
+}
+
+/// Splits a track list on whitespace outside `()` and `[]`.
+fn split_track_list(value: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let (mut depth, mut start) = (0, None);
+    for (index, c) in value.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            _ if c.is_whitespace() && depth == 0 => {
+                if let Some(start) = start.take() {
+                    tokens.push(&value[start..index]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(index);
+    }
+    if let Some(start) = start {
+        tokens.push(&value[start..]);
+    }
+    tokens
+}
+
+/// Compound selector: optional tag or `*`, then any number of `#id` and
+/// `.class`. Combinators, pseudo-classes, pseudo-elements and attribute
+/// selectors aren't supported and make the selector invalid, rather than
+/// matching whatever the part before them matches.
+fn parse_compound_selector(text: &str) -> Option<Selector> {
+    let mut selector = Selector::default();
+    let name_end = |rest: &str| rest.find(|c| !is_selector_name(c)).unwrap_or(rest.len());
+    let mut rest = match text.strip_prefix('*') {
+        Some(rest) => rest,
+        None if text.is_empty() => return None,
+        None => {
+            let end = name_end(text);
+            if end > 0 {
+                if !is_identifier(&text[..end]) {
+                    return None;
+                }
+                selector.tag_name = Some(text[..end].to_string());
+            }
+            &text[end..]
+        }
+    };
+    while let Some(prefix) = rest.chars().next() {
+        if prefix != '#' && prefix != '.' {
+            return None;
+        }
+        let end = 1 + name_end(&rest[1..]);
+        let name = &rest[1..end];
+        if !is_identifier(name) {
+            return None;
+        }
+        if prefix == '#' {
+            selector.id = Some(name.to_string());
+        } else {
+            selector.classes.push(name.to_string());
+        }
+        rest = &rest[end..];
+    }
+    Some(selector)
+}
+
+fn is_identifier(value: &str) -> bool {
+    value.chars().next().is_some_and(|c| is_valid_start_ident(c) || c == '-') && value.chars().all(is_valid_ident)
+}
+
+/// Splits the arguments of a color function into three channels and an
+/// optional alpha. Accepts both `1, 2, 3, 0.5` and `1 2 3 / 0.5`.
+fn split_color_arguments(arguments: &str) -> Option<(Vec<String>, Option<String>)> {
+    let (mut channels, alpha) = if arguments.contains(',') {
+        let mut parts: Vec<String> = arguments.split(',').map(|part| part.trim().to_string()).collect();
+        let alpha = if parts.len() == 4 { parts.pop() } else { None };
+        (parts, alpha)
+    } else {
+        let (channels, alpha) = match arguments.split_once('/') {
+            Some((channels, alpha)) => (channels, Some(alpha.trim().to_string())),
+            None => (arguments, None),
+        };
+        (split_values(channels), alpha)
+    };
+    if channels.len() != 3 || channels.iter().any(|channel| channel.is_empty()) {
+        return None;
+    }
+    // `none` is a missing component, which is zero for our purposes
+    for channel in channels.iter_mut() {
+        if channel == "none" {
+            *channel = "0".to_string();
+        }
+    }
+    Some((channels, alpha))
+}
+
+/// Splits a dimension such as `12.5em` into its number and unit.
+fn split_dimension(value: &str) -> Option<(f32, &str)> {
+    let bytes = value.as_bytes();
+    let digits = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        i
+    };
+    let mut end = 0;
+    if end < bytes.len() && (bytes[end] == b'+' || bytes[end] == b'-') {
+        end += 1;
+    }
+    end = digits(end);
+    if end < bytes.len() && bytes[end] == b'.' {
+        end = digits(end + 1);
+    }
+    // an exponent needs digits, otherwise the `e` belongs to `em` or `ex`
+    if end < bytes.len() && (bytes[end] == b'e' || bytes[end] == b'E') {
+        let mut exponent = end + 1;
+        if exponent < bytes.len() && (bytes[exponent] == b'+' || bytes[exponent] == b'-') {
+            exponent += 1;
+        }
+        if exponent < bytes.len() && bytes[exponent].is_ascii_digit() {
+            end = digits(exponent);
+        }
+    }
+    let number = parse_number(&value[..end])?;
+    Some((number, &value[end..]))
+}
+
+fn parse_number(value: &str) -> Option<f32> {
+    value.parse::<f32>().ok().filter(|number| number.is_finite())
+}
+
+fn parse_rgb_channel(value: &str) -> Option<u8> {
+    match value.strip_suffix('%') {
+        Some(percent) => Some(to_channel(parse_number(percent)? / 100.0)),
+        None => Some(parse_number(value)?.round().clamp(0.0, 255.0) as u8),
+    }
+}
+
+/// Percentage (or bare number in percent units) as a fraction in 0..=1.
+fn parse_fraction(value: &str) -> Option<f32> {
+    let percent = parse_number(value.strip_suffix('%').unwrap_or(value))?;
+    Some((percent / 100.0).clamp(0.0, 1.0))
+}
+
+fn parse_alpha(value: &str) -> Option<u8> {
+    if value == "none" {
+        return Some(0);
+    }
+    let alpha = match value.strip_suffix('%') {
+        Some(percent) => parse_number(percent)? / 100.0,
+        None => parse_number(value)?,
+    };
+    Some(to_channel(alpha))
+}
+
+/// Hue in degrees.
+fn parse_hue(value: &str) -> Option<f32> {
+    let units: [(&str, f32); 4] = [
+        ("deg", 1.0),
+        ("grad", 0.9),
+        ("rad", 180.0 / std::f32::consts::PI),
+        ("turn", 360.0),
+    ];
+    for (unit, factor) in units {
+        if let Some(number) = value.strip_suffix(unit) {
+            return Some(parse_number(number)? * factor);
+        }
+    }
+    parse_number(value)
+}
+
+fn to_channel(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (f32, f32, f32) {
+    let hue = hue.rem_euclid(360.0);
+    let a = saturation * lightness.min(1.0 - lightness);
+    let f = |n: f32| {
+        let k = (n + hue / 30.0) % 12.0;
+        lightness - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+    };
+    (f(0.0), f(8.0), f(4.0))
+}
+
+fn hwb_to_rgb(hue: f32, whiteness: f32, blackness: f32) -> (f32, f32, f32) {
+    if whiteness + blackness >= 1.0 {
+        let gray = whiteness / (whiteness + blackness);
+        return (gray, gray, gray);
+    }
+    let (r, g, b) = hsl_to_rgb(hue, 1.0, 0.5);
+    let scale = |channel: f32| channel * (1.0 - whiteness - blackness) + whiteness;
+    (scale(r), scale(g), scale(b))
+}
+
+fn starts_like_length(value: &str) -> bool {
+    value.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-' || c == '+')
+}
+
 fn is_valid_ident(c: char) -> bool {
     is_valid_start_ident(c) || c.is_digit(10) || c == '-'
 }
@@ -208,7 +1767,7 @@ fn is_valid_start_ident(c: char) -> bool {
 }
 
 fn is_closing_bracket_or_letter(c: char) -> bool {
-    !(is_closing_bracket(c) || is_letter(c))
+    !(is_closing_bracket(c) || is_letter(c) || c == '-')
 }
 
 fn is_closing_bracket(c: char) -> bool {
@@ -220,7 +1779,7 @@ fn is_letter(c: char) -> bool {
 }
 
 fn is_not_property_identifier(c: char) -> bool {
-    !is_letter(c)
+    !(is_letter(c) || c == '-')
 }
 
 fn is_not_property_identifier_or_closing_bracket(c: char) -> bool {
@@ -243,8 +1802,8 @@ fn is_non_ascii(c: char) -> bool {
     c >= '\u{0080}'
 }
 
-fn is_not_valid_selector(c: char) -> bool {
-    !is_letter(c) && c != '.' && c != '#'
+fn is_selector_name(c: char) -> bool {
+    is_valid_ident(c)
 }
 
 // for test
@@ -255,7 +1814,7 @@ fn assert_stylesheet(stylesheet: &Stylesheet) {
     //selector
     assert_eq!(stylesheet.rules[0].selector.tag_name, Some("body".to_string()));
     assert_eq!(stylesheet.rules[0].selector.id, None);
-    assert_eq!(stylesheet.rules[0].selector.class, None);
+    assert!(stylesheet.rules[0].selector.classes.is_empty());
     //properties
     // assert_eq!(stylesheet.rules[0].properties[0].name, PropertyName::Color);
     // assert_eq!(stylesheet.rules[0].properties[0].value, PropertyValue::Color(Color::Hex(0x772233)));
@@ -264,7 +1823,7 @@ fn assert_stylesheet(stylesheet: &Stylesheet) {
     // .orange
     // selector
     assert_eq!(stylesheet.rules[1].selector.tag_name, None);
-    assert_eq!(stylesheet.rules[1].selector.class, Some("orange".to_string()));
+    assert_eq!(stylesheet.rules[1].selector.classes, vec!["orange".to_string()]);
     assert_eq!(stylesheet.rules[1].selector.id, None);
     // properties
     // assert_eq!(stylesheet.rules[1].properties[0].name, PropertyName::BackgroundColor);
@@ -272,7 +1831,7 @@ fn assert_stylesheet(stylesheet: &Stylesheet) {
     // #blue
     // selector
     assert_eq!(stylesheet.rules[2].selector.tag_name, None);
-    assert_eq!(stylesheet.rules[2].selector.class, None);
+    assert!(stylesheet.rules[2].selector.classes.is_empty());
     assert_eq!(stylesheet.rules[2].selector.id, Some("blue".to_string()));
     // properties
     // assert_eq!(stylesheet.rules[2].properties[0].name, PropertyName::BackgroundColor);
@@ -321,16 +1880,22 @@ body {
 fn test_parse_rule() {
     // 1 property
     let mut parser = CssParser::new("  body {   color: red; }");
-    let rule = parser.parse_rule();
+    let rule = parser.parse_rule().remove(0);
     assert_eq!(rule.selector.tag_name, Some("body".to_string()));
     assert_eq!(rule.properties.len(), 1);
     // assert_eq!(rule.properties[0].name, PropertyName::Color);
     // assert_eq!(rule.properties[0].value, PropertyValue::Color(Color::Named("red".to_string())));
     // 2 properties
     let mut parser = CssParser::new("  body {   color: red;  \n margin: 10px;  }");
-    let rule = parser.parse_rule();
+    let rule = parser.parse_rule().remove(0);
     assert_eq!(rule.selector.tag_name, Some("body".to_string()));
-    assert_eq!(rule.properties.len(), 2);
+    // color + four margin longhands
+    assert_eq!(rule.properties.len(), 5);
+    // a longhand after the shorthand overrides one side only
+    let mut parser = CssParser::new("div { margin: 10px; margin-left: 0px; }");
+    let rule = parser.parse_rule().remove(0);
+    assert_eq!(rule.properties[&PropertyName::MarginTop], PropertyValue::Length(Length::Px(10.0)));
+    assert_eq!(rule.properties[&PropertyName::MarginLeft], PropertyValue::Length(Length::Px(0.0)));
     // assert_eq!(rule.properties[1].name, PropertyName::Margin);
     // assert_eq!(rule.properties[1].value, PropertyValue::Length(Length::Px(10)));
 }
@@ -339,22 +1904,62 @@ fn test_parse_rule() {
 fn test_parse_selector() {
     // test tag name
     let mut parser = CssParser::new("  body  { color: red; }");
-    let selector = parser.parse_selector();
+    let selector = parser.parse_selectors().unwrap().remove(0);
     assert_eq!(selector.tag_name, Some("body".to_string()));
     assert_eq!(selector.id, None);
-    assert_eq!(selector.class, None);
+    assert!(selector.classes.is_empty());
     // test id
     let mut parser = CssParser::new("  #id    { color: red; }");
-    let selector = parser.parse_selector();
+    let selector = parser.parse_selectors().unwrap().remove(0);
     assert_eq!(selector.tag_name, None);
     assert_eq!(selector.id, Some("id".to_string()));
-    assert_eq!(selector.class, None);
+    assert!(selector.classes.is_empty());
     // test class
     let mut parser = CssParser::new("  .class   { color: red; }");
-    let selector = parser.parse_selector();
+    let selector = parser.parse_selectors().unwrap().remove(0);
     assert_eq!(selector.tag_name, None);
     assert_eq!(selector.id, None);
-    assert_eq!(selector.class, Some("class".to_string()));
+    assert_eq!(selector.classes, vec!["class".to_string()]);
+    // test compound
+    let mut parser = CssParser::new("div#main.card.wide { color: red; }");
+    let selector = parser.parse_selectors().unwrap().remove(0);
+    assert_eq!(selector.tag_name, Some("div".to_string()));
+    assert_eq!(selector.id, Some("main".to_string()));
+    assert_eq!(selector.classes, vec!["card".to_string(), "wide".to_string()]);
+    assert_eq!(selector.specificity(), (1, 2, 1));
+    // test universal
+    let mut parser = CssParser::new("* { color: red; }");
+    let selector = parser.parse_selectors().unwrap().remove(0);
+    assert_eq!(selector, Selector::default());
+    assert_eq!(selector.specificity(), (0, 0, 0));
+    // selector list
+    let mut parser = CssParser::new("h1,  h2.title\n{ color: red; }");
+    let selectors = parser.parse_selectors().unwrap();
+    assert_eq!(selectors, vec![
+        Selector::new(Some("h1".to_string()), None, Vec::new()),
+        Selector::new(Some("h2".to_string()), None, vec!["title".to_string()]),
+    ]);
+    // unsupported selectors, and a list with one of them, are invalid as a whole
+    for css in ["a:hover", "div p", "ul > li", "div::before", "input[type]", "h1, h2 p", "h1,", ".1a", "#", ""] {
+        let mut parser = CssParser::new(&format!("{} {{ color: red; }}", css));
+        assert_eq!(parser.parse_selectors(), None, "{:?}", css);
+    }
+}
+
+#[test]
+fn test_parse_important() {
+    let mut parser = CssParser::new("p { color: red !important; margin: 10px ! IMPORTANT; padding: 5px; }");
+    let rule = parser.parse_rule().remove(0);
+    assert!(rule.is_important(&PropertyName::Color));
+    assert!(rule.is_important(&PropertyName::MarginTop));
+    assert!(rule.is_important(&PropertyName::MarginLeft));
+    assert!(!rule.is_important(&PropertyName::PaddingTop));
+    assert_eq!(rule.properties[&PropertyName::Color], PropertyValue::Color(Color::Named("red".to_string())));
+    assert_eq!(rule.properties[&PropertyName::MarginTop], PropertyValue::Length(Length::Px(10.0)));
+    // a later normal declaration does not override an important one
+    let mut parser = CssParser::new("p { color: red !important; color: blue; }");
+    let rule = parser.parse_rule().remove(0);
+    assert_eq!(rule.properties[&PropertyName::Color], PropertyValue::Color(Color::Named("red".to_string())));
 }
 
 #[test]
@@ -370,34 +1975,138 @@ fn test_parse_property() {
 fn test_process_property_members_color() {
     let color_key = String::from("color");
     let color_value = String::from("red");
-    let (name, value) =
+    let declarations =
         CssParser::process_property_members(color_key, color_value);
-    assert_eq!(name, PropertyName::Color);
-    assert_eq!(value, PropertyValue::Color(Color::Named("red".to_string())));
+    assert_eq!(declarations, vec![(PropertyName::Color, PropertyValue::Color(Color::Named("red".to_string())))]);
+}
+
+#[cfg(test)]
+fn px(px: f32) -> PropertyValue {
+    PropertyValue::Length(Length::Px(px))
 }
 
 #[test]
 fn test_process_property_members_margin() {
     let margin_key = String::from("margin");
     let margin_value = String::from("10px");
-    let (name, value) =
+    let declarations =
         CssParser::process_property_members(margin_key, margin_value);
-    assert_eq!(name, PropertyName::Margin);
-    assert_eq!(value, PropertyValue::Length(Length::Px(10)));
+    assert_eq!(declarations, vec![
+        (PropertyName::MarginTop, px(10.0)),
+        (PropertyName::MarginRight, px(10.0)),
+        (PropertyName::MarginBottom, px(10.0)),
+        (PropertyName::MarginLeft, px(10.0)),
+    ]);
+    // two values: vertical horizontal
+    let declarations = CssParser::process_property_members("margin".to_string(), "10px 20px".to_string());
+    assert_eq!(declarations, vec![
+        (PropertyName::MarginTop, px(10.0)),
+        (PropertyName::MarginRight, px(20.0)),
+        (PropertyName::MarginBottom, px(10.0)),
+        (PropertyName::MarginLeft, px(20.0)),
+    ]);
+    // three values: top horizontal bottom
+    let declarations = CssParser::process_property_members("padding".to_string(), "1px 2px 3px".to_string());
+    assert_eq!(declarations, vec![
+        (PropertyName::PaddingTop, px(1.0)),
+        (PropertyName::PaddingRight, px(2.0)),
+        (PropertyName::PaddingBottom, px(3.0)),
+        (PropertyName::PaddingLeft, px(2.0)),
+    ]);
+    // four values: clockwise from the top, auto allowed for margins
+    let declarations = CssParser::process_property_members("margin".to_string(), "1px auto 3px 4px".to_string());
+    assert_eq!(declarations, vec![
+        (PropertyName::MarginTop, px(1.0)),
+        (PropertyName::MarginRight, PropertyValue::Keyword("auto".to_string())),
+        (PropertyName::MarginBottom, px(3.0)),
+        (PropertyName::MarginLeft, px(4.0)),
+    ]);
+    // five values is invalid and the declaration is dropped
+    assert!(CssParser::process_property_members("margin".to_string(), "1px 2px 3px 4px 5px".to_string()).is_empty());
+    // margins can be negative, paddings, border widths and font sizes can't
+    assert_eq!(CssParser::process_property_members("margin-left".to_string(), "-1px".to_string()),
+               vec![(PropertyName::MarginLeft, px(-1.0))]);
+    for (name, value) in [("padding", "1px -2px"), ("padding-left", "-1px"), ("padding-top", "auto"),
+                          ("border-width", "-1px"), ("border", "-1px solid"), ("font-size", "-10px"),
+                          ("font-size", "-50%"), ("font", "-12px serif")] {
+        assert!(CssParser::process_property_members(name.to_string(), value.to_string()).is_empty(), "{}: {}", name, value);
+    }
+}
+
+#[test]
+fn test_process_property_members_border() {
+    let declarations = CssParser::process_property_members("border".to_string(), "1px solid #ff0000".to_string());
+    assert_eq!(declarations.len(), 12);
+    assert!(declarations.contains(&(PropertyName::BorderLeftWidth, px(1.0))));
+    assert!(declarations.contains(&(PropertyName::BorderTopStyle, PropertyValue::BorderStyle(BorderStyle::Solid))));
+    assert!(declarations.contains(&(PropertyName::BorderBottomColor, PropertyValue::Color(Color::Hex(0xff0000)))));
+    // any order, missing components get their initial values
+    let declarations = CssParser::process_property_members("border-top".to_string(), "dashed thick".to_string());
+    assert_eq!(declarations, vec![
+        (PropertyName::BorderTopWidth, px(5.0)),
+        (PropertyName::BorderTopStyle, PropertyValue::BorderStyle(BorderStyle::Dashed)),
+        (PropertyName::BorderTopColor, PropertyValue::Color(Color::Current)),
+    ]);
+    let declarations = CssParser::process_property_members("border-style".to_string(), "solid dotted".to_string());
+    assert_eq!(declarations, vec![
+        (PropertyName::BorderTopStyle, PropertyValue::BorderStyle(BorderStyle::Solid)),
+        (PropertyName::BorderRightStyle, PropertyValue::BorderStyle(BorderStyle::Dotted)),
+        (PropertyName::BorderBottomStyle, PropertyValue::BorderStyle(BorderStyle::Solid)),
+        (PropertyName::BorderLeftStyle, PropertyValue::BorderStyle(BorderStyle::Dotted)),
+    ]);
+    let declarations = CssParser::process_property_members("border-width".to_string(), "thin 2px".to_string());
+    assert_eq!(declarations[1], (PropertyName::BorderRightWidth, px(2.0)));
+    assert_eq!(declarations[2], (PropertyName::BorderBottomWidth, px(1.0)));
+    let declarations = CssParser::process_property_members("border-color".to_string(), "red green blue".to_string());
+    assert_eq!(declarations[3], (PropertyName::BorderLeftColor, PropertyValue::Color(Color::Named("green".to_string()))));
+}
+
+#[test]
+fn test_process_property_members_background() {
+    let declarations = CssParser::process_property_members("background".to_string(), "url(a.png) no-repeat #00ff00".to_string());
+    assert_eq!(declarations, vec![(PropertyName::BackgroundColor, PropertyValue::Color(Color::Hex(0x00ff00)))]);
+    let declarations = CssParser::process_property_members("background".to_string(), "none".to_string());
+    assert_eq!(declarations, vec![(PropertyName::BackgroundColor, PropertyValue::Color(Color::Named("transparent".to_string())))]);
+    let declarations = CssParser::process_property_members("background".to_string(), "rgb(0 0 0 / 10%) url(a.png)".to_string());
+    assert_eq!(declarations, vec![(PropertyName::BackgroundColor, PropertyValue::Color(Color::Rgba(0, 0, 0, 26)))]);
+}
+
+#[test]
+fn test_process_property_members_font() {
+    let declarations = CssParser::process_property_members(
+        "font".to_string(), "italic bold 16px/1.5 \"Helvetica Neue\", Arial, sans-serif".to_string());
+    assert_eq!(declarations, vec![
+        (PropertyName::FontStyle, PropertyValue::Keyword("italic".to_string())),
+        (PropertyName::FontWeight, PropertyValue::Number(700.0)),
+        (PropertyName::FontSize, px(16.0)),
+        (PropertyName::LineHeight, PropertyValue::Number(1.5)),
+        (PropertyName::FontFamily, PropertyValue::FontFamily(vec![
+            "Helvetica Neue".to_string(), "Arial".to_string(), "sans-serif".to_string(),
+        ])),
+    ]);
+    // only size and family are required, the rest is reset
+    let declarations = CssParser::process_property_members("font".to_string(), "12px serif".to_string());
+    assert_eq!(declarations[0], (PropertyName::FontStyle, PropertyValue::Keyword("normal".to_string())));
+    assert_eq!(declarations[1], (PropertyName::FontWeight, PropertyValue::Number(400.0)));
+    assert_eq!(declarations[3], (PropertyName::LineHeight, PropertyValue::Keyword("normal".to_string())));
+    let declarations = CssParser::process_property_members("font".to_string(), "large / 20px serif".to_string());
+    assert_eq!(declarations[2], (PropertyName::FontSize, PropertyValue::Keyword("large".to_string())));
+    assert_eq!(declarations[3], (PropertyName::LineHeight, px(20.0)));
+    // a family is mandatory
+    assert!(CssParser::process_property_members("font".to_string(), "bold 12px".to_string()).is_empty());
 }
 
 #[test]
 fn test_process_property_members_background_color() {
     let background_color_key = String::from("background-color");
     let background_color_value = String::from("red");
-    let (name, value) = CssParser::process_property_members(
+    let declarations = CssParser::process_property_members(
         background_color_key,
         background_color_value,
     );
-    assert_eq!(name, PropertyName::BackgroundColor);
     assert_eq!(
-        value,
-        PropertyValue::Color(Color::Named("red".to_string()))
+        declarations,
+        vec![(PropertyName::BackgroundColor, PropertyValue::Color(Color::Named("red".to_string())))]
     );
 }
 
@@ -407,13 +2116,65 @@ fn test_parse_color() {
     // test named color
     let value = String::from("red");
     let color = CssParser::parse_color(value);
-    assert_eq!(color, Color::Named("red".to_string()));
+    assert_eq!(color, Some(Color::Named("red".to_string())));
     // test hex color
     let value = String::from("#ff0000");
     let color = CssParser::parse_color(value);
-    assert_eq!(color, Color::Hex(0xff0000));
+    assert_eq!(color, Some(Color::Hex(0xff0000)));
     // test rgb color
-    //todo
+    let value = String::from("rgb(255, 128, 0)");
+    let color = CssParser::parse_color(value);
+    assert_eq!(color, Some(Color::Rgba(255, 128, 0, 255)));
+}
+
+#[cfg(test)]
+fn rgba(value: &str) -> Option<(u8, u8, u8, u8)> {
+    CssParser::parse_color(value.to_string()).map(|color| color.get_rgba())
+}
+
+#[test]
+fn test_parse_color_hex() {
+    assert_eq!(rgba("#f00"), Some((255, 0, 0, 255)));
+    assert_eq!(rgba("#f008"), Some((255, 0, 0, 136)));
+    assert_eq!(rgba("#FF8000"), Some((255, 128, 0, 255)));
+    assert_eq!(rgba("#ff800080"), Some((255, 128, 0, 128)));
+    assert_eq!(rgba("#ff80"), Some((255, 255, 136, 0)));
+    assert_eq!(rgba("#ff80000"), None);
+    assert_eq!(rgba("#ggg"), None);
+}
+
+#[test]
+fn test_parse_color_functions() {
+    // legacy and modern rgb syntax, percentages and alpha
+    assert_eq!(rgba("rgba(255, 0, 0, 0.5)"), Some((255, 0, 0, 128)));
+    assert_eq!(rgba("rgb(255 0 0 / 50%)"), Some((255, 0, 0, 128)));
+    assert_eq!(rgba("rgb(100%, 50%, 0%)"), Some((255, 128, 0, 255)));
+    assert_eq!(rgba("RGB(300, -20, 0)"), Some((255, 0, 0, 255)));
+    assert_eq!(rgba("rgb(none 255 0)"), Some((0, 255, 0, 255)));
+    // hsl, values checked against what browsers compute
+    assert_eq!(rgba("hsl(120, 100%, 50%)"), Some((0, 255, 0, 255)));
+    assert_eq!(rgba("hsl(0.5turn 100% 25%)"), Some((0, 128, 128, 255)));
+    assert_eq!(rgba("hsla(240deg, 100%, 50%, 0.25)"), Some((0, 0, 255, 64)));
+    assert_eq!(rgba("hsl(30 50% 60% / 1)"), Some((204, 153, 102, 255)));
+    // hwb
+    assert_eq!(rgba("hwb(0 0% 0%)"), Some((255, 0, 0, 255)));
+    assert_eq!(rgba("hwb(120 20% 30%)"), Some((51, 179, 51, 255)));
+    assert_eq!(rgba("hwb(0 60% 60%)"), Some((128, 128, 128, 255)));
+    // malformed
+    assert_eq!(rgba("rgb(1, 2)"), None);
+    assert_eq!(rgba("rgb(a, b, c)"), None);
+    assert_eq!(rgba("lab(50% 40 59)"), None);
+}
+
+#[test]
+fn test_parse_color_keywords() {
+    assert_eq!(rgba("rebeccapurple"), Some((102, 51, 153, 255)));
+    assert_eq!(rgba("LightGoldenRodYellow"), Some((250, 250, 210, 255)));
+    assert_eq!(rgba("transparent"), Some((0, 0, 0, 0)));
+    assert_eq!(CssParser::parse_color("currentColor".to_string()), Some(Color::Current));
+    assert_eq!(rgba("notacolor"), None);
+    assert_eq!(Color::Rgba(255, 0, 0, 128).get_rgb_str(), "rgba(255, 0, 0, 0.5)");
+    assert_eq!(Color::Named("orange".to_string()).get_rgb_str(), "rgb(255, 165, 0)");
 }
 
 #[test]
@@ -421,10 +2182,293 @@ fn test_parse_length() {
     // test px
     let value = String::from("10px");
     let length = CssParser::parse_length(value);
-    assert_eq!(length, Length::Px(10));
+    assert_eq!(length, Some(Length::Px(10.0)));
     // test %
     let value = String::from("10%");
     let length = CssParser::parse_length(value);
-    assert_eq!(length, Length::Percent(10));
+    assert_eq!(length, Some(Length::Percent(10.0)));
+    // fractional values and every unit
+    let length = |value: &str| CssParser::parse_length(value.to_string());
+    assert_eq!(length("1.5em"), Some(Length::Em(1.5)));
+    assert_eq!(length("-.5rem"), Some(Length::Rem(-0.5)));
+    assert_eq!(length("2ex"), Some(Length::Ex(2.0)));
+    assert_eq!(length("3ch"), Some(Length::Ch(3.0)));
+    assert_eq!(length("50vw"), Some(Length::Vw(50.0)));
+    assert_eq!(length("50VH"), Some(Length::Vh(50.0)));
+    assert_eq!(length("10vmin"), Some(Length::Vmin(10.0)));
+    assert_eq!(length("10vmax"), Some(Length::Vmax(10.0)));
+    assert_eq!(length("12pt"), Some(Length::Pt(12.0)));
+    assert_eq!(length("1pc"), Some(Length::Pc(1.0)));
+    assert_eq!(length("1in"), Some(Length::In(1.0)));
+    assert_eq!(length("2.54cm"), Some(Length::Cm(2.54)));
+    assert_eq!(length("10mm"), Some(Length::Mm(10.0)));
+    assert_eq!(length("1e1px"), Some(Length::Px(10.0)));
+    assert_eq!(length("150%"), Some(Length::Percent(150.0)));
+    assert_eq!(length("0"), Some(Length::Px(0.0)));
+    // unknown units and unitless non-zero values are invalid
+    assert_eq!(length("10qq"), None);
+    assert_eq!(length("10"), None);
+    assert_eq!(length("px"), None);
+
+}
+#[cfg(test)]
+fn calc(value: &str) -> Option<Length> {
+    CssParser::parse_length(value.to_string())
+}
+
+#[test]
+fn test_parse_calc_simplifies() {
+    // folds to a single value at parse time
+    assert_eq!(calc("calc(10px + 5px)"), Some(Length::Px(15.0)));
+    assert_eq!(calc("calc(1in - 6px * 2)"), Some(Length::Px(84.0)));
+    assert_eq!(calc("calc((2em + 1em) / 3)"), Some(Length::Em(1.0)));
+    assert_eq!(calc("min(10px, 2px, 30px)"), Some(Length::Px(2.0)));
+    assert_eq!(calc("max(1em, 2em)"), Some(Length::Em(2.0)));
+    assert_eq!(calc("clamp(10px, 50px, 20px)"), Some(Length::Px(20.0)));
+    assert_eq!(calc("CALC( 2 * 25% )"), Some(Length::Percent(50.0)));
+    // mixed units stay an expression
+    assert_eq!(calc("calc(100% - 2em)"), Some(Length::Calc(Box::new(Calc::Sum(vec![
+        Calc::Value(Length::Percent(100.0)),
+        Calc::Value(Length::Em(-2.0)),
+    ])))));
+    assert_eq!(calc("min(50vw, 400px)"), Some(Length::Calc(Box::new(Calc::Min(vec![
+        Calc::Value(Length::Vw(50.0)),
+        Calc::Value(Length::Px(400.0)),
+    ])))));
+    assert_eq!(calc("calc(100% - 2em)").unwrap().to_str(), "calc(100% - 2em)");
+    assert_eq!(calc("min(50vw, 400px)").unwrap().to_str(), "min(50vw, 400px)");
+}
+
+#[test]
+fn test_parse_calc_invalid() {
+    // + and - need whitespace around them
+    assert_eq!(calc("calc(100%-2em)"), None);
+    assert_eq!(calc("calc(1px -2px)"), None);
+    // lengths can't be multiplied together or be divisors, numbers are not lengths
+    assert_eq!(calc("calc(1px * 2px)"), None);
+    assert_eq!(calc("calc(10px / 2px)"), None);
+    assert_eq!(calc("calc(10px / 0)"), None);
+    assert_eq!(calc("calc(1px + 2)"), None);
+    assert_eq!(calc("calc(5)"), None);
+    assert_eq!(calc("clamp(1px, 2px)"), None);
+    assert_eq!(calc("calc(1px"), None);
+    assert_eq!(calc("foo(1px)"), None);
+}
+
+#[test]
+fn test_calc_resolves() {
+    let context = LengthContext {
+        font_size: 10.0,
+        root_font_size: 16.0,
+        viewport: Viewport { width: 1000.0, height: 500.0, ..Viewport::default() },
+    };
+    let length = calc("calc(100% - 2em)").unwrap().compute(&context);
+    assert_eq!(length.resolve(300.0), 280.0);
+    let length = calc("min(50vw, 400px)").unwrap().compute(&context);
+    assert_eq!(length, Length::Px(400.0));
+    let length = calc("clamp(1rem, 10%, 100px)").unwrap().compute(&context);
+    assert_eq!(length.resolve(100.0), 16.0);
+    assert_eq!(length.resolve(500.0), 50.0);
+    assert_eq!(length.resolve(5000.0), 100.0);
+    // a negative factor swaps min and max
+    let length = calc("calc(-1 * min(10%, 20px))").unwrap().compute(&context);
+    assert_eq!(length.resolve(100.0), -10.0);
+    assert_eq!(length.resolve(1000.0), -20.0);
+}
+
+#[cfg(test)]
+fn media_matches(prelude: &str, viewport: &Viewport) -> bool {
+    parse_media_query_list(prelude).matches(viewport)
+}
+
+#[test]
+fn test_parse_media_rules() {
+    let css = r#"
+body { color: red; }
+@media screen and (max-width: 600px) {
+    body { color: blue }
+    @media (orientation: landscape) {
+        .wide { margin: 0; }
+    }
+}
+@font-face { font-family: x; src: url(x.woff); }
+@charset "utf-8";
+@media print { body { color: black; } }
+#after { color: green; }
+"#;
+    let stylesheet = CssParser::new(css).parse_stylesheet();
+    assert_eq!(stylesheet.rules.len(), 5);
+    assert!(stylesheet.rules[0].media.is_empty());
+    assert_eq!(stylesheet.rules[1].selector.tag_name, Some("body".to_string()));
+    assert_eq!(stylesheet.rules[1].media.len(), 1);
+    assert_eq!(stylesheet.rules[1].properties[&PropertyName::Color], PropertyValue::Color(Color::Named("blue".to_string())));
+    assert_eq!(stylesheet.rules[2].selector.classes, vec!["wide".to_string()]);
+    assert_eq!(stylesheet.rules[2].media.len(), 2);
+    assert_eq!(stylesheet.rules[4].selector.id, Some("after".to_string()));
+
+    let narrow = Viewport { width: 500.0, height: 800.0, ..Viewport::default() };
+    let wide = Viewport { width: 800.0, height: 600.0, ..Viewport::default() };
+    assert!(stylesheet.rules[1].applies_to(&narrow));
+    assert!(!stylesheet.rules[1].applies_to(&wide));
+    // portrait, so the nested block does not apply
+    assert!(!stylesheet.rules[2].applies_to(&narrow));
+    assert!(!stylesheet.rules[3].applies_to(&narrow));
+}
+
+#[test]
+fn test_media_queries() {
+    let viewport = Viewport { width: 800.0, height: 600.0, ..Viewport::default() };
+    assert!(media_matches("", &viewport));
+    assert!(media_matches("all", &viewport));
+    assert!(media_matches("only screen", &viewport));
+    assert!(!media_matches("print", &viewport));
+    assert!(media_matches("not print", &viewport));
+    assert!(media_matches("print, (min-width: 800px)", &viewport));
+    assert!(!media_matches("(min-width: 801px)", &viewport));
+    assert!(media_matches("(max-width: 50em)", &viewport));
+    assert!(media_matches("(width: 800px) and (height: 600px)", &viewport));
+    assert!(media_matches("(orientation: landscape)", &viewport));
+    assert!(media_matches("(min-aspect-ratio: 4/3)", &viewport));
+    assert!(!media_matches("(min-aspect-ratio: 16/9)", &viewport));
+    assert!(media_matches("(prefers-color-scheme: light)", &viewport));
+    assert!(!media_matches("(prefers-color-scheme: dark)", &viewport));
+    assert!(media_matches("(resolution: 96dpi)", &viewport));
+    assert!(!media_matches("(min-resolution: 2dppx)", &viewport));
+    let retina = Viewport { resolution: 2.0, color_scheme: ColorScheme::Dark, ..viewport };
+    assert!(media_matches("(min-resolution: 2x) and (prefers-color-scheme: dark)", &retina));
+    // range syntax
+    assert!(media_matches("(width >= 600px)", &viewport));
+    assert!(media_matches("(600px < width)", &viewport));
+    assert!(media_matches("(400px <= width <= 800px)", &viewport));
+    assert!(!media_matches("(400px <= width < 800px)", &viewport));
+    assert!(media_matches("(width)", &viewport));
+    // unknown features and malformed queries never match, the rest of the list still does
+    assert!(!media_matches("(hover: hover)", &viewport));
+    assert!(!media_matches("screen and", &viewport));
+    assert!(!media_matches("(min-width: 10%)", &viewport));
+    assert!(media_matches("screen and and (width), screen", &viewport));
+}
+
+#[test]
+fn test_parse_imports() {
+    let css = r#"
+@charset "utf-8";
+@import "base.css";
+@import url(print.css) print;
+@import url('theme.css') screen and (min-width: 600px);
+body { color: red; }
+@import "ignored.css";
+"#;
+    let stylesheet = CssParser::new(css).parse_stylesheet();
+    let urls: Vec<&str> = stylesheet.imports.iter().map(|import| import.url.as_str()).collect();
+    assert_eq!(urls, vec!["base.css", "print.css", "theme.css"]);
+    assert!(stylesheet.imports[0].media.queries.is_empty());
+    assert!(!stylesheet.imports[1].media.matches(&Viewport::default()));
+    assert_eq!(stylesheet.imports[2].media.queries[0].features.len(), 1);
+    assert_eq!(stylesheet.rules.len(), 1);
+}
+
+#[test]
+fn test_parse_inline_style() {
+    let rule = CssParser::new("color: red; margin: 1px 2px !important; width: 10px").parse_inline_style();
+    assert_eq!(rule.properties.len(), 6);
+    assert_eq!(rule.properties[&PropertyName::Width], px(10.0));
+    assert!(rule.is_important(&PropertyName::MarginLeft));
+    assert!(!rule.is_important(&PropertyName::Color));
+}
+
+#[test]
+fn test_parse_custom_properties_and_var() {
+    let rule = CssParser::new("div { --Brand: #fff; color: var(--Brand); margin: var(--m) 0; --empty:; }").parse_stylesheet().rules.remove(0);
+    assert_eq!(rule.properties[&PropertyName::Custom("--Brand".to_string())], PropertyValue::Other("#fff".to_string()));
+    assert_eq!(rule.properties[&PropertyName::Custom("--empty".to_string())], PropertyValue::Other("".to_string()));
+    assert_eq!(rule.properties[&PropertyName::Color],
+               PropertyValue::Unresolved { property: "color".to_string(), value: "var(--Brand)".to_string() });
+    assert_eq!(rule.properties[&PropertyName::MarginLeft],
+               PropertyValue::Unresolved { property: "margin".to_string(), value: "var(--m) 0".to_string() });
+
+    let mut lookup = |name: &str| if name == "--x" { Some("1px".to_string()) } else { None };
+    assert_eq!(CssParser::substitute_vars("var(--x) var(--y, var(--x, 2px)) 3px", &mut lookup), Some("1px 1px 3px".to_string()));
+    assert_eq!(CssParser::substitute_vars("calc(var(--y, 1px + 2px) * 2)", &mut lookup), Some("calc(1px + 2px * 2)".to_string()));
+    assert_eq!(CssParser::substitute_vars("var(--y)", &mut lookup), None);
+    assert_eq!(CssParser::substitute_vars("var(--y,)", &mut lookup), Some("".to_string()));
+    assert_eq!(CssParser::parse_unresolved("margin", "1px 2px", &PropertyName::MarginLeft), Some(px(2.0)));
+}
+
+#[test]
+fn test_parse_unknown_properties() {
+    let css = "div { text-shadow: 1px 1px red; Transition: all 1s; -webkit-box-flex: 1; text-shadow: none; color: notacolor; }";
+    let rule = CssParser::new(css).parse_stylesheet().rules.remove(0);
+    let unknown = |name: &str| rule.properties.get(&PropertyName::Unknown(name.to_string()));
+    assert_eq!(rule.properties.len(), 3);
+    assert_eq!(unknown("text-shadow"), Some(&PropertyValue::Other("none".to_string())));
+    assert_eq!(unknown("transition"), Some(&PropertyValue::Other("all 1s".to_string())));
+    assert_eq!(unknown("-webkit-box-flex"), Some(&PropertyValue::Other("1".to_string())));
+    assert_eq!(PropertyName::Unknown("text-shadow".to_string()).css_name(), "text-shadow");
+}
 
-}
\ No newline at end of file
+#[test]
+fn test_parse_flex_properties() {
+    let values = |name: &str, value: &str| -> Vec<String> {
+        CssParser::parse_declaration(name, value).iter().map(|(_, value)| value.to_str()).collect()
+    };
+    assert_eq!(values("flex", "1"), vec!["1", "1", "0px"]);
+    assert_eq!(values("flex", "2 3"), vec!["2", "3", "0px"]);
+    assert_eq!(values("flex", "1 0 50%"), vec!["1", "0", "50%"]);
+    assert_eq!(values("flex", "100px 2"), vec!["2", "1", "100px"]);
+    assert_eq!(values("flex", "auto"), vec!["1", "1", "auto"]);
+    assert_eq!(values("flex", "none"), vec!["0", "0", "auto"]);
+    assert_eq!(values("flex", "content"), vec!["1", "1", "content"]);
+    assert!(values("flex", "1 2 3").is_empty());
+    assert!(values("flex", "-1").is_empty());
+    assert_eq!(values("flex-flow", "wrap column"), vec!["column", "wrap"]);
+    assert_eq!(values("flex-flow", "row-reverse"), vec!["row-reverse", "nowrap"]);
+    assert!(values("flex-flow", "wrap wrap").is_empty());
+    assert_eq!(values("gap", "10px"), vec!["10px", "10px"]);
+    assert_eq!(values("gap", "normal 5%"), vec!["normal", "5%"]);
+    assert!(values("gap", "auto").is_empty());
+    assert_eq!(values("order", "-2"), vec!["-2"]);
+    assert!(values("order", "1.5").is_empty());
+    assert!(values("flex-grow", "-1").is_empty());
+    assert_eq!(values("align-self", "Auto"), vec!["auto"]);
+    assert_eq!(values("justify-content", "space-between"), vec!["space-between"]);
+    assert!(values("align-items", "space-between").is_empty());
+    assert_eq!(values("display", "inline-flex"), vec!["inline-flex"]);
+}
+
+#[test]
+fn test_parse_grid_properties() {
+    let values = |name: &str, value: &str| -> Vec<String> {
+        CssParser::parse_declaration(name, value).iter().map(|(_, value)| value.to_str()).collect()
+    };
+    assert_eq!(values("grid-template-columns", "100px 1fr AUTO"), vec!["100px 1fr auto"]);
+    assert_eq!(values("grid-template-columns", "[a] minmax(50px, 2fr) [b c] 25%"), vec!["[a] minmax(50px, 2fr) [b c] 25%"]);
+    assert_eq!(values("grid-template-rows", "repeat(2, [x] 10px) 1fr"), vec!["[x] 10px [x] 10px 1fr"]);
+    assert_eq!(values("grid-template-rows", "none"), vec!["none"]);
+    assert!(values("grid-template-columns", "minmax(1fr, 10px)").is_empty());
+    assert!(values("grid-template-columns", "-10px").is_empty());
+    assert!(values("grid-template-columns", "repeat(0, 10px)").is_empty());
+    assert!(values("grid-template-columns", "repeat(auto-fill, 10px)").is_empty());
+    assert!(values("grid-template-columns", "repeat(1000000, 10px)").is_empty());
+    assert_eq!(values("grid-auto-rows", "min-content 20px"), vec!["min-content 20px"]);
+    assert!(values("grid-auto-rows", "[a] 20px").is_empty());
+    assert_eq!(values("grid-template-areas", "'a a .' \"b ... c\""), vec!["\"a a .\" \"b . c\""]);
+    assert!(values("grid-template-areas", "'a b' 'c'").is_empty());
+    assert!(values("grid-template-areas", "'a b a'").is_empty());
+    assert!(values("grid-template-areas", "'a a' 'a b'").is_empty());
+    assert_eq!(values("grid-auto-flow", "dense column"), vec!["column dense"]);
+    assert_eq!(values("grid-auto-flow", "dense"), vec!["row dense"]);
+    assert!(values("grid-auto-flow", "row column").is_empty());
+    assert_eq!(values("grid-row", "1 / span 2"), vec!["1", "span 2"]);
+    assert_eq!(values("grid-column", "-1"), vec!["-1", "auto"]);
+    assert_eq!(values("grid-column", "main"), vec!["main", "main"]);
+    assert!(values("grid-row", "0").is_empty());
+    assert!(values("grid-row", "span 0").is_empty());
+    assert!(values("grid-row", "1 / 2 / 3").is_empty());
+    assert_eq!(values("grid-area", "head"), vec!["head", "head", "head", "head"]);
+    assert_eq!(values("grid-area", "1 / 2"), vec!["1", "2", "auto", "auto"]);
+    assert_eq!(values("grid-gap", "5px 10px"), vec!["5px", "10px"]);
+    assert_eq!(values("justify-items", "left"), vec!["left"]);
+    assert_eq!(values("justify-self", "auto"), vec!["auto"]);
+    assert_eq!(values("display", "inline-grid"), vec!["inline-grid"]);
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use crate::css::{Comparison, MediaFeature, MediaQuery, MediaQueryList, MediaType, Orientation, ColorScheme, PropertyName,
+                 PropertyValue, RangeFeature, Rule, Selector, Stylesheet};
+use crate::css_parser::CssParser;
+
+/// Errors of the rule list operations, named after their DOM exceptions.
+#[derive(Debug, PartialEq)]
+pub enum CssomError {
+    IndexSize,
+    Syntax,
+}
+
+impl CssomError {
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            CssomError::IndexSize => "IndexSizeError",
+            CssomError::Syntax => "SyntaxError",
+        }
+    }
+}
+
+impl Stylesheet {
+    /// Serializes the sheet. Consecutive rules with the same media are
+    /// grouped back into `@media` blocks.
+    pub fn to_css(&self) -> String {
+        let mut css = String::new();
+        for import in &self.imports {
+            css.push_str(&format!("@import url(\"{}\")", import.url));
+            if !import.media.queries.is_empty() {
+                css.push(' ');
+                css.push_str(&import.media.to_css());
+            }
+            css.push_str(";\n");
+        }
+        let mut open: &[MediaQueryList] = &[];
+        for rule in &self.rules {
+            // close the blocks this rule is not in, then open its own
+            let common = open.iter().zip(&rule.media).take_while(|(a, b)| a == b).count();
+            for depth in (common..open.len()).rev() {
+                css.push_str(&format!("{}}}\n", indent(depth)));
+            }
+            for (depth, media) in rule.media.iter().enumerate().skip(common) {
+                css.push_str(&format!("{}@media {} {{\n", indent(depth), media.to_css()));
+            }
+            open = &rule.media;
+            css.push_str(&format!("{}{}\n", indent(open.len()), rule.to_css()));
+        }
+        for depth in (0..open.len()).rev() {
+            css.push_str(&format!("{}}}\n", indent(depth)));
+        }
+        css
+    }
+
+    /// `insertRule`: parses `css`, which must be a single rule, and inserts
+    /// it before rule `index`. An `@media` block becomes one rule per rule
+    /// inside it, a selector list one rule per selector.
+    pub fn insert_rule(&mut self, css: &str, index: usize) -> Result<usize, CssomError> {
+        if index > self.rules.len() {
+            return Err(CssomError::IndexSize);
+        }
+        let mut parsed = CssParser::new(css).parse_stylesheet();
+        if parsed.rules.is_empty() || top_level_blocks(css) > 1 {
+            return Err(CssomError::Syntax);
+        }
+        for rule in &mut parsed.rules {
+            rule.id = self.next_rule_id;
+            self.next_rule_id += 1;
+        }
+        self.rules.splice(index..index, parsed.rules);
+        Ok(index)
+    }
+
+    /// `deleteRule`
+    pub fn delete_rule(&mut self, index: usize) -> Result<(), CssomError> {
+        if index >= self.rules.len() {
+            return Err(CssomError::IndexSize);
+        }
+        self.rules.remove(index);
+        Ok(())
+    }
+
+    /// The rule with `Rule::id` `id`, `None` once it's deleted.
+    pub fn rule_by_id_mut(&mut self, id: usize) -> Option<&mut Rule> {
+        self.rules.iter_mut().find(|rule| rule.id == id)
+    }
+}
+
+impl Rule {
+    /// `cssText`, e.g. `div.a { color: rgb(255, 0, 0); margin-top: 1px !important; }`
+    pub fn to_css(&self) -> String {
+        let declarations = self.declarations();
+        if declarations.is_empty() {
+            return format!("{} {{ }}", self.selector.to_css());
+        }
+        let declarations: Vec<String> = declarations.iter()
+            .map(|(name, value)| {
+                let priority = if self.is_important(name) { " !important" } else { "" };
+                format!("{}: {}{};", name.css_name(), value.to_str(), priority)
+            })
+            .collect();
+        format!("{} {{ {} }}", self.selector.to_css(), declarations.join(" "))
+    }
+
+    pub fn selector_text(&self) -> String {
+        self.selector.to_css()
+    }
+
+    /// Declarations in registry order, then custom and unknown properties by name.
+    pub fn declarations(&self) -> Vec<(&PropertyName, &PropertyValue)> {
+        let mut declarations: Vec<(&PropertyName, &PropertyValue)> = self.properties.iter().collect();
+        declarations.sort_by_key(|(name, _)| {
+            let position = PropertyName::ALL.iter().position(|known| known == *name).unwrap_or(PropertyName::ALL.len());
+            (position, name.css_name().to_string())
+        });
+        declarations
+    }
+
+    /// `getPropertyValue`. A shorthand has a value only when all of its
+    /// longhands are set to the same one.
+    pub fn get_property_value(&self, name: &str) -> String {
+        property_value(&self.properties, name)
+    }
+
+    /// `getPropertyPriority`: `"important"` or an empty string.
+    pub fn get_property_priority(&self, name: &str) -> &'static str {
+        let longhands = declared_longhands(name);
+        if !longhands.is_empty() && longhands.iter().all(|name| self.is_important(name)) {
+            "important"
+        } else {
+            ""
+        }
+    }
+
+    /// `setProperty`. Invalid values and priorities leave the rule unchanged,
+    /// an empty value removes the property.
+    pub fn set_property(&mut self, name: &str, value: &str, priority: &str) {
+        let important = match priority.to_ascii_lowercase().as_str() {
+            "" => false,
+            "important" => true,
+            _ => return,
+        };
+        if value.trim().is_empty() {
+            self.remove_property(name);
+            return;
+        }
+        for (name, value) in CssParser::parse_declaration(name, value) {
+            if important {
+                self.important.insert(name.clone());
+            } else {
+                self.important.remove(&name);
+            }
+            self.properties.insert(name, value);
+        }
+    }
+
+    /// `removeProperty`, returns the value the property had.
+    pub fn remove_property(&mut self, name: &str) -> String {
+        let value = self.get_property_value(name);
+        for name in declared_longhands(name) {
+            self.properties.remove(&name);
+            self.important.remove(&name);
+        }
+        value
+    }
+}
+
+/// The properties a declaration of `name` sets, custom and unknown ones included.
+/// `getPropertyValue` over `properties`, a rule's declarations or a node's
+/// computed values: the value of `name`, an empty string when it isn't set.
+pub fn property_value(properties: &HashMap<PropertyName, PropertyValue>, name: &str) -> String {
+    let longhands = declared_longhands(name);
+    let values: Vec<Option<&PropertyValue>> = longhands.iter().map(|name| properties.get(name)).collect();
+    match values.first() {
+        Some(Some(first)) if values.iter().all(|value| *value == Some(first)) => first.to_str(),
+        _ => String::new(),
+    }
+}
+
+fn declared_longhands(name: &str) -> Vec<PropertyName> {
+    let name = name.trim();
+    if name.starts_with("--") {
+        return vec![PropertyName::Custom(name.to_string())];
+    }
+    let name = name.to_ascii_lowercase();
+    let longhands = CssParser::longhands(&name);
+    if longhands.is_empty() {
+        vec![PropertyName::Unknown(name)]
+    } else {
+        longhands
+    }
+}
+
+/// Number of `{}` blocks at the top level of `css`, outside strings and
+/// comments: the rules it holds, however many `Rule`s they parse into.
+fn top_level_blocks(css: &str) -> usize {
+    let mut chars = css.chars().peekable();
+    let (mut blocks, mut depth, mut quote) = (0, 0usize, None);
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(_) if c == '\\' => {
+                chars.next();
+            }
+            Some(open) if open == c => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '/' && chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = None;
+                for c in chars.by_ref() {
+                    if previous == Some('*') && c == '/' {
+                        break;
+                    }
+                    previous = Some(c);
+                }
+            }
+            None if c == '{' => {
+                if depth == 0 {
+                    blocks += 1;
+                }
+                depth += 1;
+            }
+            None if c == '}' => depth = depth.saturating_sub(1),
+            None => {}
+        }
+    }
+    blocks
+}
+
+fn indent(depth: usize) -> String {
+    "    ".repeat(depth)
+}
+
+impl Selector {
+    pub fn to_css(&self) -> String {
+        let mut css = self.tag_name.clone().unwrap_or_default();
+        if let Some(id) = &self.id {
+            css.push('#');
+            css.push_str(id);
+        }
+        for class in &self.classes {
+            css.push('.');
+            css.push_str(class);
+        }
+        if css.is_empty() {
+            css.push('*');
+        }
+        css
+    }
+}
+
+impl MediaQueryList {
+    pub fn to_css(&self) -> String {
+        let queries: Vec<String> = self.queries.iter().map(MediaQuery::to_css).collect();
+        queries.join(", ")
+    }
+}
+
+impl MediaQuery {
+    pub fn to_css(&self) -> String {
+        let mut parts = Vec::new();
+        // the type can be left out of `all and (...)`
+        if self.negated || self.media_type != MediaType::All || self.features.is_empty() {
+            let media_type = match &self.media_type {
+                MediaType::All => "all",
+                MediaType::Screen => "screen",
+                MediaType::Print => "print",
+                MediaType::Other(other) => other,
+            };
+            parts.push(if self.negated { format!("not {}", media_type) } else { media_type.to_string() });
+        }
+        parts.extend(self.features.iter().map(MediaFeature::to_css));
+        parts.join(" and ")
+    }
+}
+
+impl MediaFeature {
+    pub fn to_css(&self) -> String {
+        match self {
+            MediaFeature::Range(feature, comparison, value) => {
+                let (name, value) = match feature {
+                    RangeFeature::Width => ("width", format!("{}px", value)),
+                    RangeFeature::Height => ("height", format!("{}px", value)),
+                    RangeFeature::AspectRatio => ("aspect-ratio", value.to_string()),
+                    RangeFeature::Resolution => ("resolution", format!("{}dppx", value)),
+                };
+                match comparison {
+                    Comparison::Equal => format!("({}: {})", name, value),
+                    Comparison::GreaterOrEqual => format!("(min-{}: {})", name, value),
+                    Comparison::LessOrEqual => format!("(max-{}: {})", name, value),
+                    Comparison::Greater => format!("({} > {})", name, value),
+                    Comparison::Less => format!("({} < {})", name, value),
+                }
+            }
+            MediaFeature::Orientation(Orientation::Portrait) => String::from("(orientation: portrait)"),
+            MediaFeature::Orientation(Orientation::Landscape) => String::from("(orientation: landscape)"),
+            MediaFeature::PrefersColorScheme(ColorScheme::Light) => String::from("(prefers-color-scheme: light)"),
+            MediaFeature::PrefersColorScheme(ColorScheme::Dark) => String::from("(prefers-color-scheme: dark)"),
+            MediaFeature::Unknown(feature) => format!("({})", feature),
+        }
+    }
+}
+
+#[test]
+fn test_serialize_round_trip() {
+    let css = r#"
+@import url("base.css") print;
+* { color: red; }
+div#main.a.b { margin: 1px 2px !important; --Gap: 4px; text-shadow: none; font-family: "Open Sans", serif; }
+@media screen and (min-width: 600px), (orientation: landscape) {
+    p { padding-top: calc(10% + 2em); }
+    @media (prefers-color-scheme: dark) { p { color: currentColor; display: none; } }
+    span { border-top: 1px solid blue; }
+}
+a { color: rgba(0, 0, 255, 0.5); }
+"#;
+    let stylesheet = CssParser::new(css).parse_stylesheet();
+    let serialized = stylesheet.to_css();
+    let reparsed = CssParser::new(&serialized).parse_stylesheet();
+    // named colors come back as rgb(), so the rules are compared serialized
+    assert_eq!(reparsed.to_css(), serialized);
+    assert_eq!(reparsed.imports, stylesheet.imports);
+    assert_eq!(reparsed.rules.len(), stylesheet.rules.len());
+    for (reparsed, rule) in reparsed.rules.iter().zip(&stylesheet.rules) {
+        assert_eq!(reparsed.media, rule.media);
+        assert_eq!(reparsed.important, rule.important);
+    }
+
+    assert!(serialized.starts_with("@import url(\"base.css\") print;\n* { color: rgb(255, 0, 0); }\n"));
+    assert_eq!(stylesheet.rules[1].to_css(), "div#main.a.b { margin-top: 1px !important; margin-bottom: 1px !important; \
+        margin-left: 2px !important; margin-right: 2px !important; font-family: \"Open Sans\", serif; --Gap: 4px; text-shadow: none; }");
+    assert!(serialized.contains("@media screen and (min-width: 600px), (orientation: landscape) {\n    p { padding-top: calc(10% + 2em); }\n    @media (prefers-color-scheme: dark) {\n"));
+}
+
+#[test]
+fn test_cssom_rules_and_declarations() {
+    let mut stylesheet = CssParser::new("p { color: red; }").parse_stylesheet();
+    assert_eq!(stylesheet.insert_rule("div { margin: 1px; }", 0), Ok(0));
+    assert_eq!(stylesheet.insert_rule("span { }", 5), Err(CssomError::IndexSize));
+    assert_eq!(stylesheet.insert_rule("   ", 0), Err(CssomError::Syntax));
+    // one rule only, though a selector list or an @media block may parse into several
+    let mut other = CssParser::new("").parse_stylesheet();
+    assert_eq!(other.insert_rule("a { } b { } c { }", 0), Err(CssomError::Syntax));
+    assert_eq!(other.insert_rule("a { font-family: \"}{\"; } /* { */", 0), Ok(0));
+    assert_eq!(other.insert_rule("@media print { a { color: red; } b { color: blue; } }", 0), Ok(0));
+    assert_eq!(other.insert_rule("a, b { }", 0), Ok(0));
+    assert_eq!(other.rules.len(), 5);
+    assert_eq!(stylesheet.rules[0].selector_text(), "div");
+    assert_eq!(stylesheet.delete_rule(2), Err(CssomError::IndexSize));
+
+    let rule = &mut stylesheet.rules[0];
+    assert_eq!(rule.get_property_value("margin"), "1px");
+    assert_eq!(rule.get_property_value("margin-left"), "1px");
+    assert_eq!(rule.get_property_value("color"), "");
+
+    rule.set_property("margin-left", "5px", "important");
+    assert_eq!(rule.get_property_value("margin"), "");
+    assert_eq!(rule.get_property_priority("margin-left"), "important");
+    assert_eq!(rule.get_property_priority("margin"), "");
+    rule.set_property("color", "notacolor", "");
+    rule.set_property("color", "blue", "urgent");
+    assert_eq!(rule.get_property_value("color"), "");
+    rule.set_property("Color", "blue", "");
+    assert_eq!(rule.get_property_value("color"), "rgb(0, 0, 255)");
+    rule.set_property("--x", "var(--y)", "");
+    assert_eq!(rule.get_property_value("--x"), "var(--y)");
+
+    assert_eq!(rule.remove_property("margin-left"), "5px");
+    assert_eq!(rule.get_property_priority("margin-left"), "");
+    rule.set_property("color", "", "");
+    assert_eq!(rule.to_css(), "div { margin-top: 1px; margin-bottom: 1px; margin-right: 1px; --x: var(--y); }");
+
+    assert_eq!(stylesheet.delete_rule(0), Ok(()));
+    assert_eq!(stylesheet.to_css(), "p { color: rgb(255, 0, 0); }\n");
+}
+
+#[test]
+fn test_rule_ids_survive_insert_and_delete() {
+    let mut stylesheet = CssParser::new("a { color: red; } b { color: red; } c { color: red; }").parse_stylesheet();
+    // what `const s = sheet.cssRules[1].style` keeps pointing at
+    let id = stylesheet.rules[1].id;
+    assert_eq!(stylesheet.delete_rule(0), Ok(()));
+    stylesheet.rule_by_id_mut(id).unwrap().set_property("color", "blue", "");
+    assert_eq!(stylesheet.insert_rule("d { }", 0), Ok(0));
+    stylesheet.rule_by_id_mut(id).unwrap().set_property("margin-top", "1px", "");
+    assert_eq!(stylesheet.to_css(), "d { }\nb { color: rgb(0, 0, 255); margin-top: 1px; }\nc { color: rgb(255, 0, 0); }\n");
+
+    // ids of deleted rules aren't given to new ones
+    let deleted = stylesheet.rules[0].id;
+    assert_eq!(stylesheet.delete_rule(0), Ok(()));
+    assert_eq!(stylesheet.insert_rule("e { }", 2), Ok(2));
+    assert!(stylesheet.rule_by_id_mut(deleted).is_none());
+    assert!(stylesheet.rule_by_id_mut(stylesheet.rules[2].id).is_some());
+    assert_ne!(stylesheet.rules[2].id, deleted);
+}
+
+#[test]
+fn test_property_value_reads_declarations_only() {
+    let stylesheet = CssParser::new("p { margin: 2px; color: red; }").parse_stylesheet();
+    let properties = &stylesheet.rules[0].properties;
+    assert_eq!(property_value(properties, "margin"), "2px");
+    assert_eq!(property_value(properties, "color"), "rgb(255, 0, 0)");
+    // names of the style object's own members aren't properties
+    assert_eq!(property_value(properties, "setProperty"), "");
+    assert_eq!(property_value(properties, "__ruleId"), "");
+    assert_eq!(property_value(properties, "getPropertyValue"), "");
+}
@@ -1,14 +1,20 @@
 use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 use std::{fmt, panic};
-use crate::css::{DisplayType, Length, PropertyName, PropertyValue};
+use crate::css::{font_size_keyword, BorderStyle, Color, ComputedAs, CssWideKeyword, Length, LengthContext, Origin, PropertyName, PropertyValue, Rule, Selector, Specificity, TrackBreadth, Viewport, DEFAULT_FONT_SIZE};
 use crate::{CssParser, Stylesheet};
+use crate::browser::Stylesheets;
 use crate::js;
+use crate::rule_index::RuleIndex;
+use crate::style::ComputedStyle;
 
-#[derive(PartialEq, Eq, Clone)]
+#[derive(PartialEq, Clone)]
 pub struct Node {
     pub children: Vec<Node>,
     pub node_type: NodeType,
     pub styles: HashMap<PropertyName, PropertyValue>,
+    /// `styles` in the form layout uses
+    pub computed: ComputedStyle,
 }
 
 #[derive(PartialEq, Eq, Clone)]
@@ -52,75 +58,367 @@ impl Node {
             node_type,
             children,
             styles: HashMap::new(),
+            computed: ComputedStyle::default(),
         }
     }
 
-    fn set_default_styles(&mut self) {
-        self.styles.insert(PropertyName::Width, PropertyValue::Length(Length::Percent(100)));
-        if let NodeType::Text(_s) = &self.node_type {
-            self.styles.insert(PropertyName::Display, PropertyValue::Display(DisplayType::Inline));
-        }
+    /// The first child element with the tag name, such as the `head` or the
+    /// `body` of `html`. White space text may sit between them.
+    pub fn child_element(&self, tag_name: &str) -> Option<&Node> {
+        self.children.iter().find(|child| matches!(&child.node_type, NodeType::Element(data) if data.tag_name == tag_name))
     }
 
-    fn inherit_styles(&mut self, parent_styles: &HashMap<PropertyName, PropertyValue>) {
-        for (key, value) in parent_styles {
-            if key == &PropertyName::Color {
-                self.styles.insert(key.clone(), value.clone());
-            }
+    /// Turns specified values into computed values as the registry says.
+    /// `font-size` is resolved first, against the parent, and every other
+    /// length is then resolved against it. Percentages depend on the
+    /// containing block and are left to layout.
+    fn compute_values(&mut self, parent_styles: &HashMap<PropertyName, PropertyValue>, viewport: &Viewport, root_font_size: Option<f32>) {
+        let parent_font_size = match parent_styles.get(&PropertyName::FontSize) {
+            Some(PropertyValue::Length(Length::Px(px))) => *px,
+            _ => DEFAULT_FONT_SIZE,
+        };
+        let mut context = LengthContext {
+            font_size: parent_font_size,
+            root_font_size: root_font_size.unwrap_or(DEFAULT_FONT_SIZE),
+            viewport: *viewport,
+        };
+        let font_size = match self.styles.get(&PropertyName::FontSize) {
+            // percentages of font-size refer to the parent font size
+            Some(PropertyValue::Length(length)) => length.compute(&context).resolve(parent_font_size),
+            Some(PropertyValue::Keyword(keyword)) => font_size_keyword(keyword, parent_font_size).unwrap_or(parent_font_size),
+            _ => parent_font_size,
+        };
+        self.styles.insert(PropertyName::FontSize, PropertyValue::Length(Length::Px(font_size)));
+        context.font_size = font_size;
+        if root_font_size.is_none() {
+            // this is the root element, rem is relative to its own font size
+            context.root_font_size = font_size;
         }
-    }
 
-    fn add_styles_rec(&mut self, stylesheet: &Stylesheet, parent_styles: &HashMap<PropertyName, PropertyValue>) {
-        self.set_default_styles();
-        self.inherit_styles(parent_styles);
-        match self.node_type {
-            NodeType::Element(ref element) => {
-                for rule in &stylesheet.rules {
-                    if let Some(tag_name) = &rule.selector.tag_name {
-                        if *tag_name == element.tag_name {
-                            self.styles.extend(rule.properties.clone());
-                        }
-                    }
-                    if let Some(id) = element.get_id() {
-                        if let Some(selector_id) = &rule.selector.id {
-                            if *selector_id == *id {
-                                self.styles.extend(rule.properties.clone());
+        let border_styles: HashMap<PropertyName, PropertyValue> = self.styles.iter()
+            .filter(|(_, value)| matches!(value, PropertyValue::BorderStyle(_)))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        for (name, value) in self.styles.iter_mut() {
+            match name.info().computed {
+                ComputedAs::Specified | ComputedAs::FontSize => {}
+                ComputedAs::Length => match value {
+                    PropertyValue::Length(length) => *length = length.compute(&context),
+                    PropertyValue::Tracks(list) => {
+                        for track in &mut list.tracks {
+                            for breadth in [&mut track.min, &mut track.max] {
+                                if let TrackBreadth::Length(length) = breadth {
+                                    *length = length.compute(&context);
+                                }
                             }
                         }
                     }
-                    for class in element.get_classes() {
-                        if let Some(selector_class) = &rule.selector.class {
-                            if *selector_class == class {
-                                self.styles.extend(rule.properties.clone());
-                            }
+                    _ => {}
+                }
+                ComputedAs::LineHeight => {
+                    if let PropertyValue::Length(length) = value {
+                        *length = Length::Px(length.compute(&context).resolve(font_size));
+                    }
+                }
+                ComputedAs::FontWeight => {
+                    if let PropertyValue::Keyword(keyword) = value {
+                        let parent_weight = match parent_styles.get(&PropertyName::FontWeight) {
+                            Some(PropertyValue::Number(weight)) => *weight,
+                            _ => 400.0,
+                        };
+                        *value = PropertyValue::Number(relative_font_weight(keyword, parent_weight));
+                    }
+                }
+                ComputedAs::BorderWidth(style) => {
+                    match border_styles.get(&style) {
+                        Some(PropertyValue::BorderStyle(BorderStyle::None | BorderStyle::Hidden)) =>
+                            *value = PropertyValue::Length(Length::Px(0.0)),
+                        _ => if let PropertyValue::Length(length) = value {
+                            *length = length.compute(&context);
                         }
                     }
                 }
-            },
-            NodeType::Text(_) => {},
-            _ => {}
+                ComputedAs::Color => {
+                    if let PropertyValue::Color(Color::Current) = value {
+                        // `color: currentColor` is the inherited color
+                        *value = parent_styles.get(name).cloned().unwrap_or_else(|| name.info().initial);
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_styles_rec(&mut self, rules: &RuleIndex, parent_styles: &HashMap<PropertyName, PropertyValue>,
+                      viewport: &Viewport, root_font_size: Option<f32>) {
+        let mut cascaded = HashMap::new();
+        if let NodeType::Element(ref element) = self.node_type {
+            let inline_style = element.attributes.get("style")
+                .map(|style| CssParser::new(style).parse_inline_style());
+            for (name, value) in cascade(element, rules, inline_style.as_ref()) {
+                cascaded.insert(name.clone(), value.clone());
+            }
         }
+        substitute_vars(&mut cascaded, parent_styles);
+        self.styles = HashMap::new();
+        for name in PropertyName::ALL.iter() {
+            let info = name.info();
+            let inherited = || parent_styles.get(name).cloned().unwrap_or_else(|| info.initial.clone());
+            let value = match cascaded.remove(name) {
+                Some(PropertyValue::CssWide(CssWideKeyword::Initial)) => info.initial.clone(),
+                Some(PropertyValue::CssWide(CssWideKeyword::Inherit)) => inherited(),
+                // `revert` is resolved by the cascade, what is left of it behaves as `unset`
+                Some(PropertyValue::CssWide(_)) | None if info.inherited => inherited(),
+                Some(PropertyValue::CssWide(_)) | None => info.initial.clone(),
+                Some(value) => value,
+            };
+            self.styles.insert(name.clone(), value);
+        }
+        // declarations the registry doesn't know
+        self.styles.extend(cascaded);
+        self.compute_values(parent_styles, viewport, root_font_size);
+        self.computed = ComputedStyle::from_styles(&self.styles);
+        self.add_children_styles(rules, viewport, root_font_size);
+    }
 
+    /// Styles the children. Siblings that selectors can't tell apart get the
+    /// same styles, so only the first of them goes through the cascade.
+    fn add_children_styles(&mut self, rules: &RuleIndex, viewport: &Viewport, root_font_size: Option<f32>) {
+        let root_font_size = match self.styles.get(&PropertyName::FontSize) {
+            Some(PropertyValue::Length(Length::Px(px))) => root_font_size.or(Some(*px)),
+            _ => root_font_size,
+        };
+        let mut shared: HashMap<SharingKey, (HashMap<PropertyName, PropertyValue>, ComputedStyle)> = HashMap::new();
         for child in &mut self.children {
-            child.add_styles_rec(stylesheet, &self.styles);
+            let key = child.sharing_key();
+            match key.as_ref().and_then(|key| shared.get(key)) {
+                Some((styles, computed)) => {
+                    child.styles = styles.clone();
+                    child.computed = computed.clone();
+                    child.add_children_styles(rules, viewport, root_font_size);
+                }
+                None => {
+                    child.add_styles_rec(rules, &self.styles, viewport, root_font_size);
+                    if let Some(key) = key {
+                        shared.insert(key, (child.styles.clone(), child.computed.clone()));
+                    }
+                }
+            }
         }
     }
 
+    /// What the styles of a node depend on besides its parent: selectors
+    /// only look at the tag, id and classes. Nodes with a `style` attribute
+    /// are not shared.
+    fn sharing_key(&self) -> Option<SharingKey> {
+        match &self.node_type {
+            NodeType::Element(element) => {
+                if element.attributes.contains_key("style") {
+                    return None;
+                }
+                let mut classes: Vec<String> = element.get_classes().into_iter().map(String::from).collect();
+                classes.sort();
+                Some((element.tag_name.clone(), element.get_id().cloned(), classes))
+            }
+            NodeType::Text(_) | NodeType::Comment(_) => Some((String::new(), None, Vec::new())),
+        }
+    }
+
+    /// Styles the tree with the user agent stylesheet and `stylesheet`.
     pub fn add_styles(&mut self, stylesheet: &Stylesheet) {
+        let user_agent = CssParser::user_agent_stylesheet();
+        self.add_styles_from(&[&user_agent, stylesheet], &Viewport::default());
+    }
+
+    /// Styles the tree with several stylesheets, given in source order.
+    pub fn add_styles_from(&mut self, stylesheets: &[&Stylesheet], viewport: &Viewport) {
         let styles = HashMap::new();
-        self.add_styles_rec(stylesheet, &styles);
+        let rules = RuleIndex::new(stylesheets, viewport);
+        self.add_styles_rec(&rules, &styles, viewport, None);
     }
 
-    pub fn add_js(&mut self, js: &str) {
-        let result = panic::catch_unwind(|| {
-            js::init(js, &self);
-        });
+    /// Runs `js` against the tree, with `stylesheets` as `document.styleSheets`.
+    pub fn add_js(&mut self, js: &str, stylesheets: &Stylesheets) {
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            js::init(js, self, Rc::clone(stylesheets));
+        }));
         if result.is_err() {
             println!("Error in JS: {}", js);
         }
     }
 }
 
+/// Cascade order of a declaration: origin and importance, then specificity,
+/// then position in the document (stylesheet, rule).
+type CascadePriority = (u8, Specificity, usize, usize);
+
+/// `style` attributes beat every selector of their origin.
+const INLINE_SPECIFICITY: Specificity = (usize::MAX, 0, 0);
+
+/// Tag, id and sorted classes, see `Node::sharing_key`.
+type SharingKey = (String, Option<String>, Vec<String>);
+
+pub fn matches(element: &ElementData, selector: &Selector) -> bool {
+    if let Some(tag_name) = &selector.tag_name {
+        if *tag_name != element.tag_name {
+            return false;
+        }
+    }
+    if let Some(id) = &selector.id {
+        if element.get_id() != Some(id) {
+            return false;
+        }
+    }
+    let classes = element.get_classes();
+    selector.classes.iter().all(|class| classes.contains(class.as_str()))
+}
+
+/// Returns the winning declaration of every property set on `element` by
+/// the indexed `rules`. `inline_style` holds the declarations of the
+/// element's `style` attribute.
+fn cascade<'a>(element: &ElementData, rules: &RuleIndex<'a>, inline_style: Option<&'a Rule>)
+               -> HashMap<&'a PropertyName, &'a PropertyValue> {
+    let mut declarations: Vec<(CascadePriority, Origin, &PropertyName, &PropertyValue)> = Vec::new();
+    for indexed in rules.matching_rules(element) {
+        let rule = indexed.rule;
+        for (name, value) in &rule.properties {
+            let precedence = indexed.origin.precedence(rule.is_important(name));
+            let priority = (precedence, indexed.specificity, indexed.sheet_index, indexed.rule_index);
+            declarations.push((priority, indexed.origin, name, value));
+        }
+    }
+    if let Some(rule) = inline_style {
+        for (name, value) in &rule.properties {
+            let precedence = Origin::Author.precedence(rule.is_important(name));
+            declarations.push(((precedence, INLINE_SPECIFICITY, rules.sheet_count, 0), Origin::Author, name, value));
+        }
+    }
+    declarations.sort_by_key(|declaration| declaration.0);
+
+    let mut by_property: HashMap<&PropertyName, Vec<(Origin, &PropertyValue)>> = HashMap::new();
+    for (_, origin, name, value) in declarations {
+        by_property.entry(name).or_default().push((origin, value));
+    }
+    let mut winners = HashMap::new();
+    for (name, mut values) in by_property {
+        while let Some((origin, value)) = values.pop() {
+            if *value == PropertyValue::CssWide(CssWideKeyword::Revert) {
+                // as if the origin had not set the property at all
+                values.retain(|(other, _)| *other != origin);
+                continue;
+            }
+            winners.insert(name, value);
+            break;
+        }
+    }
+    winners
+}
+
+/// Computes custom properties, inherited ones included, and substitutes
+/// `var()` in the other declarations. Custom properties in a reference
+/// cycle and declarations that are invalid after substitution are dropped,
+/// which makes them behave as `unset`.
+fn substitute_vars(cascaded: &mut HashMap<PropertyName, PropertyValue>, parent_styles: &HashMap<PropertyName, PropertyValue>) {
+    let mut raw: HashMap<String, String> = HashMap::new();
+    for (name, value) in parent_styles {
+        if let (PropertyName::Custom(name), PropertyValue::Other(value)) = (name, value) {
+            raw.insert(name.clone(), value.clone());
+        }
+    }
+    let declared: Vec<PropertyName> = cascaded.keys()
+        .filter(|name| matches!(name, PropertyName::Custom(_)))
+        .cloned()
+        .collect();
+    for name in declared {
+        let value = cascaded.remove(&name).unwrap();
+        if let PropertyName::Custom(name) = name {
+            match value {
+                PropertyValue::Other(value) => {
+                    raw.insert(name, value);
+                }
+                PropertyValue::CssWide(CssWideKeyword::Initial) => {
+                    raw.remove(&name);
+                }
+                // custom properties inherit, so the rest keep the parent's value
+                _ => {}
+            }
+        }
+    }
+
+    let computed = compute_custom_properties(&raw);
+
+    for (name, value) in cascaded.iter_mut() {
+        if let PropertyValue::Unresolved { property, value: unresolved } = value {
+            let substituted = CssParser::substitute_vars(unresolved, &mut |var: &str| computed.get(var).cloned());
+            *value = match substituted.and_then(|text| CssParser::parse_unresolved(property, &text, name)) {
+                Some(parsed) => parsed,
+                None => PropertyValue::CssWide(CssWideKeyword::Unset),
+            };
+        }
+    }
+    for (name, value) in &computed {
+        cascaded.insert(PropertyName::Custom(name.clone()), PropertyValue::Other(value.clone()));
+    }
+}
+
+/// Substitutes the `var()`s of every custom property in `raw`, each after
+/// the properties it references. The references are followed with an
+/// explicit stack, so that no chain of them can overflow the call stack.
+/// Properties on a reference cycle are invalid and left out, as are those
+/// whose substitution fails.
+fn compute_custom_properties(raw: &HashMap<String, String>) -> HashMap<String, String> {
+    let mut computed: HashMap<&str, Option<String>> = HashMap::new();
+    let mut cyclic: HashSet<&str> = HashSet::new();
+    for name in raw.keys() {
+        if computed.contains_key(name.as_str()) {
+            continue;
+        }
+        // the properties being resolved, each with the references left to visit
+        let mut resolving: Vec<(&str, Vec<&str>)> = vec![(name, CssParser::var_references(&raw[name]))];
+        let mut on_stack: HashSet<&str> = HashSet::from([name.as_str()]);
+        while let Some((name, references)) = resolving.last_mut() {
+            if let Some(reference) = references.pop() {
+                if computed.contains_key(reference) || !raw.contains_key(reference) {
+                    continue;
+                }
+                if on_stack.contains(reference) {
+                    let start = resolving.iter().position(|(other, _)| *other == reference).unwrap();
+                    cyclic.extend(resolving[start..].iter().map(|(name, _)| *name));
+                } else {
+                    let (reference, value) = raw.get_key_value(reference).unwrap();
+                    resolving.push((reference, CssParser::var_references(value)));
+                    on_stack.insert(reference);
+                }
+                continue;
+            }
+            let name = *name;
+            resolving.pop();
+            on_stack.remove(name);
+            let value = if cyclic.contains(name) {
+                None
+            } else {
+                CssParser::substitute_vars(&raw[name], &mut |var: &str| computed.get(var).cloned().flatten())
+            };
+            computed.insert(name, value);
+        }
+    }
+    computed.into_iter()
+        .filter_map(|(name, value)| value.map(|value| (name.to_string(), value)))
+        .collect()
+}
+
+/// Computed `bolder` and `lighter`, from the table in CSS Fonts.
+fn relative_font_weight(keyword: &str, parent_weight: f32) -> f32 {
+    match (keyword, parent_weight) {
+        ("bolder", weight) if weight < 350.0 => 400.0,
+        ("bolder", weight) if weight < 550.0 => 700.0,
+        ("bolder", weight) if weight < 900.0 => 900.0,
+        ("lighter", weight) if weight < 550.0 => 100.0,
+        ("lighter", weight) if weight < 750.0 => 400.0,
+        ("lighter", weight) if weight >= 750.0 => 700.0,
+        (_, weight) => weight,
+    }
+}
+
 impl fmt::Debug for Node {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self.node_type)
@@ -207,3 +505,403 @@ body {
     // assert_eq!(node.children[0].styles.len(), 1);
     // assert_eq!(node.children[1].styles.len(), 1);
 }
+
+
+#[cfg(test)]
+fn styled_body(html: &str, stylesheets: &[&Stylesheet]) -> Node {
+    let mut parser = crate::html_parser::HtmlParser::new(html);
+    let mut body = parser.parse_nodes().remove(0);
+    body.add_styles_from(stylesheets, &Viewport::default());
+    body
+}
+
+#[cfg(test)]
+fn color_of(node: &Node) -> Option<&PropertyValue> {
+    node.styles.get(&PropertyName::Color)
+}
+
+#[cfg(test)]
+fn named(color: &str) -> PropertyValue {
+    PropertyValue::Color(Color::Named(color.to_string()))
+}
+
+#[test]
+fn test_cascade_specificity_beats_source_order() {
+    // browsers paint this blue: the id rule wins although the class rule comes later
+    let stylesheet = CssParser::new("#x { color: blue; } .a { color: red; }").parse_stylesheet();
+    let body = styled_body(r#"<body><div id="x" class="a"></div></body>"#, &[&stylesheet]);
+    assert_eq!(color_of(&body.children[0]), Some(&named("blue")));
+
+    // div.a (0,1,1) beats a later .a (0,1,0)
+    let stylesheet = CssParser::new("div.a { color: red; } .a { color: blue; }").parse_stylesheet();
+    let body = styled_body(r#"<body><div class="a"></div></body>"#, &[&stylesheet]);
+    assert_eq!(color_of(&body.children[0]), Some(&named("red")));
+
+    // .a.b (0,2,0) beats #-less later rules of lower specificity
+    let stylesheet = CssParser::new(".a.b { color: red; } div.b { color: blue; } .a { color: green; }").parse_stylesheet();
+    let body = styled_body(r#"<body><div class="a b"></div></body>"#, &[&stylesheet]);
+    assert_eq!(color_of(&body.children[0]), Some(&named("red")));
+}
+
+#[test]
+fn test_cascade_source_order() {
+    // equal specificity: the last rule wins, regardless of class order in the attribute
+    let stylesheet = CssParser::new(".b { color: red; } .a { color: blue; }").parse_stylesheet();
+    let body = styled_body(r#"<body><div class="b a"></div></body>"#, &[&stylesheet]);
+    assert_eq!(color_of(&body.children[0]), Some(&named("blue")));
+
+    // across stylesheets the later sheet wins
+    let first = CssParser::new("div { color: red; }").parse_stylesheet();
+    let second = CssParser::new("div { color: blue; }").parse_stylesheet();
+    let body = styled_body("<body><div></div></body>", &[&first, &second]);
+    assert_eq!(color_of(&body.children[0]), Some(&named("blue")));
+}
+
+#[test]
+fn test_cascade_selector_lists() {
+    // selectors we don't support drop their rule rather than match as their first part
+    let stylesheet = CssParser::new("div p { color: red; } ul > li { color: red; } div::before { color: red; } \
+                                     a:hover { color: red; } h2, h1 { color: blue; }").parse_stylesheet();
+    let body = styled_body("<body><div><a></a><h1></h1><ul></ul></div></body>", &[&stylesheet]);
+    let div = &body.children[0];
+    assert_eq!(color_of(div), Some(&named("black")));
+    assert_eq!(color_of(&div.children[0]), Some(&named("black")));
+    assert_eq!(color_of(&div.children[1]), Some(&named("blue")));
+    assert_eq!(color_of(&div.children[2]), Some(&named("black")));
+}
+
+#[test]
+fn test_cascade_important() {
+    // !important on a type selector beats a normal id rule
+    let stylesheet = CssParser::new("div { color: red !important; } #x { color: blue; }").parse_stylesheet();
+    let body = styled_body(r#"<body><div id="x"></div></body>"#, &[&stylesheet]);
+    assert_eq!(color_of(&body.children[0]), Some(&named("red")));
+
+    // between two important declarations specificity decides again
+    let stylesheet = CssParser::new("#x { color: blue !important; } div { color: red !important; }").parse_stylesheet();
+    let body = styled_body(r#"<body><div id="x"></div></body>"#, &[&stylesheet]);
+    assert_eq!(color_of(&body.children[0]), Some(&named("blue")));
+}
+
+#[test]
+fn test_cascade_origin() {
+    let user_agent = CssParser::new("div { color: red; margin: 8px !important; }")
+        .parse_stylesheet()
+        .with_origin(Origin::UserAgent);
+    let author = CssParser::new("div { color: blue; margin: 0px !important; }").parse_stylesheet();
+    // the author sheet comes first in the list on purpose: origin outranks source order
+    let body = styled_body("<body><div></div></body>", &[&author, &user_agent]);
+    let div = &body.children[0];
+    assert_eq!(color_of(div), Some(&named("blue")));
+    assert_eq!(div.styles.get(&PropertyName::MarginTop), Some(&PropertyValue::Length(Length::Px(8.0))));
+}
+
+#[test]
+fn test_current_color_inherits() {
+    let stylesheet = CssParser::new("body { color: red; } div { color: currentColor; }").parse_stylesheet();
+    let body = styled_body("<body><div></div></body>", &[&stylesheet]);
+    assert_eq!(color_of(&body.children[0]), Some(&named("red")));
+}
+
+#[cfg(test)]
+fn px_of(node: &Node, name: PropertyName) -> Option<f32> {
+    match node.styles.get(&name) {
+        Some(PropertyValue::Length(Length::Px(px))) => Some(*px),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_compute_lengths() {
+    let css = r#"
+body { font-size: 20px; }
+.a { font-size: 1.5em; margin-top: 2em; padding-top: 1rem; width: 50%; }
+.b { font-size: 50%; padding-left: 2ex; }
+.c { font-size: large; margin-left: 12pt; margin-right: 1in; margin-bottom: 2.54cm; padding-bottom: 10mm; }
+.d { width: 50vw; height: 10vh; margin-top: 10vmin; margin-bottom: 10vmax; padding-top: 1pc; }
+.e { font-size: calc(1em + 2px); width: calc(50% + 1em); margin-top: min(10vw, 2em); }
+"#;
+    let stylesheet = CssParser::new(css).parse_stylesheet();
+    let viewport = Viewport { width: 800.0, height: 600.0, ..Viewport::default() };
+    let html = r#"<body><div class="a"><div class="b"></div></div><div class="c"></div><div class="d"></div><div class="e"></div></body>"#;
+    let mut body = crate::html_parser::HtmlParser::new(html).parse_nodes().remove(0);
+    body.add_styles_from(&[&stylesheet], &viewport);
+
+    let a = &body.children[0];
+    assert_eq!(px_of(a, PropertyName::FontSize), Some(30.0));
+    assert_eq!(px_of(a, PropertyName::MarginTop), Some(60.0));
+    // rem follows the root element, not the parent
+    assert_eq!(px_of(a, PropertyName::PaddingTop), Some(20.0));
+    // percentages stay for layout
+    assert_eq!(a.styles.get(&PropertyName::Width), Some(&PropertyValue::Length(Length::Percent(50.0))));
+
+    let b = &a.children[0];
+    assert_eq!(px_of(b, PropertyName::FontSize), Some(15.0));
+    assert_eq!(px_of(b, PropertyName::PaddingLeft), Some(15.0));
+
+    let c = &body.children[1];
+    assert_eq!(px_of(c, PropertyName::FontSize), Some(18.0));
+    assert_eq!(px_of(c, PropertyName::MarginLeft), Some(16.0));
+    assert_eq!(px_of(c, PropertyName::MarginRight), Some(96.0));
+    assert!((px_of(c, PropertyName::MarginBottom).unwrap() - 96.0).abs() < 0.001);
+    assert!((px_of(c, PropertyName::PaddingBottom).unwrap() - 37.795).abs() < 0.001);
+
+    let d = &body.children[2];
+    assert_eq!(px_of(d, PropertyName::Width), Some(400.0));
+    assert_eq!(px_of(d, PropertyName::Height), Some(60.0));
+    assert_eq!(px_of(d, PropertyName::MarginTop), Some(60.0));
+    assert_eq!(px_of(d, PropertyName::MarginBottom), Some(80.0));
+    assert_eq!(px_of(d, PropertyName::PaddingTop), Some(16.0));
+    // font-size is inherited
+    assert_eq!(px_of(d, PropertyName::FontSize), Some(20.0));
+
+    // calc() keeps the percentage for layout but resolves everything else
+    let e = &body.children[3];
+    assert_eq!(px_of(e, PropertyName::FontSize), Some(22.0));
+    assert_eq!(px_of(e, PropertyName::MarginTop), Some(44.0));
+    match e.styles.get(&PropertyName::Width) {
+        Some(PropertyValue::Length(width)) => assert_eq!(width.resolve(200.0), 122.0),
+        width => panic!("unexpected width {:?}", width),
+    }
+}
+
+#[test]
+fn test_cascade_media_queries() {
+    let css = r#"
+div { color: black; }
+@media (max-width: 600px) { div { color: red; } }
+@media (min-width: 601px) and (orientation: landscape) { div { color: blue; } }
+@media print { div { color: green; } }
+"#;
+    let stylesheet = CssParser::new(css).parse_stylesheet();
+    let html = r#"<body><div></div></body>"#;
+    let unstyled = crate::html_parser::HtmlParser::new(html).parse_nodes().remove(0);
+
+    let mut narrow = unstyled.clone();
+    narrow.add_styles_from(&[&stylesheet], &Viewport { width: 400.0, height: 600.0, ..Viewport::default() });
+    assert_eq!(color_of(&narrow.children[0]), Some(&named("red")));
+
+    let mut wide = unstyled.clone();
+    wide.add_styles_from(&[&stylesheet], &Viewport { width: 1024.0, height: 768.0, ..Viewport::default() });
+    assert_eq!(color_of(&wide.children[0]), Some(&named("blue")));
+
+    let mut tall = unstyled;
+    tall.add_styles_from(&[&stylesheet], &Viewport { width: 700.0, height: 900.0, ..Viewport::default() });
+    assert_eq!(color_of(&tall.children[0]), Some(&named("black")));
+}
+
+#[test]
+fn test_cascade_inline_style() {
+    let stylesheet = CssParser::new("#x { color: blue; margin-top: 1px !important; } div { padding-top: 1px !important; }").parse_stylesheet();
+    let html = r#"<body><div id="x" style="color: red; margin-top: 2px; padding-top: 2px !important"></div></body>"#;
+    let body = styled_body(html, &[&stylesheet]);
+    let div = &body.children[0];
+    // the attribute beats the id selector
+    assert_eq!(color_of(div), Some(&named("red")));
+    // but not an important declaration, unless it is important itself
+    assert_eq!(px_of(div, PropertyName::MarginTop), Some(1.0));
+    assert_eq!(px_of(div, PropertyName::PaddingTop), Some(2.0));
+}
+
+#[test]
+fn test_user_agent_stylesheet() {
+    use crate::css::DisplayType;
+    let author = CssParser::new("p { margin-top: 0px; } span { display: block; }").parse_stylesheet();
+    let html = r#"<body><h1>a</h1><p><b>b</b><span>c</span></p><script>d</script></body>"#;
+    let mut body = crate::html_parser::HtmlParser::new(html).parse_nodes().remove(0);
+    body.add_styles(&author);
+    let display = |node: &Node| node.styles.get(&PropertyName::Display).cloned();
+
+    assert_eq!(px_of(&body, PropertyName::MarginLeft), Some(8.0));
+    let h1 = &body.children[0];
+    assert_eq!(display(h1), Some(PropertyValue::Display(DisplayType::Block)));
+    assert_eq!(px_of(h1, PropertyName::FontSize), Some(32.0));
+    assert_eq!(px_of(h1, PropertyName::MarginTop), Some(32.0 * 0.67));
+
+    let p = &body.children[1];
+    // author rules beat the user agent whatever their specificity
+    assert_eq!(px_of(p, PropertyName::MarginTop), Some(0.0));
+    assert_eq!(px_of(p, PropertyName::MarginBottom), Some(16.0));
+    assert_eq!(display(&p.children[0]), Some(PropertyValue::Display(DisplayType::Inline)));
+    assert_eq!(p.children[0].styles.get(&PropertyName::FontWeight), Some(&PropertyValue::Number(700.0)));
+    assert_eq!(display(&p.children[1]), Some(PropertyValue::Display(DisplayType::Block)));
+
+    assert_eq!(display(&body.children[2]), Some(PropertyValue::Display(DisplayType::None)));
+}
+
+#[test]
+fn test_inheritance_and_css_wide_keywords() {
+    let user_agent = CssParser::new("p { margin-top: 10px; color: green; }").parse_stylesheet()
+        .with_origin(Origin::UserAgent);
+    let author = CssParser::new(r#"
+body { font-weight: bold; font-style: italic; text-align: center; line-height: 150%; margin-top: 5px; font-size: 20px; }
+.inherit { margin-top: inherit; }
+.initial { font-weight: initial; color: initial; }
+.unset { text-align: unset; margin: unset; }
+.revert { margin-top: 3px; margin-top: revert; color: revert; }
+.lighter { font-weight: lighter; border-style: solid none; border-width: 2px; }
+.shorthand { font: inherit; }
+"#).parse_stylesheet();
+    let html = r#"<body><div class="inherit"></div><div class="initial"></div><div class="unset"></div><p class="revert"></p><div class="lighter"></div><div class="shorthand"></div></body>"#;
+    let body = styled_body(html, &[&user_agent, &author]);
+    let style = |node: &Node, name: PropertyName| node.styles.get(&name).cloned();
+    let keyword = |keyword: &str| Some(PropertyValue::Keyword(keyword.to_string()));
+
+    let inherit = &body.children[0];
+    // inherited properties inherit, the rest start from their initial value
+    assert_eq!(style(inherit, PropertyName::FontWeight), Some(PropertyValue::Number(700.0)));
+    assert_eq!(style(inherit, PropertyName::FontStyle), keyword("italic"));
+    assert_eq!(style(inherit, PropertyName::TextAlign), keyword("center"));
+    // line-height percentages are computed against the font size, then inherited as a length
+    assert_eq!(px_of(inherit, PropertyName::LineHeight), Some(30.0));
+    assert_eq!(px_of(inherit, PropertyName::PaddingTop), Some(0.0));
+    assert_eq!(style(inherit, PropertyName::Width), keyword("auto"));
+    assert_eq!(px_of(inherit, PropertyName::MarginTop), Some(5.0));
+
+    let initial = &body.children[1];
+    assert_eq!(style(initial, PropertyName::FontWeight), Some(PropertyValue::Number(400.0)));
+    assert_eq!(color_of(initial), Some(&named("black")));
+
+    let unset = &body.children[2];
+    assert_eq!(style(unset, PropertyName::TextAlign), keyword("center"));
+    assert_eq!(px_of(unset, PropertyName::MarginTop), Some(0.0));
+
+    let revert = &body.children[3];
+    // the author origin is rolled back to the user agent rule
+    assert_eq!(px_of(revert, PropertyName::MarginTop), Some(10.0));
+    assert_eq!(color_of(revert), Some(&named("green")));
+
+    let lighter = &body.children[4];
+    assert_eq!(style(lighter, PropertyName::FontWeight), Some(PropertyValue::Number(400.0)));
+    // border widths compute to zero without a border style
+    assert_eq!(px_of(lighter, PropertyName::BorderTopWidth), Some(2.0));
+    assert_eq!(px_of(lighter, PropertyName::BorderLeftWidth), Some(0.0));
+    assert_eq!(px_of(&body, PropertyName::BorderTopWidth), Some(0.0));
+
+    let shorthand = &body.children[5];
+    assert_eq!(style(shorthand, PropertyName::FontStyle), keyword("italic"));
+    assert_eq!(px_of(shorthand, PropertyName::FontSize), Some(20.0));
+}
+
+#[test]
+fn test_custom_properties() {
+    let css = r#"
+body { --brand-color: #ff0000; --gap: 4px; --a: var(--b); --b: var(--a, 1px); }
+div { color: var(--brand-color); margin: var(--gap) calc(var(--gap) * 2); }
+.override { --brand-color: blue; }
+.fallback { color: var(--missing, green); padding-top: var(--missing); }
+.cycle { margin-top: var(--a, 7px); width: var(--b, 9px); }
+.invalid { --gap: red; }
+.initial { --brand-color: initial; }
+"#;
+    let stylesheet = CssParser::new(css).parse_stylesheet();
+    let html = r#"<body><div></div><div class="override"><div></div></div><div class="fallback"></div><div class="cycle"></div><div class="invalid"></div><div class="initial"></div></body>"#;
+    let body = styled_body(html, &[&stylesheet]);
+    let hex = |hex: u32| PropertyValue::Color(Color::Hex(hex));
+
+    let div = &body.children[0];
+    assert_eq!(color_of(div), Some(&hex(0xff0000)));
+    assert_eq!(px_of(div, PropertyName::MarginTop), Some(4.0));
+    assert_eq!(px_of(div, PropertyName::MarginLeft), Some(8.0));
+
+    // custom properties inherit
+    let nested = &body.children[1].children[0];
+    assert_eq!(color_of(&body.children[1]), Some(&named("blue")));
+    assert_eq!(color_of(nested), Some(&named("blue")));
+    assert_eq!(nested.styles.get(&PropertyName::Custom("--gap".to_string())), Some(&PropertyValue::Other("4px".to_string())));
+
+    let fallback = &body.children[2];
+    assert_eq!(color_of(fallback), Some(&named("green")));
+    assert_eq!(px_of(fallback, PropertyName::PaddingTop), Some(0.0));
+
+    // --a and --b reference each other, so both are invalid and the fallbacks are used
+    let cycle = &body.children[3];
+    assert_eq!(px_of(cycle, PropertyName::MarginTop), Some(7.0));
+    assert_eq!(px_of(cycle, PropertyName::Width), Some(9.0));
+    assert!(!cycle.styles.contains_key(&PropertyName::Custom("--a".to_string())));
+
+    // invalid at computed-value time: margin is unset, color still works
+    let invalid = &body.children[4];
+    assert_eq!(px_of(invalid, PropertyName::MarginTop), Some(0.0));
+    assert_eq!(color_of(invalid), Some(&hex(0xff0000)));
+
+    // color is inherited, so an invalid var() makes it take the parent's
+    assert_eq!(color_of(&body.children[5]), Some(&named("black")));
+}
+
+#[test]
+fn test_long_custom_property_chains() {
+    // a chain of references as long as this one used to overflow the stack
+    let chain: String = (0..20_000).map(|i| format!("--c{}: var(--c{}); ", i, i + 1)).collect();
+    // each step doubles the value, until it gets too long and invalid
+    let doubling: String = (0..64).map(|i| format!("--d{}: var(--d{}) var(--d{}); ", i, i + 1, i + 1)).collect();
+    let css = format!("body {{ {} --c20000: 5px; {} --d64: x; }} div {{ margin-top: var(--c0); }}", chain, doubling);
+    let stylesheet = CssParser::new(&css).parse_stylesheet();
+    let body = styled_body("<body><div></div></body>", &[&stylesheet]);
+    let custom = |name: &str| match body.styles.get(&PropertyName::Custom(name.to_string())) {
+        Some(PropertyValue::Other(value)) => Some(value.len()),
+        _ => None,
+    };
+    assert_eq!(px_of(&body.children[0], PropertyName::MarginTop), Some(5.0));
+    assert_eq!(custom("--d49"), Some(65535));
+    assert_eq!(custom("--d48"), None);
+    assert_eq!(custom("--d0"), None);
+}
+
+#[test]
+fn test_unknown_properties_cascade() {
+    let stylesheet = CssParser::new("div { text-shadow: none; transition: all 1s; } #x { text-shadow: 1px 1px red; }").parse_stylesheet();
+    let body = styled_body(r#"<body><div id="x"><p></p></div></body>"#, &[&stylesheet]);
+    let unknown = |node: &Node, name: &str| node.styles.get(&PropertyName::Unknown(name.to_string())).cloned();
+    let div = &body.children[0];
+    assert_eq!(unknown(div, "text-shadow"), Some(PropertyValue::Other("1px 1px red".to_string())));
+    assert_eq!(unknown(div, "transition"), Some(PropertyValue::Other("all 1s".to_string())));
+    // nothing is known about them, so they are not inherited
+    assert_eq!(unknown(&div.children[0], "text-shadow"), None);
+}
+
+#[test]
+fn test_style_sharing() {
+    let stylesheet = CssParser::new(".a { color: red; } .b { font-size: 20px; } #x { color: blue; } em { margin: 1em; }").parse_stylesheet();
+    let body = styled_body(r#"<body><p class="a b"><em>one</em></p><p class="b a"><em>two</em></p><p class="a b" style="color: green"></p><p class="a b" id="x"></p></body>"#,
+                           &[&stylesheet]);
+    let p = &body.children;
+    assert_eq!(p[0].styles, p[1].styles);
+    // the children of a shared element are still styled
+    assert_eq!(p[1].children[0].styles.get(&PropertyName::MarginTop), Some(&PropertyValue::Length(Length::Px(20.0))));
+    assert_eq!(p[1].children[0].children[0].computed.color, Color::Rgba(255, 0, 0, 255));
+    assert_eq!(p[2].computed.color, Color::Rgba(0, 128, 0, 255));
+    assert_eq!(p[3].computed.color, Color::Rgba(0, 0, 255, 255));
+}
+
+/// Benchmark: prints how long styling a 10k element document takes. Left
+/// out of the suite as debug builds are slow, run it with
+/// `cargo test --release bench_add_styles -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_add_styles_10k_elements() {
+    let mut css = String::new();
+    for i in 0..500 {
+        css.push_str(&format!(".c{} {{ color: rgb({}, 0, 0); }} #id{} {{ margin: {}px; }} div.c{}.d {{ padding: 1px; }}\n", i, i % 256, i, i % 20, i));
+    }
+    css.push_str("div { display: block; } span { font-weight: bold; } * { line-height: 1.2; }");
+    let stylesheet = CssParser::new(&css).parse_stylesheet();
+
+    let mut html = String::from("<body>");
+    for i in 0..1000 {
+        html.push_str(&format!("<div id=\"id{}\" class=\"c{} d\">", i, i % 500));
+        for j in 0..9 {
+            html.push_str(&format!("<span class=\"c{}\">text</span>", j));
+        }
+        html.push_str("</div>");
+    }
+    html.push_str("</body>");
+    let body = crate::html_parser::HtmlParser::new(&html).parse_nodes().remove(0);
+
+    let start = std::time::Instant::now();
+    let mut styled = body.clone();
+    styled.add_styles(&stylesheet);
+    let elapsed = start.elapsed();
+    assert_eq!(styled.children.len(), 1000);
+    println!("styled 10000 elements in {:?}", elapsed);
+}
@@ -21,7 +21,7 @@ impl<'a> HtmlParser<'a> {
         let mut nodes = Vec::new();
 
         while self.chars.peek().is_some() {
-            self.consume_while(char::is_whitespace);
+            let whitespace = self.consume_while(char::is_whitespace);
             if self.chars.peek().map_or(false, |c| *c == '<') {
                 self.chars.next();
                 if self.chars.peek().map_or(false, |c| *c == '/') {
@@ -35,7 +35,12 @@ impl<'a> HtmlParser<'a> {
 
                     self.node_q.push(close_tag_name);
                     break;
-                } else if self.chars.peek().map_or(false, |c| *c == '!') {
+                }
+                // white space between siblings is one space, which layout collapses where it doesn't show
+                if !whitespace.is_empty() && !nodes.is_empty() {
+                    nodes.push(Node::new(NodeType::Text(String::from(" ")), Vec::new()));
+                }
+                if self.chars.peek().is_some_and(|c| *c == '!') {
                     self.chars.next();
                     nodes.push(self.parse_comment_node());
                 } else {
@@ -56,7 +61,14 @@ impl<'a> HtmlParser<'a> {
                     nodes.insert(insert_index, node);
                 }
             } else {
-                nodes.push(self.parse_text_node());
+                let mut node = self.parse_text_node();
+                // the space separates the text from an inline element before it
+                if let NodeType::Text(text) = &mut node.node_type {
+                    if !whitespace.is_empty() && !text.is_empty() {
+                        text.insert(0, ' ');
+                    }
+                }
+                nodes.push(node);
             }
         }
         nodes
@@ -311,6 +323,22 @@ fn test_parse_text_node() {
     assert_eq!(node.node_type, NodeType::Text(String::from("test")));
 }
 
+#[test]
+fn test_text_keeps_space_after_element() {
+    let mut parser = HtmlParser::new("<p><b>bold</b> text</p>");
+    let nodes = parser.parse_nodes();
+    assert_eq!(nodes[0].children[1].node_type, NodeType::Text(String::from(" text")));
+}
+
+#[test]
+fn test_space_between_elements_is_kept() {
+    let mut parser = HtmlParser::new("<p>\n  <b>bold</b>\n <i>it</i>\n</p>");
+    let nodes = parser.parse_nodes();
+    // only between the siblings, not before the first or after the last
+    assert_eq!(nodes[0].children.len(), 3);
+    assert_eq!(nodes[0].children[1].node_type, NodeType::Text(String::from(" ")));
+}
+
 #[test]
 fn test_parse_comment_node() {
     let mut parser = HtmlParser::new("--test-->");
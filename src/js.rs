@@ -1,4 +1,7 @@
-use crate::browser::NODES;
+use std::rc::Rc;
+use crate::browser::{Stylesheets, NODES};
+use crate::css::{Origin, Rule, Stylesheet, Viewport};
+use crate::cssom;
 use crate::dom::{Node, NodeType};
 
 fn log_callback(
@@ -24,7 +27,7 @@ fn get_by_id_callback(
         let id_str = id.to_rust_string_lossy(scope);
         let nodes = unsafe {&NODES};
         let nodes = nodes_tree_to_vector(&nodes[0]);
-        for node in nodes {
+        for (index, node) in nodes.iter().enumerate() {
             if let NodeType::Element(element_data) = &node.node_type {
                 if let Some(node_id) = element_data.attributes.get("id") {
                     if node_id == &id_str {
@@ -32,16 +35,9 @@ fn get_by_id_callback(
                         let tag_name_key = v8::String::new(scope, "tagName").unwrap().into();
                         let tag_name_value = v8::String::new(scope, element_data.tag_name.as_str()).unwrap();
                         element_obj.set(scope, tag_name_key, tag_name_value.into());
+                        set_node_index(scope, element_obj, index);
                         let style_key = v8::String::new(scope, "style").unwrap().into();
-                        let style_obj = v8::Object::new(scope);
-                        for (key, value) in &node.styles {
-                            dbg!(key, value);
-                            let key_str = format!("{:?}", key);
-                            let key = v8::String::new(scope, key_str.as_str()).unwrap().into();
-                            let value_str = format!("{:?}", value);
-                            let value = v8::String::new(scope, value_str.as_str()).unwrap();
-                            style_obj.set(scope, key, value.into());
-                        }
+                        let style_obj = style_object(scope, node, index);
                         element_obj.set(scope, style_key, style_obj.into());
                         rv.set(element_obj.into());
                     }
@@ -52,24 +48,349 @@ fn get_by_id_callback(
     }
 }
 
+/// `getComputedStyle(element)` for elements handed out by `document`.
+fn get_computed_style_callback(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let element = match args.get(0).to_object(scope) {
+        Some(element) => element,
+        None => return,
+    };
+    let index_key = v8::String::new(scope, NODE_INDEX_KEY).unwrap().into();
+    let index = match element.get(scope, index_key).and_then(|index| index.integer_value(scope)) {
+        Some(index) => index as usize,
+        None => return,
+    };
+    let nodes = unsafe { &*std::ptr::addr_of!(NODES) };
+    let nodes = nodes_tree_to_vector(&nodes[0]);
+    if let Some(node) = nodes.get(index) {
+        let style_obj = style_object(scope, node, index);
+        rv.set(style_obj.into());
+    }
+}
+
+/// `element.style.getPropertyValue(name)`, read from the computed values
+/// of the element's node.
+fn get_property_value_callback(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let name = string_arg(scope, &args, 0);
+    let value = match get_index(scope, args.this(), NODE_INDEX_KEY) {
+        Some(index) => {
+            let nodes = unsafe { &*std::ptr::addr_of!(NODES) };
+            let nodes = nodes_tree_to_vector(&nodes[0]);
+            nodes.get(index).map(|node| cssom::property_value(&node.styles, &name)).unwrap_or_default()
+        }
+        None => String::new(),
+    };
+    rv.set(v8::String::new(scope, value.as_str()).unwrap().into());
+}
+
+/// Where an element object's node is in `nodes_tree_to_vector`, so that
+/// callbacks can find it again.
+const NODE_INDEX_KEY: &str = "__nodeIndex";
+
+fn set_node_index(scope: &mut v8::HandleScope, element_obj: v8::Local<v8::Object>, index: usize) {
+    let index_key = v8::String::new(scope, NODE_INDEX_KEY).unwrap().into();
+    let index_value = v8::Integer::new(scope, index as i32);
+    element_obj.set(scope, index_key, index_value.into());
+}
+
+/// The computed values of `node`, the `index`th element, under both their
+/// JS and CSS names, unsupported properties included.
+fn style_object<'s>(scope: &mut v8::HandleScope<'s>, node: &Node, index: usize) -> v8::Local<'s, v8::Object> {
+    let style_obj = v8::Object::new(scope);
+    set_node_index(scope, style_obj, index);
+    for (property_name, property_value) in &node.styles {
+        let value = v8::String::new(scope, property_value.to_str().as_str()).unwrap();
+        for name in [property_name.to_str(), property_name.css_name()] {
+            let key = v8::String::new(scope, name).unwrap().into();
+            style_obj.set(scope, key, value.into());
+        }
+    }
+    set_function(scope, style_obj, "getPropertyValue", get_property_value_callback);
+    style_obj
+}
+
+/// The sheet a sheet or `rule.style` object stands for, by its index in the
+/// page's stylesheets, and the rule by its `Rule::id`.
+const SHEET_INDEX_KEY: &str = "__sheetIndex";
+const RULE_ID_KEY: &str = "__ruleId";
+
+fn set_string(scope: &mut v8::HandleScope, obj: v8::Local<v8::Object>, key: &str, value: &str) {
+    let key = v8::String::new(scope, key).unwrap().into();
+    let value = v8::String::new(scope, value).unwrap();
+    obj.set(scope, key, value.into());
+}
+
+fn set_integer(scope: &mut v8::HandleScope, obj: v8::Local<v8::Object>, key: &str, value: usize) {
+    let key = v8::String::new(scope, key).unwrap().into();
+    let value = v8::Integer::new(scope, value as i32);
+    obj.set(scope, key, value.into());
+}
+
+fn get_index(scope: &mut v8::HandleScope, obj: v8::Local<v8::Object>, key: &str) -> Option<usize> {
+    let key = v8::String::new(scope, key).unwrap().into();
+    obj.get(scope, key)
+        .filter(|value| !value.is_undefined())
+        .and_then(|value| value.integer_value(scope))
+        .map(|value| value as usize)
+}
+
+fn set_function(scope: &mut v8::HandleScope, obj: v8::Local<v8::Object>, key: &str,
+                callback: impl v8::MapFnTo<v8::FunctionCallback>) {
+    let function = v8::FunctionTemplate::new(scope, callback).get_function(scope).unwrap();
+    let key = v8::String::new(scope, key).unwrap().into();
+    obj.set(scope, key, function.into());
+}
+
+fn throw_error(scope: &mut v8::HandleScope, message: &str) {
+    let message = v8::String::new(scope, message).unwrap();
+    let exception = v8::Exception::error(scope, message);
+    scope.throw_exception(exception);
+}
+
+/// The page's stylesheets, which `init` keeps in the isolate's slot.
+fn page_stylesheets(scope: &mut v8::HandleScope) -> Stylesheets {
+    Rc::clone(scope.get_slot::<Stylesheets>().expect("the stylesheets are set on the isolate"))
+}
+
+/// Restyles the nodes scripts read after a stylesheet changed.
+fn restyle_nodes(stylesheets: &[Stylesheet]) {
+    let nodes = unsafe { &mut *std::ptr::addr_of_mut!(NODES) };
+    let stylesheets: Vec<&Stylesheet> = stylesheets.iter().collect();
+    if let Some(body) = nodes.first_mut() {
+        body.add_styles_from(&stylesheets, &Viewport::default());
+    }
+}
+
+/// `document.styleSheets`, the author sheets. Sheets keep their index in
+/// the page's stylesheets so that edits go to the sheets the page is
+/// styled with.
+fn style_sheets_array<'s>(scope: &mut v8::HandleScope<'s>) -> v8::Local<'s, v8::Array> {
+    // the rules are copied out, so that no borrow is held while JS may run
+    let author_sheets: Vec<(usize, Vec<Rule>)> = page_stylesheets(scope).borrow().iter().enumerate()
+        .filter(|(_, stylesheet)| stylesheet.origin == Origin::Author)
+        .map(|(sheet_index, stylesheet)| (sheet_index, stylesheet.rules.clone()))
+        .collect();
+    let array = v8::Array::new(scope, 0);
+    for (length, (sheet_index, rules)) in author_sheets.iter().enumerate() {
+        let sheet_obj = v8::Object::new(scope);
+        set_integer(scope, sheet_obj, SHEET_INDEX_KEY, *sheet_index);
+        set_function(scope, sheet_obj, "insertRule", insert_rule_callback);
+        set_function(scope, sheet_obj, "deleteRule", delete_rule_callback);
+        set_css_rules(scope, sheet_obj, rules, *sheet_index);
+        array.set_index(scope, length as u32, sheet_obj.into());
+    }
+    array
+}
+
+/// (Re)builds `sheet.cssRules`. Rule objects are snapshots, taken again
+/// whenever the rule list changes, but their `style` finds its rule by id.
+fn set_css_rules(scope: &mut v8::HandleScope, sheet_obj: v8::Local<v8::Object>, rules: &[Rule], sheet_index: usize) {
+    let rules_array = v8::Array::new(scope, rules.len() as i32);
+    for (rule_index, rule) in rules.iter().enumerate() {
+        let rule_obj = v8::Object::new(scope);
+        set_string(scope, rule_obj, "selectorText", &rule.selector_text());
+        set_string(scope, rule_obj, "cssText", &rule.to_css());
+        let style_obj = rule_style_object(scope, rule, sheet_index);
+        let style_key = v8::String::new(scope, "style").unwrap().into();
+        rule_obj.set(scope, style_key, style_obj.into());
+        rules_array.set_index(scope, rule_index as u32, rule_obj.into());
+    }
+    let rules_key = v8::String::new(scope, "cssRules").unwrap().into();
+    sheet_obj.set(scope, rules_key, rules_array.into());
+}
+
+/// `rule.style`: the declarations under their JS and CSS names and the
+/// `CSSStyleDeclaration` methods.
+fn rule_style_object<'s>(scope: &mut v8::HandleScope<'s>, rule: &Rule, sheet_index: usize) -> v8::Local<'s, v8::Object> {
+    let style_obj = v8::Object::new(scope);
+    set_integer(scope, style_obj, SHEET_INDEX_KEY, sheet_index);
+    set_integer(scope, style_obj, RULE_ID_KEY, rule.id);
+    set_declarations(scope, style_obj, rule);
+    set_function(scope, style_obj, "getPropertyValue", rule_get_property_value_callback);
+    set_function(scope, style_obj, "getPropertyPriority", get_property_priority_callback);
+    set_function(scope, style_obj, "setProperty", set_property_callback);
+    set_function(scope, style_obj, "removeProperty", remove_property_callback);
+    style_obj
+}
+
+fn set_declarations(scope: &mut v8::HandleScope, style_obj: v8::Local<v8::Object>, rule: &Rule) {
+    for (property_name, property_value) in rule.declarations() {
+        for name in [property_name.to_str(), property_name.css_name()] {
+            set_string(scope, style_obj, name, &property_value.to_str());
+        }
+    }
+}
+
+/// Runs `f` on the rule a `rule.style` object belongs to, `None` once the
+/// rule is deleted. The sheets stay borrowed during `f`, so it mustn't call
+/// into JS.
+fn with_rule<R>(scope: &mut v8::HandleScope, style_obj: v8::Local<v8::Object>, f: impl FnOnce(&mut Rule) -> R) -> Option<R> {
+    let sheet_index = get_index(scope, style_obj, SHEET_INDEX_KEY)?;
+    let rule_id = get_index(scope, style_obj, RULE_ID_KEY)?;
+    let stylesheets = page_stylesheets(scope);
+    let mut stylesheets = stylesheets.borrow_mut();
+    let rule = stylesheets.get_mut(sheet_index)?.rule_by_id_mut(rule_id)?;
+    Some(f(rule))
+}
+
+fn string_arg(scope: &mut v8::HandleScope, args: &v8::FunctionCallbackArguments, index: i32) -> String {
+    let arg = args.get(index);
+    if arg.is_undefined() {
+        return String::new();
+    }
+    arg.to_string(scope).map(|arg| arg.to_rust_string_lossy(scope)).unwrap_or_default()
+}
+
+/// Runs a change to the rule behind `this` and brings the style object and
+/// the nodes up to date.
+fn edit_rule(scope: &mut v8::HandleScope, style_obj: v8::Local<v8::Object>, edit: impl FnOnce(&mut Rule) -> String) -> Option<String> {
+    let (before, after, result) = with_rule(scope, style_obj, |rule| {
+        let before = rule.clone();
+        let result = edit(rule);
+        (before, rule.clone(), result)
+    })?;
+    restyle_nodes(&page_stylesheets(scope).borrow());
+    for (property_name, _) in before.declarations() {
+        for name in [property_name.to_str(), property_name.css_name()] {
+            let key = v8::String::new(scope, name).unwrap().into();
+            style_obj.delete(scope, key);
+        }
+    }
+    set_declarations(scope, style_obj, &after);
+    Some(result)
+}
+
+/// `rule.style.getPropertyValue(name)`, read from the rule's declarations.
+fn rule_get_property_value_callback(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let name = string_arg(scope, &args, 0);
+    let value = with_rule(scope, args.this(), |rule| rule.get_property_value(&name)).unwrap_or_default();
+    rv.set(v8::String::new(scope, value.as_str()).unwrap().into());
+}
+
+/// `style.getPropertyPriority(name)`
+fn get_property_priority_callback(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let name = string_arg(scope, &args, 0);
+    let priority = with_rule(scope, args.this(), |rule| rule.get_property_priority(&name)).unwrap_or("");
+    rv.set(v8::String::new(scope, priority).unwrap().into());
+}
+
+/// `style.setProperty(name, value, priority)`
+fn set_property_callback(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut _rv: v8::ReturnValue,
+) {
+    let name = string_arg(scope, &args, 0);
+    let value = string_arg(scope, &args, 1);
+    let priority = string_arg(scope, &args, 2);
+    edit_rule(scope, args.this(), |rule| {
+        rule.set_property(&name, &value, &priority);
+        String::new()
+    });
+}
+
+/// `style.removeProperty(name)`, returns the old value.
+fn remove_property_callback(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let name = string_arg(scope, &args, 0);
+    let value = edit_rule(scope, args.this(), |rule| rule.remove_property(&name)).unwrap_or_default();
+    rv.set(v8::String::new(scope, value.as_str()).unwrap().into());
+}
+
+/// `sheet.insertRule(css, index)`, returns the index.
+fn insert_rule_callback(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut rv: v8::ReturnValue,
+) {
+    let sheet_obj = args.this();
+    let css = string_arg(scope, &args, 0);
+    let index = args.get(1).integer_value(scope).unwrap_or(0).max(0) as usize;
+    let sheet_index = match get_index(scope, sheet_obj, SHEET_INDEX_KEY) {
+        Some(sheet_index) => sheet_index,
+        None => return,
+    };
+    let stylesheets = page_stylesheets(scope);
+    let inserted = {
+        let mut stylesheets = stylesheets.borrow_mut();
+        let inserted = match stylesheets.get_mut(sheet_index) {
+            Some(stylesheet) => stylesheet.insert_rule(&css, index).map(|index| (index, stylesheet.rules.clone())),
+            None => return,
+        };
+        if inserted.is_ok() {
+            restyle_nodes(&stylesheets);
+        }
+        inserted
+    };
+    match inserted {
+        Ok((index, rules)) => {
+            set_css_rules(scope, sheet_obj, &rules, sheet_index);
+            rv.set(v8::Integer::new(scope, index as i32).into());
+        }
+        Err(error) => throw_error(scope, error.to_str()),
+    }
+}
+
+/// `sheet.deleteRule(index)`
+fn delete_rule_callback(
+    scope: &mut v8::HandleScope,
+    args: v8::FunctionCallbackArguments,
+    mut _rv: v8::ReturnValue,
+) {
+    let sheet_obj = args.this();
+    let index = args.get(0).integer_value(scope).unwrap_or(0).max(0) as usize;
+    let sheet_index = match get_index(scope, sheet_obj, SHEET_INDEX_KEY) {
+        Some(sheet_index) => sheet_index,
+        None => return,
+    };
+    let stylesheets = page_stylesheets(scope);
+    let deleted = {
+        let mut stylesheets = stylesheets.borrow_mut();
+        let deleted = match stylesheets.get_mut(sheet_index) {
+            Some(stylesheet) => stylesheet.delete_rule(index).map(|()| stylesheet.rules.clone()),
+            None => return,
+        };
+        if deleted.is_ok() {
+            restyle_nodes(&stylesheets);
+        }
+        deleted
+    };
+    match deleted {
+        Ok(rules) => set_css_rules(scope, sheet_obj, &rules, sheet_index),
+        Err(error) => throw_error(scope, error.to_str()),
+    }
+}
 
 fn add_document_structure<'a>(scope: &'a mut v8::ContextScope<v8::HandleScope>, nodes: &'a Vec<&'a Node>) -> v8::Local<'a, v8::Object> {
     let global = scope.get_current_context().global(scope);
     let document_obj = v8::Object::new(scope);
 
-    for node in nodes {
+    for (index, node) in nodes.iter().enumerate() {
         if let NodeType::Element(elem) = &node.node_type {
             let tag_key = v8::String::new(scope, elem.tag_name.as_str()).unwrap().into();
             let tag_obj = v8::Object::new(scope);
+            set_node_index(scope, tag_obj, index);
 
             let style_key = v8::String::new(scope, "style").unwrap().into();
-            let style_obj = v8::Object::new(scope);
-
-            for (property_name, property_value) in &node.styles {
-                let property_name_key = v8::String::new(scope, property_name.to_str()).unwrap().into();
-                let property_value_key = v8::String::new(scope, property_value.to_str().as_str()).unwrap();
-                style_obj.set(scope, property_name_key, property_value_key.into());
-            }
+            let style_obj = style_object(scope, node, index);
 
             tag_obj.set(scope, style_key, style_obj.into());
 
@@ -83,6 +404,15 @@ fn add_document_structure<'a>(scope: &'a mut v8::ContextScope<v8::HandleScope>,
     let get_by_id_key = v8::String::new(scope, "getElementById").unwrap().into();
     document_obj.set(scope, get_by_id_key, get_by_id_fn.into());
 
+    let get_computed_style_fn_template = v8::FunctionTemplate::new(scope, get_computed_style_callback);
+    let get_computed_style_fn = get_computed_style_fn_template.get_function(scope).unwrap();
+    let get_computed_style_key = v8::String::new(scope, "getComputedStyle").unwrap().into();
+    global.set(scope, get_computed_style_key, get_computed_style_fn.into());
+
+    let style_sheets_key = v8::String::new(scope, "styleSheets").unwrap().into();
+    let style_sheets = style_sheets_array(scope);
+    document_obj.set(scope, style_sheets_key, style_sheets.into());
+
     // Add the document object to the global object
     let document_key = v8::String::new(scope, "document").unwrap().into();
     global.set(scope, document_key, document_obj.into());
@@ -121,7 +451,9 @@ pub fn nodes_tree_to_vector(node: &Node) -> Vec<&Node>{
     nodes_out
 }
 
-pub fn init(js: &str, node: &Node) {
+/// Runs `js` with `node` as the document and `stylesheets` as
+/// `document.styleSheets`.
+pub fn init(js: &str, node: &Node, stylesheets: Stylesheets) {
     // init
     let platform = v8::new_default_platform(0, false);
     v8::V8::initialize_platform(platform.into());
@@ -129,6 +461,7 @@ pub fn init(js: &str, node: &Node) {
 
     // add isolate and context
     let isolate = &mut v8::Isolate::new(Default::default());
+    isolate.set_slot(stylesheets);
     let scope = &mut v8::HandleScope::new(isolate);
     let global = v8::ObjectTemplate::new(scope);
     let context = v8::Context::new_from_template(scope, global);
@@ -1,30 +1,46 @@
+use std::collections::HashMap;
 use std::collections::hash_set::Union;
 use std::fmt;
-use crate::css::{Length, PropertyName, PropertyValue};
+use std::ops::Range;
+use crate::css::{BorderStyle, GridLine, Track, TrackBreadth, TrackList, Viewport};
 use crate::{css, dom, layout};
 use crate::css_parser::CssParser;
 use crate::dom::{ElementData, NodeType};
+use crate::style::{Alignment, ComputedStyle, FlexBasis, FlexDirection, FlexWrap, FontStyle, Sides, Size, TextAlign, WhiteSpace};
 use crate::html_parser::HtmlParser;
 use crate::render::render;
 
 #[derive(Clone, PartialEq)]
 pub struct LayoutBox {
+    /// Margin box: the space the box takes in its parent's flow.
     pub dimensions: Dimensions,
+    /// Border box: the area painted with the background and the border.
+    /// For inline boxes and text, the bounding box of their fragments.
     pub actual_dimensions: Dimensions,
+    /// Border boxes of an inline box on each of its lines, line-height boxes
+    /// of the runs of a text box.
+    pub fragments: Vec<Dimensions>,
     // for rendering
-    pub content: Option<Content>,
+    /// Runs of text, one per line.
+    pub content: Vec<Content>,
+    /// Source text of a text box.
+    pub text: String,
     pub color: Color,
     pub background_color: Color,
     pub name: String,
     pub margin: Indentations,
     pub padding: Indentations,
+    /// Border widths, inside `actual_dimensions` like the padding.
+    pub border: Indentations,
+    pub border_style: Sides<BorderStyle>,
+    pub border_color: Sides<Color>,
+    pub font: Font,
     pub box_type: BoxType,
     pub children: Vec<LayoutBox>,
-    v_elements: i16,
-    h_elements: i16,
+    /// Computed style of the element or text node the box was made for.
+    pub style: ComputedStyle,
 }
 
-
 #[derive(Clone, Default, PartialEq, Debug)]
 pub struct Content {
     pub x: i16,
@@ -33,6 +49,67 @@ pub struct Content {
 }
 
 
+/// Advance widths of printable ASCII in thousandths of an em, from the
+/// metrics of Helvetica, which the sans-serif face text is drawn with matches.
+const ADVANCES: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/// Height of the glyphs above the baseline, in em.
+const ASCENT: f32 = 0.8;
+
+/// What text in a box is drawn with.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Font {
+    pub size: f32,
+    pub weight: f32,
+    pub italic: bool,
+    /// Used line height in px.
+    pub line_height: f32,
+}
+
+impl Default for Font {
+    fn default() -> Self {
+        Font { size: 16.0, weight: 400.0, italic: false, line_height: 16.0 * 1.2 }
+    }
+}
+
+impl Font {
+    pub fn from_style(style: &ComputedStyle) -> Self {
+        Font {
+            size: style.font_size,
+            weight: style.font_weight,
+            italic: style.font_style != FontStyle::Normal,
+            line_height: style.line_height.resolve(style.font_size),
+        }
+    }
+
+    /// Bold faces are drawn for `600` and up.
+    pub fn is_bold(&self) -> bool {
+        self.weight >= 600.0
+    }
+
+    /// Width of `text` in px.
+    pub fn text_width(&self, text: &str) -> f32 {
+        let advance = |c: char| match c {
+            ' '..='~' => ADVANCES[c as usize - ' ' as usize],
+            _ => 556,
+        };
+        text.chars().map(|c| advance(c) as f32).sum::<f32>() * self.size / 1000.0
+    }
+
+    /// Distance from the top of the line-height box to the baseline: half
+    /// the leading, then the ascent.
+    fn baseline(&self) -> f32 {
+        (self.line_height - self.size) / 2.0 + ASCENT * self.size
+    }
+}
+
 #[derive(Clone, Default, PartialEq, Debug)]
 pub struct Dimensions {
     pub x: i16,
@@ -41,6 +118,24 @@ pub struct Dimensions {
     pub height: i16,
 }
 
+impl Dimensions {
+    /// Rounds a rectangle laid out in f32 to whole px. Coordinates past the
+    /// range of i16 saturate.
+    fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Dimensions { x: x.round() as i16, y: y.round() as i16, width: width.round() as i16, height: height.round() as i16 }
+    }
+
+    /// The right edge, in f32 as it may be past the range of i16.
+    fn right(&self) -> f32 {
+        self.x as f32 + self.width as f32
+    }
+
+    /// The bottom edge, in f32 as it may be past the range of i16.
+    fn bottom(&self) -> f32 {
+        self.y as f32 + self.height as f32
+    }
+}
+
 impl fmt::Debug for LayoutBox {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}: {{", self.name)?;
@@ -52,10 +147,16 @@ impl fmt::Debug for LayoutBox {
 }
 
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum BoxType {
     Block,
     Inline,
+    /// Placed on lines as a whole, laid out inside like a block.
+    InlineBlock,
+    /// A text node, its words are placed on the lines of its block.
+    Text,
+    /// The bullet or number of a list item, outside of its box.
+    Marker,
 }
 
 
@@ -67,6 +168,18 @@ pub struct Indentations {
     pub left: i16,
 }
 
+impl Indentations {
+    /// Left plus right, in f32 as the sum may be past the range of i16.
+    fn horizontal(&self) -> f32 {
+        self.left as f32 + self.right as f32
+    }
+
+    /// Top plus bottom, in f32 as the sum may be past the range of i16.
+    fn vertical(&self) -> f32 {
+        self.top as f32 + self.bottom as f32
+    }
+}
+
 
 #[derive(Clone, Default, PartialEq, Debug)]
 pub struct Color {
@@ -85,232 +198,1944 @@ impl Color {
     pub fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
         Color { r, g, b, a }
     }
+
+    pub fn from_css(color: &css::Color) -> Self {
+        let (r, g, b, a) = color.get_rgba();
+        Color::new(r, g, b, a)
+    }
 }
 
 impl Default for LayoutBox {
     fn default() -> LayoutBox {
         LayoutBox {
-            content: None,
+            content: vec![],
+            text: String::new(),
             dimensions: Dimensions::default(),
             actual_dimensions: Dimensions::default(),
+            fragments: vec![],
             color: Color::new(0, 0, 0, 255),
             margin: Indentations::default(),
             background_color: Color::new(255, 255, 255, 255),
             name: String::from("default"),
             box_type: BoxType::Block,
             padding: Indentations::default(),
+            border: Indentations::default(),
+            border_style: Sides::default(),
+            border_color: Sides::default(),
+            font: Font::default(),
             children: vec![],
-            v_elements: 0,
-            h_elements: 0,
+            style: ComputedStyle::default(),
         }
     }
 }
 
+/// The rectangle children are laid out in, the content box of their parent.
+/// `height` is `None` while the parent's height depends on its content.
+#[derive(Clone, Copy, Debug)]
+struct ContainingBlock {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: Option<f32>,
+}
+
+/// Adjoining vertical margins, which collapse into one: the largest
+/// positive margin plus the most negative one (CSS 2.1 8.3.1).
+#[derive(Clone, Copy, Debug, Default)]
+struct CollapsedMargin {
+    positive: f32,
+    negative: f32,
+}
+
+impl CollapsedMargin {
+    fn new(margin: f32) -> Self {
+        CollapsedMargin { positive: margin.max(0.0), negative: margin.min(0.0) }
+    }
+
+    fn adjoin(self, other: CollapsedMargin) -> Self {
+        CollapsedMargin { positive: self.positive.max(other.positive), negative: self.negative.min(other.negative) }
+    }
+
+    fn resolve(self) -> f32 {
+        self.positive + self.negative
+    }
+}
+
 impl LayoutBox {
     pub fn build_layout_tree(node: &dom::Node) -> Vec<LayoutBox> {
-        let mut base = LayoutBox::default();
-        base.dimensions.width = crate::render::WIDTH as i16;
-        base.actual_dimensions.width = crate::render::WIDTH as i16;
-        let mut body = LayoutBox::build_box(node, &mut base,
-                                            &ElementData::default(), 0);
-        body.name = if let NodeType::Element(element_data) = &node.node_type {
-            element_data.tag_name.clone()
-        } else {
+        LayoutBox::build_layout_tree_with_viewport(node, &Viewport::default())
+    }
+
+    /// Lays `node` out in a viewport of the given size.
+    pub fn build_layout_tree_with_viewport(node: &dom::Node, viewport: &Viewport) -> Vec<LayoutBox> {
+        if !matches!(node.node_type, NodeType::Element(_)) {
             panic!("Root node must be an element");
+        }
+        let mut root = match LayoutBox::from_node(node) {
+            Some(root) => root,
+            None => return Vec::new(),
+        };
+        // the initial containing block is the viewport
+        let viewport = ContainingBlock { x: 0.0, y: 0.0, width: viewport.width, height: Some(viewport.height) };
+        // an inline root is laid out as a block
+        root.layout_block(&viewport, CollapsedMargin::default());
+        vec![root]
+    }
+
+    /// Builds the box tree of `node`: a box for every element and text node,
+    /// none for `display: none` subtrees and empty text.
+    fn from_node(node: &dom::Node) -> Option<LayoutBox> {
+        let style = &node.computed;
+        let mut box_ = LayoutBox::default();
+        match &node.node_type {
+            NodeType::Element(_) if style.display == css::DisplayType::None => return None,
+            NodeType::Element(element_data) => {
+                box_.name = element_data.tag_name.clone();
+                box_.box_type = match style.display {
+                    css::DisplayType::Inline => BoxType::Inline,
+                    css::DisplayType::InlineBlock | css::DisplayType::InlineFlex | css::DisplayType::InlineGrid => BoxType::InlineBlock,
+                    _ => BoxType::Block,
+                };
+            }
+            NodeType::Text(text) if !text.is_empty() => {
+                box_.name = String::from("text");
+                box_.box_type = BoxType::Text;
+                box_.text = text.clone();
+            }
+            _ => return None,
+        }
+        box_.color = Color::from_css(&style.color);
+        box_.background_color = Color::from_css(&style.background_color);
+        box_.border = Indentations {
+            top: style.border_width.top.round() as i16,
+            right: style.border_width.right.round() as i16,
+            bottom: style.border_width.bottom.round() as i16,
+            left: style.border_width.left.round() as i16,
         };
-        body.children = LayoutBox::build_layout_tree_helper(&node.children, &mut body, 0);
-        LayoutBox::expand_blocks_that_have_text(&mut body);
-        vec![body]
+        box_.border_style = style.border_style.clone();
+        box_.border_color = Sides {
+            top: Color::from_css(&style.border_color.top),
+            right: Color::from_css(&style.border_color.right),
+            bottom: Color::from_css(&style.border_color.bottom),
+            left: Color::from_css(&style.border_color.left),
+        };
+        box_.font = Font::from_style(style);
+        box_.style = style.clone();
+        if box_.box_type != BoxType::Text {
+            let children = node.children.iter().filter_map(LayoutBox::from_node);
+            if box_.is_flex_container() || box_.is_grid_container() {
+                box_.children = children.collect();
+                box_.wrap_items();
+            } else {
+                box_.children = children.flat_map(LayoutBox::split_around_blocks).collect();
+                if box_.box_type != BoxType::Inline {
+                    box_.wrap_inline_runs();
+                }
+            }
+            let mut ordinal = 0;
+            for child in &mut box_.children {
+                if child.style.display == css::DisplayType::ListItem {
+                    ordinal += 1;
+                    child.add_marker(ordinal);
+                }
+            }
+        }
+        Some(box_)
     }
 
+    /// Breaks an inline box with block-level boxes in it around them, into
+    /// the blocks and the parts of the inline box before, between and after
+    /// them (CSS 2.1 9.2.1.1). The left edge goes with the first part, the
+    /// right edge with the last.
+    fn split_around_blocks(self) -> Vec<LayoutBox> {
+        fn contains_block(box_: &LayoutBox) -> bool {
+            box_.box_type == BoxType::Inline && box_.children.iter()
+                .any(|child| child.box_type == BoxType::Block || contains_block(child))
+        }
+        if !contains_block(&self) {
+            return vec![self];
+        }
+        let shell = LayoutBox { children: Vec::new(), ..self.clone() };
+        let mut pieces = vec![shell.clone()];
+        for child in self.children {
+            for part in child.split_around_blocks() {
+                if part.box_type == BoxType::Block {
+                    pieces.push(part);
+                    pieces.push(shell.clone());
+                } else {
+                    pieces.last_mut().unwrap().children.push(part);
+                }
+            }
+        }
+        let parts = pieces.iter().filter(|piece| piece.box_type == BoxType::Inline).count();
+        for (part, piece) in pieces.iter_mut().filter(|piece| piece.box_type == BoxType::Inline).enumerate() {
+            if part > 0 {
+                piece.border.left = 0;
+                piece.style.margin.left = Size::Length(css::Length::Px(0.0));
+                piece.style.padding.left = css::Length::Px(0.0);
+            }
+            if part < parts - 1 {
+                piece.border.right = 0;
+                piece.style.margin.right = Size::Length(css::Length::Px(0.0));
+                piece.style.padding.right = css::Length::Px(0.0);
+            }
+        }
+        pieces.retain(|piece| piece.box_type == BoxType::Block || !piece.children.is_empty());
+        pieces
+    }
+
+    /// Wraps the runs of inline-level children of a box that also has
+    /// block-level ones in anonymous block boxes, so that every block
+    /// contains either only blocks or only inline content (CSS 2.1 9.2.1.1).
+    /// Text is an anonymous inline box already.
+    fn wrap_inline_runs(&mut self) {
+        if !self.children.iter().any(|child| child.box_type == BoxType::Block) {
+            return;
+        }
+        let (mut children, mut run) = (Vec::new(), Vec::new());
+        for child in std::mem::take(&mut self.children) {
+            if child.is_inline_level() {
+                run.push(child);
+                continue;
+            }
+            self.wrap_run(&mut run, &mut children);
+            children.push(child);
+        }
+        self.wrap_run(&mut run, &mut children);
+        self.children = children;
+    }
+
+    /// Moves a run of inline-level boxes into an anonymous block at the end
+    /// of `boxes`, unless it is only collapsible white space, which doesn't
+    /// show between blocks.
+    fn wrap_run(&self, run: &mut Vec<LayoutBox>, boxes: &mut Vec<LayoutBox>) {
+        if run.iter().all(LayoutBox::is_collapsible_space) {
+            run.clear();
+            return;
+        }
+        let mut block = self.anonymous_block(run.remove(0));
+        block.children.append(run);
+        boxes.push(block);
+    }
+
+    /// Text of white space only, which collapses to one space or less.
+    fn is_collapsible_space(&self) -> bool {
+        self.box_type == BoxType::Text && self.text.trim().is_empty()
+            && !matches!(self.style.white_space, WhiteSpace::Pre | WhiteSpace::PreWrap | WhiteSpace::BreakSpaces)
+    }
 
-    fn expand_blocks_that_have_text(parent: &mut LayoutBox) {
-        let mut count: i16 = 0;
-        for i in 0..parent.children.len() {
-            if parent.children[i].content.is_some() {
-                parent.children[i].content.as_mut().unwrap().y += 20 * count;
-                count += 1;
+    /// Makes the children of a flex or grid container its items (CSS Flexbox
+    /// 4, CSS Grid 6.1): inline-level children are blockified and each run of
+    /// text is wrapped in an anonymous block.
+    fn wrap_items(&mut self) {
+        let (mut items, mut run) = (Vec::new(), Vec::new());
+        for mut child in std::mem::take(&mut self.children) {
+            if child.box_type == BoxType::Text {
+                run.push(child);
+                continue;
             }
-            if i < parent.children.len() - 1 {
-                parent.children[i + 1].actual_dimensions.y += 20 * count;
+            self.wrap_run(&mut run, &mut items);
+            if child.box_type == BoxType::Inline {
+                child.wrap_inline_runs();
+            }
+            if child.is_inline_level() {
+                child.box_type = BoxType::Block;
+            }
+            items.push(child);
+        }
+        self.wrap_run(&mut run, &mut items);
+        self.children = items;
+    }
+
+    /// An anonymous block box around `child`, styled by inheritance only.
+    fn anonymous_block(&self, child: LayoutBox) -> LayoutBox {
+        let style = self.style.anonymous_child(css::DisplayType::Block);
+        LayoutBox {
+            name: String::from("anonymous"),
+            box_type: BoxType::Block,
+            color: Color::from_css(&style.color),
+            background_color: Color::from_css(&style.background_color),
+            font: Font::from_style(&style),
+            style,
+            children: vec![child],
+            ..LayoutBox::default()
+        }
+    }
+
+    fn is_flex_container(&self) -> bool {
+        matches!(self.style.display, css::DisplayType::Flex | css::DisplayType::InlineFlex)
+    }
+
+    fn is_grid_container(&self) -> bool {
+        matches!(self.style.display, css::DisplayType::Grid | css::DisplayType::InlineGrid)
+    }
+
+    /// Puts the marker of the `ordinal`th list item in front of its children.
+    fn add_marker(&mut self, ordinal: usize) {
+        if let Some(text) = self.style.list_style_type.marker(ordinal) {
+            self.children.insert(0, LayoutBox {
+                name: String::from("marker"),
+                box_type: BoxType::Marker,
+                text,
+                color: self.color.clone(),
+                background_color: Color::new(0, 0, 0, 0),
+                font: self.font.clone(),
+                style: self.style.clone(),
+                ..LayoutBox::default()
+            });
+        }
+    }
+
+    fn is_inline_level(&self) -> bool {
+        matches!(self.box_type, BoxType::Inline | BoxType::InlineBlock | BoxType::Text)
+    }
+
+    /// Block-level box in normal flow (CSS 2.1 10.3.3 and 10.6.3): it fills
+    /// the width of the containing block and is as high as its content,
+    /// unless `width` and `height` say otherwise. Its vertical margins
+    /// collapse with adjoining ones (8.3.1).
+    fn layout_block(&mut self, containing_block: &ContainingBlock, margin: CollapsedMargin) -> CollapsedMargin {
+        let width = self.calculate_block_width(containing_block.width);
+        // vertical margins and padding are percentages of the width too
+        let percent_base = containing_block.width;
+        let style = &self.style;
+        self.margin.top = style.margin.top.resolve(percent_base).unwrap_or(0.0).round() as i16;
+        self.margin.bottom = style.margin.bottom.resolve(percent_base).unwrap_or(0.0).round() as i16;
+        self.padding.top = style.padding.top.resolve(percent_base).round() as i16;
+        self.padding.bottom = style.padding.bottom.resolve(percent_base).round() as i16;
+
+        let x = containing_block.x + self.margin.left as f32 + self.border.left as f32 + self.padding.left as f32;
+        let height = self.style.height.resolve_height(containing_block.height);
+        let margin = margin.adjoin(CollapsedMargin::new(self.margin.top as f32));
+        let margin_bottom = CollapsedMargin::new(self.margin.bottom as f32);
+        // without a border or padding in between, the margins adjoin those of the first and last
+        // child, unless the box is an inline-block, which keeps the margins of its content inside
+        let contained = self.box_type == BoxType::InlineBlock || self.is_flex_container() || self.is_grid_container();
+        let collapses_top = !contained && self.border.top == 0 && self.padding.top == 0;
+        let collapses_bottom = !contained && height.is_none() && self.border.bottom == 0 && self.padding.bottom == 0;
+
+        if !collapses_top {
+            let y = containing_block.y + margin.resolve() + self.border.top as f32 + self.padding.top as f32;
+            let content = ContainingBlock { x, y, width, height };
+            let (_, bottom, below) = self.layout_contents(&content, CollapsedMargin::default());
+            let below = self.finish_block(&content, bottom, below, collapses_bottom);
+            self.place_marker();
+            return below;
+        }
+        let (top, bottom, below) = self.layout_contents(&ContainingBlock { x, width, height, ..*containing_block }, margin);
+        let below = match top {
+            Some(y) => self.finish_block(&ContainingBlock { x, y, width, height }, bottom, below, collapses_bottom),
+            // only empty children: their margins and this box's top margin are one
+            None => {
+                let y = containing_block.y + below.resolve();
+                self.set_content_box(x, y, width, height.unwrap_or(0.0).max(0.0));
+                if collapses_bottom { below.adjoin(margin_bottom) } else { margin_bottom }
+            }
+        };
+        self.place_marker();
+        below
+    }
+
+    /// Puts the marker of a list item left of its box, on the baseline of
+    /// its first line.
+    fn place_marker(&mut self) {
+        if !matches!(self.children.first(), Some(child) if child.box_type == BoxType::Marker) {
+            return;
+        }
+        let baseline = self.baseline(false)
+            .unwrap_or(self.content_box().y as f32 + self.font.baseline());
+        let left = self.actual_dimensions.x as f32;
+        let marker = &mut self.children[0];
+        // the marker and a space end where the box starts
+        let width = marker.font.text_width(&marker.text) + marker.font.text_width(" ");
+        let x = (left - width).round() as i16;
+        let y = (baseline - marker.font.baseline()).round() as i16;
+        marker.content = vec![Content { x, y, text: marker.text.clone() }];
+        marker.actual_dimensions = Dimensions {
+            x, y, width: width.round() as i16, height: marker.font.line_height.round() as i16,
+        };
+        marker.dimensions = marker.actual_dimensions.clone();
+    }
+
+    /// Baseline of the first or the `last` line of text in the box.
+    fn baseline(&self, last: bool) -> Option<f32> {
+        match self.box_type {
+            BoxType::Marker => None,
+            BoxType::Text => {
+                let content = if last { self.content.last() } else { self.content.first() };
+                content.map(|content| content.y as f32 + self.font.baseline())
             }
-            parent.actual_dimensions.height += parent.children[i].actual_dimensions.height;
-            LayoutBox::expand_blocks_that_have_text(&mut parent.children[i]);
+            _ if last => self.children.iter().rev().find_map(|child| child.baseline(last)),
+            _ => self.children.iter().find_map(|child| child.baseline(last)),
         }
     }
 
+    /// Sets the content box from where it starts and the bottom of the last
+    /// child, and returns the margins left open below the box.
+    fn finish_block(&mut self, content: &ContainingBlock, bottom: f32, below: CollapsedMargin,
+                    collapses_bottom: bool) -> CollapsedMargin {
+        let margin_bottom = CollapsedMargin::new(self.margin.bottom as f32);
+        let (height, below) = match content.height {
+            Some(height) => (height, margin_bottom),
+            None if collapses_bottom => (bottom - content.y, below.adjoin(margin_bottom)),
+            None => (bottom + below.resolve() - content.y, margin_bottom),
+        };
+        self.set_content_box(content.x, content.y, content.width, height.max(0.0));
+        below
+    }
 
-    fn build_layout_tree_helper(nodes: &Vec<dom::Node>, parent: &mut LayoutBox, element_number: usize) -> Vec<LayoutBox> {
-        let mut boxes = Vec::new();
-        for (i, node) in nodes.iter().enumerate() {
-            match &node.node_type {
-                NodeType::Element(element) => {
-                    let mut box_ = LayoutBox::build_box(node, parent, element, i);
+    /// Solves `margin-left + border-left + padding-left + width + padding-right
+    /// + border-right + margin-right = containing block width` for what is
+    /// `auto`. Sets the horizontal margins and padding and returns the width.
+    fn calculate_block_width(&mut self, containing_width: f32) -> f32 {
+        let style = &self.style;
+        let padding_left = style.padding.left.resolve(containing_width).round();
+        let padding_right = style.padding.right.resolve(containing_width).round();
+        let edges = padding_left + padding_right + self.border.horizontal();
+        let width = style.width.resolve(containing_width);
+        let mut margin_left = style.margin.left.resolve(containing_width);
+        let mut margin_right = style.margin.right.resolve(containing_width);
+        self.padding.left = padding_left as i16;
+        self.padding.right = padding_right as i16;
+        if self.box_type == BoxType::InlineBlock {
+            // auto margins are zero and an auto width shrinks to fit (CSS 2.1 10.3.9)
+            let (margin_left, margin_right) = (margin_left.unwrap_or(0.0), margin_right.unwrap_or(0.0));
+            let width = width.unwrap_or_else(|| {
+                let (min, max) = self.content_widths();
+                max.min(containing_width - margin_left - margin_right - edges).max(min)
+            });
+            self.margin.left = margin_left.round() as i16;
+            self.margin.right = margin_right.round() as i16;
+            return width.max(0.0);
+        }
+        if let Some(width) = width {
+            // a box too wide for its containing block has no auto margins
+            if margin_left.unwrap_or(0.0) + edges + width + margin_right.unwrap_or(0.0) > containing_width {
+                margin_left = margin_left.or(Some(0.0));
+                margin_right = margin_right.or(Some(0.0));
+            }
+        }
+        let (width, margin_left, margin_right) = match (width, margin_left, margin_right) {
+            // over-constrained, margin-right gives way
+            (Some(width), Some(left), Some(_)) | (Some(width), Some(left), None) =>
+                (width, left, containing_width - left - edges - width),
+            (Some(width), None, Some(right)) => (width, containing_width - right - edges - width, right),
+            // two auto margins center the box
+            (Some(width), None, None) => {
+                let margin = (containing_width - edges - width) / 2.0;
+                (width, margin, margin)
+            }
+            (None, left, right) => {
+                let (left, right) = (left.unwrap_or(0.0), right.unwrap_or(0.0));
+                let width = containing_width - left - edges - right;
+                if width >= 0.0 { (width, left, right) } else { (0.0, left, right + width) }
+            }
+        };
+        self.margin.left = margin_left.round() as i16;
+        self.margin.right = margin_right.round() as i16;
+        width.max(0.0)
+    }
 
-                    box_.children = LayoutBox::build_layout_tree_helper(&node.children, &mut box_, element_number + 1);
-                    boxes.push(box_);
+    /// Narrowest and widest the content can be laid out without
+    /// overflowing: broken at every space, and not broken at all.
+    fn content_widths(&self) -> (f32, f32) {
+        if self.is_flex_container() {
+            return self.flex_content_widths();
+        }
+        if self.is_grid_container() {
+            return self.grid_content_widths();
+        }
+        let (mut min, mut max, mut line) = (0.0f32, 0.0f32, 0.0f32);
+        // a space at the edge of a text counts between content on the line only
+        let mut space = 0.0;
+        for child in &self.children {
+            let (child_min, child_max) = child.intrinsic_widths();
+            min = min.max(child_min);
+            if child.is_inline_level() {
+                let text = Some(child.text.as_str()).filter(|_| child.box_type == BoxType::Text);
+                if text.is_some_and(|text| text.starts_with(char::is_whitespace)) {
+                    space = child.font.text_width(" ");
                 }
-                NodeType::Text(text) => {
-                    parent.set_content(text);
+                if child_max > 0.0 {
+                    line += if line > 0.0 { space } else { 0.0 } + child_max;
+                    space = 0.0;
                 }
-                _ => {}
+                if text.is_some_and(|text| text.ends_with(char::is_whitespace)) {
+                    space = child.font.text_width(" ");
+                }
+            } else {
+                max = max.max(line).max(child_max);
+                (line, space) = (0.0, 0.0);
             }
         }
-        boxes
+        (min, max.max(line))
     }
 
-    fn build_box(element: &dom::Node, parent: &mut LayoutBox, element_data: &ElementData, element_number: usize) -> LayoutBox {
-        let mut box_ = LayoutBox::default();
-        for (name, value) in &element.styles {
-            match name {
-                PropertyName::Color => {
-                    if let PropertyValue::Color(color) = &value {
-                        let color = color.get_rgb();
-                        box_.color = Color::new(color.0, color.1, color.2, 255);
+    /// `content_widths` of the margin box. Percentages and `auto` need the
+    /// containing block and count as zero.
+    fn intrinsic_widths(&self) -> (f32, f32) {
+        match self.box_type {
+            BoxType::Marker => (0.0, 0.0),
+            BoxType::Text => {
+                let words: Vec<&str> = self.text.split_whitespace().collect();
+                let longest = words.iter().map(|word| self.font.text_width(word)).fold(0.0, f32::max);
+                (longest, self.font.text_width(&words.join(" ")))
+            }
+            _ => {
+                let style = &self.style;
+                let edges = style.margin.left.resolve(0.0).unwrap_or(0.0) + style.margin.right.resolve(0.0).unwrap_or(0.0)
+                    + style.padding.left.resolve(0.0) + style.padding.right.resolve(0.0)
+                    + self.border.horizontal();
+                let (min, max) = match &style.width {
+                    Size::Length(css::Length::Px(width)) => (*width, *width),
+                    _ => self.content_widths(),
+                };
+                (min + edges, max + edges)
+            }
+        }
+    }
+
+    /// `content_widths` of a flex container: items in a row sit side by side,
+    /// on one line unless they wrap.
+    fn flex_content_widths(&self) -> (f32, f32) {
+        let widths: Vec<(f32, f32)> = self.children.iter()
+            .filter(|child| child.box_type != BoxType::Marker)
+            .map(LayoutBox::intrinsic_widths)
+            .collect();
+        let widest = |widths: &mut dyn Iterator<Item=f32>| widths.fold(0.0, f32::max);
+        let (min, max) = (widest(&mut widths.iter().map(|w| w.0)), widest(&mut widths.iter().map(|w| w.1)));
+        if matches!(self.style.flex_direction, FlexDirection::Column | FlexDirection::ColumnReverse) {
+            return (min, max);
+        }
+        let gaps = self.style.column_gap.resolve(0.0) * widths.len().saturating_sub(1) as f32;
+        let max = widths.iter().map(|w| w.1).sum::<f32>() + gaps;
+        match self.style.flex_wrap {
+            FlexWrap::NoWrap => (widths.iter().map(|w| w.0).sum::<f32>() + gaps, max),
+            _ => (min, max),
+        }
+    }
+
+    /// `content_widths` of a grid container: its columns sized to the
+    /// narrowest and to the widest the items in them can be.
+    fn grid_content_widths(&self) -> (f32, f32) {
+        let (areas, columns, _) = self.place_grid_items();
+        let gap = self.style.column_gap.resolve(0.0);
+        let widths: Vec<(Range<usize>, f32, f32)> = areas.iter()
+            .map(|area| {
+                let (min, max) = self.children[area.index].intrinsic_widths();
+                (area.column.clone(), min, max)
+            })
+            .collect();
+        let min_widths: Vec<(Range<usize>, f32, f32)> = widths.iter()
+            .map(|(columns, min, _)| (columns.clone(), *min, *min))
+            .collect();
+        let width = |widths: &[(Range<usize>, f32, f32)]| {
+            size_grid_tracks(&columns, None, gap, widths, false).iter().sum::<f32>()
+                + gap * columns.len().saturating_sub(1) as f32
+        };
+        (width(&min_widths), width(&widths))
+    }
+
+    /// Lays the children out in `content`: in normal flow, or as the items
+    /// of a flex or grid container. Returns the same as `layout_children`.
+    fn layout_contents(&mut self, content: &ContainingBlock, margin: CollapsedMargin) -> (Option<f32>, f32, CollapsedMargin) {
+        if self.is_flex_container() {
+            let bottom = self.layout_flex(content);
+            return (Some(content.y), bottom, margin);
+        }
+        if self.is_grid_container() {
+            let bottom = self.layout_grid(content);
+            return (Some(content.y), bottom, margin);
+        }
+        self.layout_children(content, margin)
+    }
+
+    /// Lays out a box that keeps the margins of its children inside, such as
+    /// a flex item, in its content box `content`. An auto height fits the
+    /// content.
+    fn layout_inside(&mut self, content: &ContainingBlock) {
+        let (_, bottom, below) = self.layout_contents(content, CollapsedMargin::default());
+        let height = content.height.unwrap_or(bottom + below.resolve() - content.y);
+        self.set_content_box(content.x, content.y, content.width, height.max(0.0));
+        self.place_marker();
+    }
+
+    /// Resolves the margins and padding of a box placed by a formatting
+    /// context other than normal flow, against the width of its containing
+    /// block. Auto margins are zero until that context fills them in, the
+    /// sides where they are come back as `true`.
+    fn resolve_edges(&mut self, containing_width: f32) -> Sides<bool> {
+        let style = &self.style;
+        let margin = Sides {
+            top: style.margin.top.resolve(containing_width),
+            right: style.margin.right.resolve(containing_width),
+            bottom: style.margin.bottom.resolve(containing_width),
+            left: style.margin.left.resolve(containing_width),
+        };
+        let px = |length: Option<f32>| length.unwrap_or(0.0).round() as i16;
+        self.margin = Indentations { top: px(margin.top), right: px(margin.right), bottom: px(margin.bottom), left: px(margin.left) };
+        self.padding = Indentations {
+            top: px(Some(style.padding.top.resolve(containing_width))),
+            right: px(Some(style.padding.right.resolve(containing_width))),
+            bottom: px(Some(style.padding.bottom.resolve(containing_width))),
+            left: px(Some(style.padding.left.resolve(containing_width))),
+        };
+        Sides {
+            top: margin.top.is_none(),
+            right: margin.right.is_none(),
+            bottom: margin.bottom.is_none(),
+            left: margin.left.is_none(),
+        }
+    }
+
+    /// Lays the children of a flex container out as flex items in its
+    /// content box `content` (CSS Flexbox 9) and returns the bottom of the
+    /// content.
+    fn layout_flex(&mut self, content: &ContainingBlock) -> f32 {
+        let style = self.style.clone();
+        let row = matches!(style.flex_direction, FlexDirection::Row | FlexDirection::RowReverse);
+        let reverse = matches!(style.flex_direction, FlexDirection::RowReverse | FlexDirection::ColumnReverse);
+        let wrap = style.flex_wrap != FlexWrap::NoWrap;
+        let wrap_reverse = style.flex_wrap == FlexWrap::WrapReverse;
+        let inner_main = if row { Some(content.width) } else { content.height };
+        let inner_cross = if row { content.height } else { Some(content.width) };
+        // percentages of an auto height are zero
+        let (column_gap, row_gap) = (style.column_gap.resolve(content.width), style.row_gap.resolve(content.height.unwrap_or(0.0)));
+        let (main_gap, cross_gap) = if row { (column_gap, row_gap) } else { (row_gap, column_gap) };
+
+        // 9.2: flex base sizes and hypothetical main sizes, in `order`
+        let mut indices: Vec<usize> = (0..self.children.len())
+            .filter(|&index| self.children[index].box_type != BoxType::Marker)
+            .collect();
+        indices.sort_by_key(|&index| self.children[index].style.order);
+        let mut items: Vec<FlexItem> = indices.into_iter()
+            .map(|index| FlexItem::new(index, &mut self.children[index], content, &style))
+            .collect();
+
+        // 9.3: collect the items into lines
+        let mut lines: Vec<Range<usize>> = Vec::new();
+        let (mut start, mut used) = (0, 0.0);
+        for (index, item) in items.iter().enumerate() {
+            let outer = item.hypothetical + item.main_edges;
+            if wrap && index > start && inner_main.is_some_and(|main| used + main_gap + outer > main) {
+                lines.push(start..index);
+                start = index;
+            }
+            used = if index == start { outer } else { used + main_gap + outer };
+        }
+        if !items.is_empty() {
+            lines.push(start..items.len());
+        }
+
+        // 9.7: grow or shrink the items to fill their lines
+        let gaps = |line: &Range<usize>| main_gap * (line.len() - 1) as f32;
+        let outer_main = |items: &[FlexItem]| items.iter().map(|item| item.target + item.main_edges).sum::<f32>();
+        for line in &lines {
+            let line_items = &mut items[line.clone()];
+            let available = match inner_main {
+                Some(main) => main - gaps(line),
+                None => line_items.iter().map(|item| item.hypothetical + item.main_edges).sum(),
+            };
+            resolve_flexible_lengths(line_items, available);
+        }
+        let main_size = inner_main.unwrap_or_else(|| lines.iter()
+            .map(|line| outer_main(&items[line.clone()]) + gaps(line))
+            .fold(0.0, f32::max));
+
+        // 9.4: cross sizes of the items and the lines
+        if row {
+            for item in &mut items {
+                let child = &mut self.children[item.index];
+                let height = child.style.height.resolve_height(content.height);
+                child.layout_inside(&ContainingBlock { x: 0.0, y: 0.0, width: item.target, height });
+                item.cross = child.content_box().height as f32;
+                // the baseline of an item without text is its bottom border edge
+                item.baseline = child.baseline(false)
+                    .unwrap_or(child.actual_dimensions.bottom())
+                    - child.dimensions.y as f32;
+            }
+        }
+        let baselines: Vec<f32> = lines.iter()
+            .map(|line| items[line.clone()].iter()
+                .filter(|item| item.aligns_baseline(row))
+                .map(|item| item.baseline)
+                .fold(0.0, f32::max))
+            .collect();
+        let mut line_sizes: Vec<f32> = lines.iter().zip(&baselines)
+            .map(|(line, above)| items[line.clone()].iter()
+                .map(|item| if item.aligns_baseline(row) {
+                    above - item.baseline + item.cross + item.cross_edges
+                } else {
+                    item.cross + item.cross_edges
+                })
+                .fold(0.0, f32::max))
+            .collect();
+        if let (false, Some(cross), Some(size)) = (wrap, inner_cross, line_sizes.first_mut()) {
+            *size = cross;
+        }
+        let lines_cross = line_sizes.iter().sum::<f32>() + cross_gap * lines.len().saturating_sub(1) as f32;
+        let cross_size = inner_cross.unwrap_or(lines_cross);
+
+        // 9.6: align-content spreads the lines in the container
+        let (mut line_start, mut between) = (0.0, 0.0);
+        if wrap {
+            let free = cross_size - lines_cross;
+            match style.align_content {
+                Alignment::Normal | Alignment::Stretch if free > 0.0 => {
+                    for size in &mut line_sizes {
+                        *size += free / lines.len() as f32;
                     }
                 }
-                PropertyName::BackgroundColor => {
-                    if let PropertyValue::Color(color) = &value {
-                        let color = color.get_rgb();
-                        box_.background_color = Color::new(color.0, color.1, color.2, 255);
+                align => (line_start, between) = distribute(flex_relative(align, wrap_reverse), free, lines.len()),
+            }
+        }
+
+        for ((line, line_size), above) in lines.iter().zip(&line_sizes).zip(&baselines) {
+            let line_cross = if wrap_reverse { cross_size - line_start - line_size } else { line_start };
+            line_start += line_size + cross_gap + between;
+
+            // 9.5: auto margins, then justify-content, take up the free space along the main axis
+            let free = main_size - outer_main(&items[line.clone()]) - gaps(line);
+            let auto_margins: usize = items[line.clone()].iter()
+                .map(|item| item.auto_main.0 as usize + item.auto_main.1 as usize)
+                .sum();
+            let (mut position, between) = if auto_margins > 0 && free > 0.0 {
+                let share = free / auto_margins as f32;
+                for item in &mut items[line.clone()] {
+                    let margin = &mut self.children[item.index].margin;
+                    let (start, end) = if row { (&mut margin.left, &mut margin.right) } else { (&mut margin.top, &mut margin.bottom) };
+                    for (auto, margin) in [(item.auto_main.0, start), (item.auto_main.1, end)] {
+                        if auto {
+                            *margin = margin.saturating_add(share.round() as i16);
+                            item.main_edges += share.round();
+                        }
                     }
                 }
-                PropertyName::Margin => {
-                    if let PropertyValue::Length(Length::Px(px)) = &value {
-                        box_.margin = Indentations {
-                            top: *px,
-                            right: *px,
-                            bottom: *px,
-                            left: *px,
-                        };
+                (0.0, 0.0)
+            } else {
+                distribute(flex_relative(style.justify_content, reverse), free, line.len())
+            };
+
+            for item in &mut items[line.clone()] {
+                let outer = item.target + item.main_edges;
+                let main = if reverse { main_size - position - outer } else { position };
+                position += outer + main_gap + between;
+
+                // 9.4 and 9.6: the item's place and size across the line
+                let child = &mut self.children[item.index];
+                let free = line_size - item.cross - item.cross_edges;
+                let offset = if item.auto_cross.0 || item.auto_cross.1 {
+                    let auto_margins = item.auto_cross.0 as u8 + item.auto_cross.1 as u8;
+                    let share = (free.max(0.0) / auto_margins as f32).round() as i16;
+                    let margin = &mut child.margin;
+                    let (start, end) = if row { (&mut margin.top, &mut margin.bottom) } else { (&mut margin.left, &mut margin.right) };
+                    if item.auto_cross.0 {
+                        *start = start.saturating_add(share);
                     }
-                }
-                PropertyName::MarginTop => {
-                    if let PropertyValue::Length(Length::Px(px)) = &value {
-                        box_.margin.top = *px;
+                    if item.auto_cross.1 {
+                        *end = end.saturating_add(share);
                     }
-                }
-                PropertyName::MarginRight => {
-                    if let PropertyValue::Length(Length::Px(px)) = &value {
-                        box_.margin.right = *px;
+                    item.cross_edges += share as f32 * auto_margins as f32;
+                    0.0
+                } else {
+                    match item.align {
+                        Alignment::Normal | Alignment::Stretch if item.cross_auto => {
+                            item.cross = (line_size - item.cross_edges).max(0.0);
+                            0.0
+                        }
+                        Alignment::Baseline if row => above - item.baseline,
+                        align => distribute(flex_relative(align, wrap_reverse), free, 1).0,
                     }
-                }
-                PropertyName::MarginBottom => {
-                    if let PropertyValue::Length(Length::Px(px)) = &value {
-                        box_.margin.bottom = *px;
+                };
+                let outer_cross = item.cross + item.cross_edges;
+                let cross = line_cross + if wrap_reverse { line_size - offset - outer_cross } else { offset };
+
+                let (x, y) = if row { (main, cross) } else { (cross, main) };
+                let (width, height) = if row { (item.target, item.cross) } else { (item.cross, item.target) };
+                child.layout_inside(&ContainingBlock {
+                    x: content.x + x + child.margin.left as f32 + child.border.left as f32 + child.padding.left as f32,
+                    y: content.y + y + child.margin.top as f32 + child.border.top as f32 + child.padding.top as f32,
+                    width,
+                    height: Some(height),
+                });
+            }
+        }
+        content.y + if row { cross_size } else { main_size }
+    }
+
+    /// Places the children of a grid container in its grid (CSS Grid 8):
+    /// items with lines to start or end at go there, auto-placement puts the
+    /// others in the first free cells in `grid-auto-flow` order. Returns the
+    /// areas of the items, and the columns and the rows of the grid, implicit
+    /// tracks included.
+    fn place_grid_items(&self) -> (Vec<GridArea>, Vec<Track>, Vec<Track>) {
+        let style = &self.style;
+        let column_lines = grid_line_names(&style.grid_template_columns, &style.grid_template_areas, true);
+        let row_lines = grid_line_names(&style.grid_template_rows, &style.grid_template_areas, false);
+        let mut indices: Vec<usize> = (0..self.children.len())
+            .filter(|&index| self.children[index].box_type != BoxType::Marker)
+            .collect();
+        indices.sort_by_key(|&index| self.children[index].style.order);
+        let placements: Vec<(usize, GridSpan<i32>, GridSpan<i32>)> = indices.into_iter()
+            .map(|index| {
+                let item = &self.children[index].style;
+                (index,
+                 resolve_grid_lines(&item.grid_column_start, &item.grid_column_end, &column_lines),
+                 resolve_grid_lines(&item.grid_row_start, &item.grid_row_end, &row_lines))
+            })
+            .collect();
+        // lines before the first one of the explicit grid add implicit tracks in front of it
+        let column_offset = placements.iter().filter_map(|item| item.1.0).fold(0, i32::min).unsigned_abs() as usize;
+        let row_offset = placements.iter().filter_map(|item| item.2.0).fold(0, i32::min).unsigned_abs() as usize;
+        let shift = |(start, span): (Option<i32>, usize), offset: usize| (start.map(|start| (start + offset as i32) as usize), span);
+
+        // auto-placement fills tracks `across`, the columns unless the flow is
+        // `column`, and adds tracks `along` the other axis when they are full
+        let flow = style.grid_auto_flow;
+        let items: Vec<(usize, GridSpan<usize>, GridSpan<usize>)> = placements.into_iter()
+            .map(|(index, column, row)| {
+                let (column, row) = (shift(column, column_offset), shift(row, row_offset));
+                if flow.column { (index, row, column) } else { (index, column, row) }
+            })
+            .collect();
+        let explicit_columns = column_offset + column_lines.len() - 1;
+        let explicit_rows = row_offset + row_lines.len() - 1;
+        let (explicit_across, explicit_along) = if flow.column { (explicit_rows, explicit_columns) } else { (explicit_columns, explicit_rows) };
+        let mut placed: Vec<(usize, Range<usize>, Range<usize>)> = Vec::new();
+        let overlaps = |placed: &[(usize, Range<usize>, Range<usize>)], across: &Range<usize>, along: &Range<usize>| placed.iter()
+            .any(|(_, a, b)| a.start < across.end && across.start < a.end && b.start < along.end && along.start < b.end);
+
+        // 8.5: first the items with both positions known
+        for (index, across, along) in &items {
+            if let ((Some(a), a_span), (Some(b), b_span)) = (across, along) {
+                placed.push((*index, *a..a + a_span, *b..b + b_span));
+            }
+        }
+        // then those locked to a track along, each after the ones before it there
+        let mut cursors: HashMap<usize, usize> = HashMap::new();
+        for (index, (_, span), along) in items.iter().filter(|(_, across, along)| across.0.is_none() && along.0.is_some()) {
+            let along = along.0.unwrap_or(0)..along.0.unwrap_or(0) + along.1;
+            let mut start = if flow.dense { 0 } else { cursors.get(&along.start).copied().unwrap_or(0) };
+            while overlaps(&placed, &(start..start + span), &along) {
+                start += 1;
+            }
+            cursors.insert(along.start, start + span);
+            placed.push((*index, start..start + span, along));
+        }
+        // the rest go from a cursor (along, across) through the cells
+        let across_count = items.iter()
+            .map(|(_, (start, span), _)| start.unwrap_or(0) + span)
+            .chain(placed.iter().map(|(_, across, _)| across.end))
+            .fold(explicit_across, usize::max);
+        let mut cursor = (0, 0);
+        for (index, (start, span), (_, along_span)) in items.iter().filter(|(_, _, along)| along.0.is_none()) {
+            if flow.dense {
+                cursor = (0, 0);
+            }
+            match start {
+                Some(start) => {
+                    if *start < cursor.1 {
+                        cursor.0 += 1;
                     }
-                }
-                PropertyName::MarginLeft => {
-                    if let PropertyValue::Length(Length::Px(px)) = &value {
-                        box_.margin.left = *px;
+                    cursor.1 = *start;
+                    while overlaps(&placed, &(cursor.1..cursor.1 + span), &(cursor.0..cursor.0 + along_span)) {
+                        cursor.0 += 1;
                     }
                 }
-                PropertyName::Padding => {
-                    if let PropertyValue::Length(Length::Px(px)) = &value {
-                        box_.padding = Indentations {
-                            top: *px,
-                            right: *px,
-                            bottom: *px,
-                            left: *px,
-                        };
+                None => {
+                    while cursor.1 + span > across_count
+                        || overlaps(&placed, &(cursor.1..cursor.1 + span), &(cursor.0..cursor.0 + along_span)) {
+                        cursor.1 += 1;
+                        if cursor.1 + span > across_count {
+                            cursor = (cursor.0 + 1, 0);
+                        }
                     }
                 }
-                PropertyName::PaddingTop => {
-                    if let PropertyValue::Length(Length::Px(px)) = &value {
-                        box_.padding.top = *px;
-                    }
+            }
+            placed.push((*index, cursor.1..cursor.1 + span, cursor.0..cursor.0 + along_span));
+        }
+
+        let along_count = placed.iter().map(|(_, _, along)| along.end).fold(explicit_along, usize::max);
+        let (column_count, row_count) = if flow.column { (along_count, across_count) } else { (across_count, along_count) };
+        let areas = placed.into_iter()
+            .map(|(index, across, along)| match flow.column {
+                true => GridArea { index, column: along, row: across },
+                false => GridArea { index, column: across, row: along },
+            })
+            .collect();
+        let columns = grid_tracks(&style.grid_template_columns.tracks, &style.grid_auto_columns, column_offset, column_count);
+        let rows = grid_tracks(&style.grid_template_rows.tracks, &style.grid_auto_rows, row_offset, row_count);
+        (areas, columns, rows)
+    }
+
+    /// Lays the children of a grid container out in its grid, in its content
+    /// box `content` (CSS Grid 11), and returns the bottom of the grid.
+    fn layout_grid(&mut self, content: &ContainingBlock) -> f32 {
+        let style = self.style.clone();
+        let (areas, columns, rows) = self.place_grid_items();
+        // percentages of an auto height are zero
+        let (column_gap, row_gap) = (style.column_gap.resolve(content.width), style.row_gap.resolve(content.height.unwrap_or(0.0)));
+        let stretches = |align: Alignment| matches!(align, Alignment::Normal | Alignment::Stretch);
+        // where the tracks in `range` start and how long they are together
+        let extent = |starts: &[f32], sizes: &[f32], range: &Range<usize>|
+            (starts[range.start], starts[range.end - 1] + sizes[range.end - 1] - starts[range.start]);
+
+        // 11.4: the columns first, from how wide the items can be
+        let widths: Vec<(Range<usize>, f32, f32)> = areas.iter()
+            .map(|area| {
+                let (min, max) = self.children[area.index].intrinsic_widths();
+                (area.column.clone(), min, max)
+            })
+            .collect();
+        let column_sizes = size_grid_tracks(&columns, Some(content.width), column_gap, &widths, stretches(style.justify_content));
+        let column_starts = track_starts(&column_sizes, Some(content.width), column_gap, style.justify_content);
+
+        // then the rows, from how high the items are at their widths
+        let mut items = Vec::new();
+        for area in &areas {
+            let (_, area_width) = extent(&column_starts, &column_sizes, &area.column);
+            let child = &mut self.children[area.index];
+            let auto = child.resolve_edges(area_width);
+            let (margin, border, padding) = (&child.margin, &child.border, &child.padding);
+            let horizontal = margin.horizontal() + border.horizontal() + padding.horizontal();
+            let justify = match child.style.justify_self {
+                Alignment::Auto => style.justify_items,
+                justify => justify,
+            };
+            let available = area_width - horizontal;
+            let width = child.style.width.resolve(area_width).unwrap_or_else(|| {
+                if stretches(justify) && !auto.left && !auto.right {
+                    available
+                } else {
+                    let (min, max) = child.content_widths();
+                    max.min(available).max(min)
                 }
-                PropertyName::PaddingRight => {
-                    if let PropertyValue::Length(Length::Px(px)) = &value {
-                        box_.padding.right = *px;
-                    }
+            }).max(0.0);
+            let height = child.style.height.resolve_height(None).unwrap_or_else(|| {
+                child.layout_inside(&ContainingBlock { x: 0.0, y: 0.0, width, height: None });
+                child.content_box().height as f32
+            });
+            items.push((width, height, auto, justify));
+        }
+        let heights: Vec<(Range<usize>, f32, f32)> = areas.iter().zip(&items)
+            .map(|(area, (_, height, _, _))| {
+                let (margin, border, padding) = {
+                    let child = &self.children[area.index];
+                    (&child.margin, &child.border, &child.padding)
+                };
+                let vertical = margin.vertical() + border.vertical() + padding.vertical();
+                (area.row.clone(), height + vertical, height + vertical)
+            })
+            .collect();
+        let row_sizes = size_grid_tracks(&rows, content.height, row_gap, &heights, stretches(style.align_content));
+        let row_starts = track_starts(&row_sizes, content.height, row_gap, style.align_content);
+
+        // 11.8: each item aligned in its area
+        for (area, (width, content_height, auto, justify)) in areas.iter().zip(items) {
+            let (x, area_width) = extent(&column_starts, &column_sizes, &area.column);
+            let (y, area_height) = extent(&row_starts, &row_sizes, &area.row);
+            let child = &mut self.children[area.index];
+            let (margin, border, padding) = (&child.margin, &child.border, &child.padding);
+            let horizontal = margin.horizontal() + border.horizontal() + padding.horizontal();
+            let vertical = margin.vertical() + border.vertical() + padding.vertical();
+            let align = match child.style.align_self {
+                Alignment::Auto => style.align_items,
+                align => align,
+            };
+            let height = child.style.height.resolve_height(Some(area_height)).unwrap_or(
+                if stretches(align) && !auto.top && !auto.bottom { (area_height - vertical).max(0.0) } else { content_height });
+            let margin = &mut child.margin;
+            let dx = align_in_area(area_width - horizontal - width, (auto.left, auto.right), justify, (&mut margin.left, &mut margin.right));
+            let dy = align_in_area(area_height - vertical - height, (auto.top, auto.bottom), align, (&mut margin.top, &mut margin.bottom));
+            child.layout_inside(&ContainingBlock {
+                x: content.x + x + dx + child.margin.left as f32 + child.border.left as f32 + child.padding.left as f32,
+                y: content.y + y + dy + child.margin.top as f32 + child.border.top as f32 + child.padding.top as f32,
+                width,
+                height: Some(height),
+            });
+        }
+        content.y + row_starts.last().zip(row_sizes.last()).map_or(0.0, |(start, size)| start + size)
+    }
+
+    /// Stacks the children top to bottom from `content.y`, after the
+    /// margins above the first one. Consecutive inline-level children share
+    /// lines. Returns where the margins above end, `None` when all children
+    /// are empty, the bottom of the last non-empty child and the margins left
+    /// open below it.
+    fn layout_children(&mut self, content: &ContainingBlock, margin: CollapsedMargin) -> (Option<f32>, f32, CollapsedMargin) {
+        let (mut top, mut y, mut margin) = (None, content.y, margin);
+        let mut index = 0;
+        while index < self.children.len() {
+            // the marker is outside of the flow
+            if self.children[index].box_type == BoxType::Marker {
+                index += 1;
+                continue;
+            }
+            if self.children[index].box_type == BoxType::Block {
+                let child = &mut self.children[index];
+                margin = child.layout_block(&ContainingBlock { y, ..*content }, margin);
+                if !child.collapses_through() {
+                    top = top.or(Some(child.actual_dimensions.y as f32));
+                    y = child.actual_dimensions.bottom();
                 }
-                PropertyName::PaddingBottom => {
-                    if let PropertyValue::Length(Length::Px(px)) = &value {
-                        box_.padding.bottom = *px;
-                    }
+                index += 1;
+                continue;
+            }
+            let end = index + self.children[index..].iter()
+                .take_while(|child| child.is_inline_level())
+                .count();
+            let line_top = y + margin.resolve();
+            let height = self.layout_lines(index..end, &ContainingBlock { y: line_top, ..*content });
+            // lines with nothing on them take no space and don't separate margins
+            if height > 0.0 {
+                top = top.or(Some(line_top));
+                y = line_top + height;
+                margin = CollapsedMargin::default();
+            }
+            index = end;
+        }
+        (top, y, margin)
+    }
+
+    /// An empty block, the margins above it collapse with those below it.
+    fn collapses_through(&self) -> bool {
+        self.box_type == BoxType::Marker
+            || self.actual_dimensions.height == 0 && self.children.iter().all(LayoutBox::collapses_through)
+    }
+
+    /// Places the inline-level children in `range` on lines from the top
+    /// of `content` (CSS 2.1 9.4.2 and 10.8) and returns the height of the
+    /// lines.
+    fn layout_lines(&mut self, range: Range<usize>, content: &ContainingBlock) -> f32 {
+        let mut items = Vec::new();
+        for index in range.clone() {
+            // the lines of an earlier layout, e.g. when a flex item was measured
+            self.children[index].clear_lines();
+            collect_inline_items(&mut self.children[index], &mut vec![index], content.width, &mut items);
+        }
+        let mut lines = LineBuilder::new(content.width, &self.font);
+        lines.place(items);
+
+        let mut tops = Vec::new();
+        let mut bottom = content.y;
+        for line in &lines.lines {
+            tops.push(bottom);
+            bottom += line.height();
+        }
+        for item in lines.items {
+            let line = &lines.lines[item.line];
+            let offset = match self.style.text_align {
+                TextAlign::Center => (content.width - line.width) / 2.0,
+                TextAlign::Right | TextAlign::End => content.width - line.width,
+                _ => 0.0,
+            };
+            let x = (content.x + offset.max(0.0) + item.x).round() as i16;
+            let baseline = tops[item.line] + line.above;
+            let box_ = self.descendant_mut(&item.path);
+            match item.kind {
+                LineItemKind::Text(text) => {
+                    let y = (baseline - item.font.baseline()).round() as i16;
+                    box_.content.push(Content { x, y, text });
+                    box_.fragments.push(Dimensions {
+                        x, y, width: item.width.round() as i16, height: item.font.line_height.round() as i16,
+                    });
                 }
-                PropertyName::PaddingLeft => {
-                    if let PropertyValue::Length(Length::Px(px)) = &value {
-                        box_.padding.left = *px;
-                    }
+                LineItemKind::Atomic { above } => {
+                    let y = (baseline - above).round() as i16;
+                    box_.translate(x.saturating_sub(box_.dimensions.x), y.saturating_sub(box_.dimensions.y));
+                }
+                // inline boxes are as high as their font, whatever the line height
+                LineItemKind::Fragment => {
+                    let top = (baseline - ASCENT * item.font.size).round();
+                    box_.fragments.push(Dimensions::new(
+                        x as f32,
+                        top - box_.padding.top as f32 - box_.border.top as f32,
+                        item.width,
+                        item.font.size.round() + box_.padding.vertical() + box_.border.vertical(),
+                    ));
                 }
-                PropertyName::Display => {
-                    if let PropertyValue::Display(display) = &value {
-                        box_.box_type = match display {
-                            css::DisplayType::Block => BoxType::Block,
-                            css::DisplayType::Inline => BoxType::Inline,
-                            _ => BoxType::Block,
-                        };
+            }
+        }
+        for child in &mut self.children[range] {
+            child.set_inline_bounds();
+        }
+        bottom - content.y
+    }
+
+    fn clear_lines(&mut self) {
+        self.fragments.clear();
+        self.content.clear();
+        for child in &mut self.children {
+            child.clear_lines();
+        }
+    }
+
+    fn descendant_mut(&mut self, path: &[usize]) -> &mut LayoutBox {
+        path.iter().fold(self, |box_, &index| &mut box_.children[index])
+    }
+
+    /// Moves the box and everything in it.
+    fn translate(&mut self, dx: i16, dy: i16) {
+        for dimensions in [&mut self.dimensions, &mut self.actual_dimensions].into_iter().chain(&mut self.fragments) {
+            dimensions.x = dimensions.x.saturating_add(dx);
+            dimensions.y = dimensions.y.saturating_add(dy);
+        }
+        for content in &mut self.content {
+            content.x = content.x.saturating_add(dx);
+            content.y = content.y.saturating_add(dy);
+        }
+        for child in &mut self.children {
+            child.translate(dx, dy);
+        }
+    }
+
+    /// Sets the dimensions of a box laid out on lines to the bounding box
+    /// of its fragments.
+    fn set_inline_bounds(&mut self) {
+        // inline-blocks are laid out as blocks
+        if self.box_type == BoxType::InlineBlock {
+            return;
+        }
+        for child in &mut self.children {
+            child.set_inline_bounds();
+        }
+        let mut fragments = self.fragments.iter();
+        let first = match fragments.next() {
+            Some(first) => first.clone(),
+            None => return,
+        };
+        self.actual_dimensions = fragments.fold(first, |bounds, fragment| {
+            let (x, y) = (bounds.x.min(fragment.x) as f32, bounds.y.min(fragment.y) as f32);
+            Dimensions::new(
+                x,
+                y,
+                bounds.right().max(fragment.right()) - x,
+                bounds.bottom().max(fragment.bottom()) - y,
+            )
+        });
+        let actual = &self.actual_dimensions;
+        self.dimensions = Dimensions {
+            x: actual.x.saturating_sub(self.margin.left),
+            width: (actual.width as f32 + self.margin.horizontal()) as i16,
+            ..actual.clone()
+        };
+    }
+
+    /// Stores the content box through the border box and the margin box
+    /// around it.
+    fn set_content_box(&mut self, x: f32, y: f32, width: f32, height: f32) {
+        let (x, y, width, height) = (x.round(), y.round(), width.round(), height.round());
+        let (border_x, border_y) = (x - self.padding.left as f32 - self.border.left as f32,
+                                    y - self.padding.top as f32 - self.border.top as f32);
+        let border_width = width + self.padding.horizontal() + self.border.horizontal();
+        let border_height = height + self.padding.vertical() + self.border.vertical();
+        self.actual_dimensions = Dimensions::new(border_x, border_y, border_width, border_height);
+        self.dimensions = Dimensions::new(
+            border_x - self.margin.left as f32,
+            border_y - self.margin.top as f32,
+            border_width + self.margin.horizontal(),
+            border_height + self.margin.vertical(),
+        );
+    }
+
+    /// The area inside the padding, where the children are laid out.
+    pub fn content_box(&self) -> Dimensions {
+        let Dimensions { x, y, width, height } = self.actual_dimensions;
+        Dimensions::new(
+            x as f32 + self.border.left as f32 + self.padding.left as f32,
+            y as f32 + self.border.top as f32 + self.padding.top as f32,
+            width as f32 - self.border.horizontal() - self.padding.horizontal(),
+            height as f32 - self.border.vertical() - self.padding.vertical(),
+        )
+    }
+
+    /// The areas painted with the background: one per line for inline
+    /// boxes, none for text.
+    pub fn border_boxes(&self) -> Vec<Dimensions> {
+        match self.box_type {
+            BoxType::Block | BoxType::InlineBlock => vec![self.actual_dimensions.clone()],
+            BoxType::Inline => self.fragments.clone(),
+            BoxType::Text | BoxType::Marker => vec![],
+        }
+    }
+
+    /// Rectangles that draw the border, in px. An inline box broken across
+    /// lines has its left border on the first line and its right border on
+    /// the last.
+    pub fn border_rectangles(&self) -> Vec<(Dimensions, Color)> {
+        let areas = self.border_boxes();
+        let mut rectangles = Vec::new();
+        for (index, area) in areas.iter().enumerate() {
+            let border = Indentations {
+                left: if index == 0 { self.border.left } else { 0 },
+                right: if index == areas.len() - 1 { self.border.right } else { 0 },
+                ..self.border.clone()
+            };
+            rectangles.append(&mut self.area_border_rectangles(area, &border));
+        }
+        rectangles
+    }
+
+    /// Rectangles that draw `border` around the border box `area`; the top
+    /// and bottom sides run across the corners.
+    fn area_border_rectangles(&self, area: &Dimensions, border: &Indentations) -> Vec<(Dimensions, Color)> {
+        let Dimensions { x, y, width, height } = area.clone();
+        let side_height = height.saturating_sub(border.top).saturating_sub(border.bottom);
+        let sides_y = y.saturating_add(border.top);
+        let sides = [
+            (Dimensions { x, y, width, height: border.top }, &self.border_style.top, &self.border_color.top, true),
+            (Dimensions { x: x.saturating_add(width).saturating_sub(border.right), y: sides_y, width: border.right, height: side_height },
+             &self.border_style.right, &self.border_color.right, false),
+            (Dimensions { x, y: y.saturating_add(height).saturating_sub(border.bottom), width, height: border.bottom },
+             &self.border_style.bottom, &self.border_color.bottom, true),
+            (Dimensions { x, y: sides_y, width: border.left, height: side_height },
+             &self.border_style.left, &self.border_color.left, false),
+        ];
+        let mut rectangles = Vec::new();
+        for (strip, style, color, horizontal) in sides {
+            if strip.width <= 0 || strip.height <= 0 {
+                continue;
+            }
+            for rectangle in border_side_rectangles(strip, style, horizontal) {
+                rectangles.push((rectangle, color.clone()));
+            }
+        }
+        rectangles
+    }
+}
+
+
+/// A line box being filled.
+struct Line {
+    /// Width of what is on the line.
+    width: f32,
+    /// Height above and below the baseline.
+    above: f32,
+    below: f32,
+    /// Lines without text or inline box margins, borders and padding take no space.
+    empty: bool,
+}
+
+impl Line {
+    fn new(strut: &Font) -> Self {
+        let mut line = Line { width: 0.0, above: 0.0, below: 0.0, empty: true };
+        line.include(strut);
+        line
+    }
+
+    /// Makes room for the line-height box of `font` on the baseline.
+    fn include(&mut self, font: &Font) {
+        self.above = self.above.max(font.baseline());
+        self.below = self.below.max(font.line_height - font.baseline());
+    }
+
+    fn height(&self) -> f32 {
+        if self.empty { 0.0 } else { self.above + self.below }
+    }
+}
+
+/// The content of an inline formatting context, in order. `path` leads
+/// from the block container to the box the item belongs to.
+enum InlineItem {
+    Word { path: Vec<usize>, text: String, font: Font },
+    /// Collapsed white space, where lines break unless `wraps` is false.
+    Space { width: f32, wraps: bool },
+    /// Start of an inline box, its left margin and its left border and padding.
+    Start { path: Vec<usize>, font: Font, margin: f32, edge: f32 },
+    End { edge: f32, margin: f32 },
+    /// An inline-block, its margin box and the part of it above the baseline.
+    Atomic { path: Vec<usize>, width: f32, height: f32, above: f32 },
+}
+
+impl InlineItem {
+    fn width(&self) -> f32 {
+        match self {
+            InlineItem::Word { text, font, .. } => font.text_width(text),
+            InlineItem::Space { width, .. } => *width,
+            InlineItem::Start { margin, edge, .. } | InlineItem::End { edge, margin } => margin + edge,
+            InlineItem::Atomic { width, .. } => *width,
+        }
+    }
+}
+
+/// Appends the items of an inline-level box and everything in it.
+fn collect_inline_items(box_: &mut LayoutBox, path: &mut Vec<usize>, containing_width: f32, items: &mut Vec<InlineItem>) {
+    if box_.box_type == BoxType::Text {
+        let wraps = !matches!(box_.style.white_space, WhiteSpace::Nowrap | WhiteSpace::Pre);
+        for (index, word) in box_.text.split(char::is_whitespace).enumerate() {
+            // a space after another one collapses, also across the edges of inline boxes
+            let after_space = matches!(
+                items.iter().rev().find(|item| !matches!(item, InlineItem::Start { .. } | InlineItem::End { .. })),
+                Some(InlineItem::Space { .. }));
+            if index > 0 && !after_space {
+                items.push(InlineItem::Space { width: box_.font.text_width(" "), wraps });
+            }
+            if !word.is_empty() {
+                items.push(InlineItem::Word { path: path.clone(), text: word.to_string(), font: box_.font.clone() });
+            }
+        }
+        return;
+    }
+    if box_.box_type == BoxType::InlineBlock {
+        // laid out at the origin, moved onto its line once that is placed
+        let origin = ContainingBlock { x: 0.0, y: 0.0, width: containing_width, height: None };
+        box_.layout_block(&origin, CollapsedMargin::default());
+        let Dimensions { width, height, .. } = box_.dimensions;
+        // on the baseline of its last line, or with its bottom margin edge when it has none
+        let above = box_.baseline(true).unwrap_or(height as f32);
+        items.push(InlineItem::Atomic { path: path.clone(), width: width as f32, height: height as f32, above });
+        return;
+    }
+    // vertical margins don't apply to inline boxes
+    let style = &box_.style;
+    box_.margin.left = style.margin.left.resolve(containing_width).unwrap_or(0.0).round() as i16;
+    box_.margin.right = style.margin.right.resolve(containing_width).unwrap_or(0.0).round() as i16;
+    box_.padding = Indentations {
+        top: style.padding.top.resolve(containing_width).round() as i16,
+        right: style.padding.right.resolve(containing_width).round() as i16,
+        bottom: style.padding.bottom.resolve(containing_width).round() as i16,
+        left: style.padding.left.resolve(containing_width).round() as i16,
+    };
+    items.push(InlineItem::Start {
+        path: path.clone(),
+        font: box_.font.clone(),
+        margin: box_.margin.left as f32,
+        edge: box_.border.left as f32 + box_.padding.left as f32,
+    });
+    for (index, child) in box_.children.iter_mut().enumerate() {
+        path.push(index);
+        collect_inline_items(child, path, containing_width, items);
+        path.pop();
+    }
+    items.push(InlineItem::End {
+        edge: box_.border.right as f32 + box_.padding.right as f32,
+        margin: box_.margin.right as f32,
+    });
+}
+
+/// A run of text, or the part of an inline box, placed on a line. `x` is
+/// from the start of the line.
+struct LineItem {
+    path: Vec<usize>,
+    line: usize,
+    x: f32,
+    width: f32,
+    font: Font,
+    kind: LineItemKind,
+}
+
+enum LineItemKind {
+    Text(String),
+    /// The part of an inline box on the line.
+    Fragment,
+    /// An inline-block, `above` the baseline by that much.
+    Atomic { above: f32 },
+}
+
+/// An inline box that has started but not ended on the current line.
+#[derive(Clone)]
+struct OpenBox {
+    path: Vec<usize>,
+    start: f32,
+    font: Font,
+}
+
+/// Breaks the content of an inline formatting context into lines no wider
+/// than `width`. Words and the box edges between them stay together, lines
+/// break at spaces.
+struct LineBuilder {
+    width: f32,
+    /// Every line makes room for the font of its block.
+    strut: Font,
+    lines: Vec<Line>,
+    items: Vec<LineItem>,
+    open: Vec<OpenBox>,
+    x: f32,
+}
+
+impl LineBuilder {
+    fn new(width: f32, strut: &Font) -> Self {
+        LineBuilder {
+            width,
+            strut: strut.clone(),
+            lines: vec![Line::new(strut)],
+            items: Vec::new(),
+            open: Vec::new(),
+            x: 0.0,
+        }
+    }
+
+    fn line(&mut self) -> &mut Line {
+        self.lines.last_mut().unwrap()
+    }
+
+    fn place(&mut self, items: Vec<InlineItem>) {
+        for index in 0..items.len() {
+            let width = items[index].width();
+            match &items[index] {
+                InlineItem::Space { wraps, .. } => {
+                    // spaces at the start and the end of a line are dropped
+                    let last = items[index + 1..].iter()
+                        .all(|item| matches!(item, InlineItem::Space { .. } | InlineItem::Start { .. } | InlineItem::End { .. }));
+                    if self.line().empty || last {
+                        continue;
+                    }
+                    let next: f32 = items[index + 1..].iter()
+                        .take_while(|item| !matches!(item, InlineItem::Space { .. }))
+                        .map(InlineItem::width)
+                        .sum();
+                    if *wraps && self.x + width + next > self.width {
+                        self.break_line();
+                    } else {
+                        self.x += width;
                     }
                 }
-                PropertyName::Width => {
-                    match &value {
-                        PropertyValue::Length(Length::Px(px)) => {
-                            box_.dimensions.width = *px;
-                        }
-                        PropertyValue::Length(Length::Percent(percent)) => {
-                            box_.dimensions.width = parent.actual_dimensions.width * (*percent as i16 / 100);
-                        }
-                        _ => { panic!("Width must be a length") }
+                InlineItem::Word { path, text, font } => self.add_word(path, text, font, width),
+                InlineItem::Atomic { path, height, above, .. } => {
+                    let line = self.lines.len() - 1;
+                    self.items.push(LineItem {
+                        path: path.clone(), line, x: self.x, width, font: self.strut.clone(),
+                        kind: LineItemKind::Atomic { above: *above },
+                    });
+                    self.x += width;
+                    let line = self.line();
+                    line.empty = false;
+                    line.above = line.above.max(*above);
+                    line.below = line.below.max(height - above);
+                }
+                InlineItem::Start { path, font, margin, edge } => {
+                    if width > 0.0 {
+                        self.line().empty = false;
                     }
+                    self.line().include(font);
+                    self.x += margin;
+                    self.open.push(OpenBox { path: path.clone(), start: self.x, font: font.clone() });
+                    self.x += edge;
                 }
-                PropertyName::Height => {
-                    match &value {
-                        PropertyValue::Length(Length::Px(px)) => {
-                            box_.dimensions.height = *px;
-                        }
-                        PropertyValue::Length(Length::Percent(percent)) => {
-                            box_.dimensions.height = parent.dimensions.height * *percent as i16;
-                        }
-                        _ => { panic!("Height must be a length") }
+                InlineItem::End { edge, margin } => {
+                    if width > 0.0 {
+                        self.line().empty = false;
                     }
+                    self.x += edge;
+                    let open = self.open.pop().unwrap();
+                    self.push_fragment(open);
+                    self.x += margin;
                 }
-                _s => { println!("kurwa{:?}", value) }
             }
         }
-        box_.name = element_data.tag_name.clone();
-        box_.calculate_position(parent, element_number);
-        box_.calculate_actual_dimensions(parent);
-        box_
+        self.line().width = self.x;
     }
 
-    fn set_content(&mut self, str: &String) {
-        let mut content = Content::default();
-        content.x = self.actual_dimensions.x + self.padding.left;
-        content.y = self.actual_dimensions.y + self.padding.top;
-        content.text = str.clone();
-        self.actual_dimensions.height += 20;
-        self.content = Some(content);
+    fn add_word(&mut self, path: &[usize], word: &str, font: &Font, width: f32) {
+        let line = self.lines.len() - 1;
+        let x = self.x;
+        match self.items.last_mut() {
+            // the next word of the same run, after a space
+            Some(LineItem { path: last, line: last_line, kind: LineItemKind::Text(text), x: start, width: run, .. })
+                if last == path && *last_line == line => {
+                text.push(' ');
+                text.push_str(word);
+                *run = x + width - *start;
+            }
+            _ => self.items.push(LineItem {
+                path: path.to_vec(), line, x, width, font: font.clone(), kind: LineItemKind::Text(word.to_string()),
+            }),
+        }
+        self.x += width;
+        self.line().empty = false;
+        self.line().include(font);
+    }
+
+    /// Ends the current line, the boxes still open continue on the next one.
+    fn break_line(&mut self) {
+        let open = std::mem::take(&mut self.open);
+        for open in &open {
+            self.push_fragment(open.clone());
+        }
+        self.line().width = self.x;
+        let mut line = Line::new(&self.strut);
+        for open in &open {
+            line.include(&open.font);
+        }
+        self.lines.push(line);
+        self.open = open.into_iter().map(|open| OpenBox { start: 0.0, ..open }).collect();
+        self.x = 0.0;
+    }
+
+    fn push_fragment(&mut self, open: OpenBox) {
+        self.items.push(LineItem {
+            path: open.path,
+            line: self.lines.len() - 1,
+            x: open.start,
+            width: self.x - open.start,
+            font: open.font,
+            kind: LineItemKind::Fragment,
+        });
+    }
+}
+
+/// A child of a flex container during flex layout. Sizes are of the content
+/// box, along the main axis or across it; `main_edges` and `cross_edges` are
+/// the margins, borders and padding along each axis.
+struct FlexItem {
+    index: usize,
+    grow: f32,
+    shrink: f32,
+    base: f32,
+    hypothetical: f32,
+    /// automatic minimum size: the item doesn't shrink below its content
+    min: f32,
+    /// main size while the free space is being shared out
+    target: f32,
+    frozen: bool,
+    cross: f32,
+    /// whether `width` or `height`, whichever is across, is `auto`
+    cross_auto: bool,
+    main_edges: f32,
+    cross_edges: f32,
+    /// auto margins at the start and at the end of each axis
+    auto_main: (bool, bool),
+    auto_cross: (bool, bool),
+    align: Alignment,
+    /// from the top of the margin box
+    baseline: f32,
+}
+
+impl FlexItem {
+    /// Resolves the margins and padding of `child` and finds its flex base
+    /// size and hypothetical main size (CSS Flexbox 9.2). The cross size of
+    /// a column item is needed for that and found here too.
+    fn new(index: usize, child: &mut LayoutBox, content: &ContainingBlock, container: &ComputedStyle) -> FlexItem {
+        let row = matches!(container.flex_direction, FlexDirection::Row | FlexDirection::RowReverse);
+        let auto = child.resolve_edges(content.width);
+        let (margin, border, padding) = (&child.margin, &child.border, &child.padding);
+        let horizontal = margin.horizontal() + border.horizontal() + padding.horizontal();
+        let vertical = margin.vertical() + border.vertical() + padding.vertical();
+        let style = &child.style;
+        let width = style.width.resolve(content.width);
+        let height = style.height.resolve_height(content.height);
+        let mut item = FlexItem {
+            index,
+            grow: style.flex_grow,
+            shrink: style.flex_shrink,
+            base: 0.0,
+            hypothetical: 0.0,
+            min: 0.0,
+            target: 0.0,
+            frozen: false,
+            cross: 0.0,
+            cross_auto: if row { height.is_none() } else { width.is_none() },
+            main_edges: if row { horizontal } else { vertical },
+            cross_edges: if row { vertical } else { horizontal },
+            auto_main: if row { (auto.left, auto.right) } else { (auto.top, auto.bottom) },
+            auto_cross: if row { (auto.top, auto.bottom) } else { (auto.left, auto.right) },
+            align: match style.align_self {
+                Alignment::Auto => container.align_items,
+                align => align,
+            },
+            baseline: 0.0,
+        };
+        let basis = match &style.flex_basis {
+            FlexBasis::Length(css::Length::Percent(_)) if !row && content.height.is_none() => None,
+            FlexBasis::Length(length) => Some(length.resolve(if row { content.width } else { content.height.unwrap_or(0.0) })),
+            FlexBasis::Auto if row => width,
+            FlexBasis::Auto => height,
+            FlexBasis::Content => None,
+        };
+        let (base, min) = if row {
+            let (min_content, max_content) = child.content_widths();
+            (basis.unwrap_or(max_content), width.map_or(min_content, |width| width.min(min_content)))
+        } else {
+            // the content height of a column item depends on its width: in a
+            // single line it is stretched already, otherwise it fits the content
+            let stretch = container.flex_wrap == FlexWrap::NoWrap && item.cross_auto
+                && !item.auto_cross.0 && !item.auto_cross.1
+                && matches!(item.align, Alignment::Normal | Alignment::Stretch);
+            let available = content.width - item.cross_edges;
+            item.cross = width.unwrap_or_else(|| if stretch {
+                available
+            } else {
+                let (min, max) = child.content_widths();
+                max.min(available).max(min)
+            }).max(0.0);
+            child.layout_inside(&ContainingBlock { x: 0.0, y: 0.0, width: item.cross, height: None });
+            let content_height = child.content_box().height as f32;
+            (basis.unwrap_or(content_height), height.map_or(content_height, |height| height.min(content_height)))
+        };
+        item.base = base.max(0.0);
+        item.min = min.max(0.0);
+        item.hypothetical = item.base.max(item.min);
+        item
+    }
+
+    fn aligns_baseline(&self, row: bool) -> bool {
+        row && self.align == Alignment::Baseline && !self.auto_cross.0 && !self.auto_cross.1
+    }
+}
+
+/// Sizes the items of a flex line so that they fill `available`, growing or
+/// shrinking them by their flex factors (CSS Flexbox 9.7).
+fn resolve_flexible_lengths(items: &mut [FlexItem], available: f32) {
+    let hypothetical: f32 = items.iter().map(|item| item.hypothetical + item.main_edges).sum();
+    let grow = hypothetical < available;
+    let factor = |item: &FlexItem| if grow { item.grow } else { item.shrink };
+    for item in items.iter_mut() {
+        item.target = item.hypothetical;
+        item.frozen = factor(item) == 0.0
+            || (grow && item.base > item.hypothetical)
+            || (!grow && item.base < item.hypothetical);
+    }
+    let free_space = |items: &[FlexItem]| available - items.iter()
+        .map(|item| item.main_edges + if item.frozen { item.target } else { item.base })
+        .sum::<f32>();
+    let initial = free_space(items);
+    while items.iter().any(|item| !item.frozen) {
+        let mut free = free_space(items);
+        let factors: f32 = items.iter().filter(|item| !item.frozen).map(factor).sum();
+        // factors that add up to less than one take only that share of the space
+        if factors < 1.0 && (initial * factors).abs() < free.abs() {
+            free = initial * factors;
+        }
+        let scaled: f32 = items.iter().filter(|item| !item.frozen).map(|item| item.shrink * item.base).sum();
+        let mut clamped = vec![false; items.len()];
+        let mut violation = 0.0;
+        for (index, item) in items.iter_mut().enumerate().filter(|(_, item)| !item.frozen) {
+            let share = match grow {
+                true => item.grow / factors,
+                false if scaled > 0.0 => item.shrink * item.base / scaled,
+                false => 0.0,
+            };
+            let target = item.base + free * share;
+            item.target = target.max(item.min);
+            clamped[index] = item.target > target;
+            violation += item.target - target;
+        }
+        // items held at their minimum keep it, the others share the space again
+        for (index, item) in items.iter_mut().enumerate() {
+            item.frozen = item.frozen || violation == 0.0 || clamped[index];
+        }
+    }
+}
+
+/// Flex-relative alignment along an axis laid out from its end when
+/// `reverse`: `Start` is where the first item goes, `End` the other side.
+fn flex_relative(align: Alignment, reverse: bool) -> Alignment {
+    match align {
+        Alignment::FlexEnd => Alignment::End,
+        Alignment::Start | Alignment::Left if reverse => Alignment::End,
+        Alignment::End | Alignment::Right if !reverse => Alignment::End,
+        Alignment::Center | Alignment::SpaceBetween | Alignment::SpaceAround | Alignment::SpaceEvenly => align,
+        _ => Alignment::Start,
+    }
+}
+
+/// Where the first of `count` boxes goes and the space added between two of
+/// them to align them in `free` space. Without room, space distribution
+/// falls back to the start, or to the center for `space-around` and
+/// `space-evenly`.
+fn distribute(align: Alignment, free: f32, count: usize) -> (f32, f32) {
+    let count = count as f32;
+    match align {
+        Alignment::End => (free, 0.0),
+        Alignment::Center => (free / 2.0, 0.0),
+        Alignment::SpaceBetween if free > 0.0 && count > 1.0 => (0.0, free / (count - 1.0)),
+        Alignment::SpaceAround if free > 0.0 => (free / count / 2.0, free / count),
+        Alignment::SpaceEvenly if free > 0.0 => (free / (count + 1.0), free / (count + 1.0)),
+        Alignment::SpaceAround | Alignment::SpaceEvenly => (free / 2.0, 0.0),
+        _ => (0.0, 0.0),
+    }
+}
+
+/// Where a grid item is: the columns and the rows it spans, counted from 0
+/// at the first track of the grid, implicit ones included.
+struct GridArea {
+    index: usize,
+    column: Range<usize>,
+    row: Range<usize>,
+}
+
+/// Where a grid item goes along one axis before auto-placement: the line it
+/// starts at, `None` until auto-placement decides, and how many tracks it
+/// spans.
+type GridSpan<T> = (Option<T>, usize);
+
+/// How far line numbers and spans in a grid may reach, bounding the size of
+/// the implicit grid.
+const MAX_GRID_LINES: i32 = 1000;
+
+/// Names of the lines of the explicit grid along one axis: those given in
+/// the track list, and `name-start` and `name-end` at the sides of each
+/// area of `grid-template-areas`, which may add explicit tracks.
+fn grid_line_names(list: &TrackList, areas: &[Vec<String>], columns: bool) -> Vec<Vec<String>> {
+    let cells = if columns { areas.first().map_or(0, Vec::len) } else { areas.len() };
+    let mut lines = list.lines.clone();
+    lines.resize(lines.len().max(cells + 1), Vec::new());
+    let mut names: Vec<&String> = areas.iter().flatten().filter(|name| *name != ".").collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let positions: Vec<usize> = areas.iter().enumerate()
+            .flat_map(|(y, row)| row.iter().enumerate()
+                .filter(|(_, cell)| *cell == name)
+                .map(move |(x, _)| if columns { x } else { y }))
+            .collect();
+        if let (Some(first), Some(last)) = (positions.iter().min(), positions.iter().max()) {
+            lines[*first].push(format!("{}-start", name));
+            lines[last + 1].push(format!("{}-end", name));
+        }
+    }
+    lines
+}
+
+/// Resolves where an item starts and ends along one axis (CSS Grid 8.3).
+/// Lines count from 0 at the start of the explicit grid and are negative
+/// before it.
+fn resolve_grid_lines(start: &GridLine, end: &GridLine, lines: &[Vec<String>]) -> GridSpan<i32> {
+    let explicit = lines.len() as i32 - 1;
+    let named = |name: &str| lines.iter().position(|names| names.iter().any(|line| line == name)).map(|line| line as i32);
+    let line = |line: &GridLine, side: &str| -> Option<i32> {
+        let line = match line {
+            GridLine::Line(line) if *line > 0 => line - 1,
+            GridLine::Line(line) => explicit + 1 + line.max(&-MAX_GRID_LINES),
+            // a name no line has is the first line after the explicit grid
+            GridLine::Name(name) => named(&format!("{}-{}", name, side)).or_else(|| named(name)).unwrap_or(explicit + 1),
+            _ => return None,
+        };
+        Some(line.clamp(-MAX_GRID_LINES, MAX_GRID_LINES))
+    };
+    let span = |line: &GridLine| match line {
+        GridLine::Span(span) => (*span).min(MAX_GRID_LINES as u32) as usize,
+        _ => 1,
+    };
+    match (line(start, "start"), line(end, "end")) {
+        // an end at or before the start swaps with it
+        (Some(start), Some(end)) if start == end => (Some(start), 1),
+        (Some(start), Some(end)) => (Some(start.min(end)), start.abs_diff(end) as usize),
+        (Some(start), None) => (Some(start), span(end)),
+        (None, Some(end)) => (Some(end - span(start) as i32), span(start)),
+        (None, None) => (None, span(start)),
     }
+}
 
+/// The `count` tracks of a grid with the explicit ones from `offset` on.
+/// `grid-auto-columns` or `grid-auto-rows` sizes the others, its pattern
+/// repeating after the explicit tracks and backwards in front of them.
+fn grid_tracks(explicit: &[Track], auto: &[Track], offset: usize, count: usize) -> Vec<Track> {
+    (0..count)
+        .map(|index| match index.checked_sub(offset) {
+            Some(index) if index < explicit.len() => explicit[index].clone(),
+            Some(index) => auto[(index - explicit.len()) % auto.len()].clone(),
+            None => auto[(auto.len() - (offset - index) % auto.len()) % auto.len()].clone(),
+        })
+        .collect()
+}
 
-    fn calculate_position(&mut self, parent: &mut LayoutBox, _element_size: usize) {
-        self.dimensions.height += self.padding.top + self.padding.bottom;
-        if parent.box_type == BoxType::Block {
-            self.dimensions.y = parent.v_elements + parent.actual_dimensions.y;
-            self.dimensions.x = parent.actual_dimensions.x;
-            parent.v_elements += self.dimensions.height + self.margin.top + self.margin.bottom;
-        } else if parent.box_type == BoxType::Inline {
-            self.dimensions.y = parent.actual_dimensions.y;
-            self.dimensions.x = parent.h_elements + parent.actual_dimensions.x;
-            parent.h_elements += self.dimensions.width + self.margin.left + self.margin.right;
+/// Sizes the tracks along one axis of a grid (CSS Grid 11.4 to 11.8) in
+/// `available` space, `None` when the size of the grid depends on them.
+/// `contributions` are the tracks each item spans with the smallest and the
+/// largest its margin box can be. Auto tracks share the space left over if
+/// the content alignment `stretch`es.
+fn size_grid_tracks(tracks: &[Track], available: Option<f32>, gap: f32,
+                    contributions: &[(Range<usize>, f32, f32)], stretch: bool) -> Vec<f32> {
+    // percentages of an indefinite size behave as `auto`
+    let fixed = |breadth: &TrackBreadth| match (breadth, available) {
+        (TrackBreadth::Length(css::Length::Px(px)), _) => Some(*px),
+        (TrackBreadth::Length(length), Some(available)) => Some(length.resolve(available)),
+        _ => None,
+    };
+    let flex = |index: usize| match tracks[index].max {
+        TrackBreadth::Fr(fr) => Some(fr),
+        _ => None,
+    };
+    let intrinsic_max = |index: usize| flex(index).is_none() && fixed(&tracks[index].max).is_none();
+    let gaps = |count: usize| gap * count.saturating_sub(1) as f32;
+
+    // 11.4: fixed sizes, the growth limits of intrinsic ones are unknown yet
+    let mut base: Vec<f32> = tracks.iter().map(|track| fixed(&track.min).unwrap_or(0.0)).collect();
+    let mut limit: Vec<f32> = (0..tracks.len())
+        .map(|index| match flex(index) {
+            Some(_) => base[index],
+            None => fixed(&tracks[index].max).map_or(f32::INFINITY, |max| max.max(base[index])),
+        })
+        .collect();
+
+    // 11.5: the items set the intrinsic sizes, those spanning fewer tracks first
+    let mut sorted: Vec<&(Range<usize>, f32, f32)> = contributions.iter().collect();
+    sorted.sort_by_key(|(range, _, _)| range.len());
+    for (range, min, max) in sorted {
+        let intrinsic_min: Vec<usize> = range.clone().filter(|&index| fixed(&tracks[index].min).is_none()).collect();
+        let used = range.clone().map(|index| base[index]).sum::<f32>() + gaps(range.len());
+        if range.clone().any(|index| flex(index).is_some()) {
+            // only the flexible tracks grow for an item in one, by their factors
+            let targets: Vec<usize> = intrinsic_min.into_iter().filter(|&index| flex(index).is_some()).collect();
+            let factors: f32 = targets.iter().filter_map(|&index| flex(index)).sum();
+            if min > &used {
+                for &index in &targets {
+                    let share = if factors > 0.0 { flex(index).unwrap_or(0.0) / factors } else { 1.0 / targets.len() as f32 };
+                    base[index] += (min - used) * share;
+                }
+            }
+            continue;
+        }
+        let wanted = match intrinsic_min.iter().all(|&index| tracks[index].min == TrackBreadth::MaxContent) {
+            true => max,
+            false => min,
+        };
+        if wanted > &used {
+            for &index in &intrinsic_min {
+                base[index] += (wanted - used) / intrinsic_min.len() as f32;
+            }
         }
+        let targets: Vec<usize> = range.clone().filter(|&index| intrinsic_max(index)).collect();
+        let wanted = match targets.iter().all(|&index| tracks[index].max == TrackBreadth::MinContent) {
+            true => min,
+            false => max,
+        };
+        let finite = |index: usize, limit: &[f32]| if limit[index].is_finite() { limit[index] } else { base[index] };
+        let used = range.clone().map(|index| finite(index, &limit)).sum::<f32>() + gaps(range.len());
+        if wanted > &used {
+            for &index in &targets {
+                limit[index] = finite(index, &limit) + (wanted - used) / targets.len() as f32;
+            }
+        }
+    }
+    for index in 0..tracks.len() {
+        limit[index] = if limit[index].is_finite() { limit[index].max(base[index]) } else { base[index] };
     }
 
-    fn calculate_actual_dimensions(&mut self, parent: &mut LayoutBox) {
-        self.actual_dimensions.x = self.dimensions.x + self.margin.left + parent.padding.left;
-        self.actual_dimensions.y = self.dimensions.y + self.margin.top + parent.padding.top;
-        self.actual_dimensions.width = self.dimensions.width - self.margin.left - self.margin.right
-            - parent.padding.right * 2;
+    // 11.6: the tracks grow to their limits while there is space, or all
+    // the way when the size of the grid depends on them
+    let inflexible: Vec<usize> = (0..tracks.len()).filter(|&index| flex(index).is_none()).collect();
+    match available {
+        Some(available) => {
+            let mut free = available - base.iter().sum::<f32>() - gaps(tracks.len());
+            loop {
+                let growing: Vec<usize> = inflexible.iter().copied().filter(|&index| limit[index] > base[index]).collect();
+                if free < 0.01 || growing.is_empty() {
+                    break;
+                }
+                let share = free / growing.len() as f32;
+                for index in growing {
+                    let grown = share.min(limit[index] - base[index]);
+                    base[index] += grown;
+                    free -= grown;
+                }
+            }
+        }
+        None => {
+            for &index in &inflexible {
+                base[index] = limit[index];
+            }
+        }
+    }
 
-        self.actual_dimensions.height = self.dimensions.height;
+    // 11.7: flexible tracks share what is left by their factors
+    let flexible: Vec<usize> = (0..tracks.len()).filter(|&index| flex(index).is_some()).collect();
+    if !flexible.is_empty() {
+        let factors = |tracks: &mut dyn Iterator<Item=usize>| tracks.filter_map(flex).sum::<f32>().max(1.0);
+        let fr = match available {
+            Some(available) => {
+                // a track whose base size is more than its share keeps it
+                let mut flexing = flexible.clone();
+                loop {
+                    let rest = available - gaps(tracks.len()) - (0..tracks.len())
+                        .filter(|index| !flexing.contains(index))
+                        .map(|index| base[index])
+                        .sum::<f32>();
+                    let fr = rest.max(0.0) / factors(&mut flexing.iter().copied());
+                    let count = flexing.len();
+                    flexing.retain(|&index| flex(index).unwrap_or(0.0) * fr >= base[index]);
+                    if flexing.len() == count {
+                        break fr;
+                    }
+                }
+            }
+            // as much as the largest track or item needs
+            None => contributions.iter()
+                .filter(|(range, _, _)| range.clone().any(|index| flex(index).is_some()))
+                .map(|(range, _, max)| {
+                    let fixed = range.clone().filter(|&index| flex(index).is_none()).map(|index| base[index]).sum::<f32>();
+                    (max - fixed - gaps(range.len())) / factors(&mut range.clone())
+                })
+                .chain(flexible.iter().map(|&index| base[index] / flex(index).unwrap_or(0.0).max(1.0)))
+                .fold(0.0, f32::max),
+        };
+        for &index in &flexible {
+            base[index] = base[index].max(flex(index).unwrap_or(0.0) * fr);
+        }
     }
 
+    // 11.8: auto tracks stretch into the space left over
+    if let (true, Some(available)) = (stretch, available) {
+        let free = available - base.iter().sum::<f32>() - gaps(tracks.len());
+        let auto: Vec<usize> = (0..tracks.len()).filter(|&index| tracks[index].max == TrackBreadth::Auto).collect();
+        if free > 0.0 {
+            for &index in &auto {
+                base[index] += free / auto.len() as f32;
+            }
+        }
+    }
+    base
+}
 
+/// Where each track starts, with the tracks aligned in `available` space by
+/// `justify-content` or `align-content`.
+fn track_starts(sizes: &[f32], available: Option<f32>, gap: f32, align: Alignment) -> Vec<f32> {
+    let used = sizes.iter().sum::<f32>() + gap * sizes.len().saturating_sub(1) as f32;
+    let free = available.map_or(0.0, |available| available - used);
+    let (mut position, between) = distribute(flex_relative(align, false), free, sizes.len());
+    sizes.iter()
+        .map(|size| {
+            let start = position;
+            position += size + gap + between;
+            start
+        })
+        .collect()
+}
 
+/// Offset of a grid item in its area with `free` space around it along one
+/// axis: auto margins take up the space, otherwise the item is aligned.
+fn align_in_area(free: f32, auto: (bool, bool), align: Alignment, margins: (&mut i16, &mut i16)) -> f32 {
+    if auto.0 || auto.1 {
+        let share = (free.max(0.0) / (auto.0 as u8 + auto.1 as u8) as f32).round() as i16;
+        if auto.0 {
+            *margins.0 = margins.0.saturating_add(share);
+        }
+        if auto.1 {
+            *margins.1 = margins.1.saturating_add(share);
+        }
+        return 0.0;
+    }
+    distribute(flex_relative(align, false), free, 1).0
 }
 
+/// Splits one side of a border into the pieces its style is drawn with.
+/// `horizontal` sides are the top and bottom ones.
+fn border_side_rectangles(strip: Dimensions, style: &BorderStyle, horizontal: bool) -> Vec<Dimensions> {
+    let (length, width) = if horizontal { (strip.width, strip.height) } else { (strip.height, strip.width) };
+    // piece of the strip from `start` along it and `from` across it
+    let piece = |start: i16, length: i16, from: i16, width: i16| if horizontal {
+        Dimensions { x: strip.x.saturating_add(start), y: strip.y.saturating_add(from), width: length, height: width }
+    } else {
+        Dimensions { x: strip.x.saturating_add(from), y: strip.y.saturating_add(start), width, height: length }
+    };
+    // dashes twice as long as the border is wide, dots as long, with gaps of the same length
+    let segments = |segment: i16| (0..length).step_by(segment as usize * 2)
+        .map(|start| piece(start, segment.min(length - start), 0, width))
+        .collect();
+    match style {
+        BorderStyle::None | BorderStyle::Hidden => vec![],
+        BorderStyle::Dotted => segments(width),
+        BorderStyle::Dashed => segments(width.saturating_mul(2)),
+        // two lines and the gap between them, a third of the width each
+        BorderStyle::Double if width >= 3 => {
+            let line = width.saturating_add(1) / 3;
+            vec![piece(0, length, 0, line), piece(0, length, width - line, line)]
+        }
+        // no 3D shading, groove, ridge, inset and outset are drawn solid
+        _ => vec![strip],
+    }
+}
 
 #[test]
 fn test_build_layout_tree() {
@@ -352,7 +2177,7 @@ fn test_build_layout_tree() {
     "#;
     let mut parser = HtmlParser::new(html1);
     let nodes = parser.parse_nodes();
-    let mut body = nodes[0].children[1].clone();
+    let mut body = nodes[0].child_element("body").unwrap().clone();
 
     let css = r#"
     .orange {
@@ -386,20 +2211,28 @@ fn test_build_layout_tree() {
     let boxes = layout::LayoutBox::build_layout_tree(&body);
     let boxes = crate::render::layout_box_tree_to_vector(boxes);
 
-    let body = &boxes[0];
+    let body_box = &boxes[0];
     let blue = &boxes[1];
     let orange1 = &boxes[3];
 
 
     let mut parser = HtmlParser::new(html2);
     let nodes = parser.parse_nodes();
-    let mut body = nodes[0].children[1].clone();
+    let mut body = nodes[0].child_element("body").unwrap().clone();
     body.add_styles(&stylesheet);
     let boxes = layout::LayoutBox::build_layout_tree(&body);
     let boxes = crate::render::layout_box_tree_to_vector(boxes);
     let orange2 = &boxes[2];
 
-    assert_eq!(orange1, orange2);
+    // body keeps the 8px margin of the user agent stylesheet, blocks fill its 584px;
+    // vertical margins collapse, body's top margin with #blue's and the siblings' with each other
+    assert_eq!(body_box.actual_dimensions, Dimensions { x: 8, y: 10, width: 584, height: 59 + 50 + 40 + 50 + 40 + 30 + 40 });
+    assert_eq!(blue.actual_dimensions, Dimensions { x: 18, y: 10, width: 564, height: 20 + 19 + 20 });
+    assert_eq!(blue.dimensions, Dimensions { x: 8, y: 0, width: 584, height: 10 + 59 + 10 });
+    assert_eq!(orange1.actual_dimensions, Dimensions { x: 58, y: 10 + 59 + 50, width: 484, height: 40 });
+    // without the line of text in #blue everything below it moves up by its height
+    assert_eq!(orange1.actual_dimensions.y - orange2.actual_dimensions.y, 19);
+    assert_eq!(orange1.actual_dimensions.width, orange2.actual_dimensions.width);
 
 
     // assert_eq!(boxes.len(), 6);
@@ -484,7 +2317,7 @@ fn test_calculate_position() {
 }"#;
     let mut parser = HtmlParser::new(html);
     let nodes = parser.parse_nodes();
-    let mut body = nodes[0].children[1].clone();
+    let mut body = nodes[0].child_element("body").unwrap().clone();
     let mut parser = CssParser::new(&css);
     let stylesheet = parser.parse_stylesheet();
     body.add_styles(&stylesheet);
@@ -494,9 +2327,498 @@ fn test_calculate_position() {
     let orange = &boxes[3];
     let green = &boxes[4];
 
-    assert_eq!(blue.dimensions.width, crate::render::WIDTH as i16);
-    assert_eq!(orange.dimensions.width, crate::render::WIDTH as i16);
-    assert_eq!(green.dimensions.width, crate::render::WIDTH as i16);
+    // every block fills the line inside the body's margins
+    let line = crate::render::WIDTH as i16 - 2 * 10;
+    assert_eq!(blue.dimensions.width, line);
+    assert_eq!(orange.dimensions.width, line);
+    assert_eq!(green.dimensions.width, line);
+    // orange has a width, margin-right takes what is left of the line
+    assert_eq!(orange.actual_dimensions, Dimensions { x: 11, y: 10 + 20 + 19 + 20 + 10, width: 40, height: 20 });
+    assert_eq!((orange.margin.left, orange.margin.right), (1, line - 1 - 40));
+    // the 1px and 10px margins between orange and green collapse into 10px
+    assert_eq!(green.actual_dimensions.y, orange.actual_dimensions.y + 20 + 10);
+}
+
+
+
+#[test]
+fn test_colors_carry_alpha() {
+    let mut parser = HtmlParser::new(r#"<body><div class="glass">text</div></body>"#);
+    let mut body = parser.parse_nodes().remove(0);
+    let css = ".glass { color: rgb(0 0 255 / 50%); background-color: currentColor; }";
+    let stylesheet = CssParser::new(css).parse_stylesheet();
+    body.add_styles(&stylesheet);
+    let boxes = crate::render::layout_box_tree_to_vector(LayoutBox::build_layout_tree(&body));
+    let glass = &boxes[1];
+    assert_eq!(glass.color, Color::new(0, 0, 255, 128));
+    assert_eq!(glass.background_color, Color::new(0, 0, 255, 128));
+}
+
+#[test]
+fn test_text_uses_font_properties() {
+    let mut parser = HtmlParser::new(r#"<body><div class="big">one</div><div>two</div><div class="tight">three</div></body>"#);
+    let mut body = parser.parse_nodes().remove(0);
+    let css = "body { font-size: 20px; } .big { font: bold 2em serif; line-height: 1.5; } .tight { line-height: 10px; }";
+    let stylesheet = CssParser::new(css).parse_stylesheet();
+    body.add_styles(&stylesheet);
+    let boxes = LayoutBox::build_layout_tree(&body);
+    let (big, normal, tight) = (&boxes[0].children[0], &boxes[0].children[1], &boxes[0].children[2]);
+
+    assert_eq!(big.font.size, 40.0);
+    assert!(big.font.is_bold());
+    assert_eq!(big.font.line_height, 60.0);
+    // font-size is inherited, `line-height: normal` follows it
+    assert_eq!(normal.font.size, 20.0);
+    assert!(!normal.font.is_bold());
+    assert_eq!(normal.font.line_height, 24.0);
+    assert_eq!(tight.font.line_height, 10.0);
+
+    // every line of text moves the text below it down by its own line height
+    let text_y = |box_: &LayoutBox| box_.children[0].content[0].y;
+    assert_eq!(text_y(normal) - text_y(big), 60);
+    assert_eq!(text_y(tight) - text_y(normal), 24);
+}
+
+#[test]
+fn test_borders() {
+    let mut parser = HtmlParser::new(r#"<body><div class="box">text</div></body>"#);
+    let mut body = parser.parse_nodes().remove(0);
+    let css = ".box { border: 2px solid red; border-left: 6px double blue; border-bottom-style: dashed; \
+        border-right-style: dotted; padding: 1px; margin: 0; }";
+    let stylesheet = CssParser::new(css).parse_stylesheet();
+    body.add_styles(&stylesheet);
+    let boxes = crate::render::layout_box_tree_to_vector(LayoutBox::build_layout_tree(&body));
+    let box_ = &boxes[1];
+
+    assert_eq!((box_.border.top, box_.border.right, box_.border.bottom, box_.border.left), (2, 2, 2, 6));
+    assert_eq!(box_.border_style.left, BorderStyle::Double);
+    assert_eq!(box_.border_color.left, Color::new(0, 0, 255, 255));
+    // the border is part of the box, the text starts inside it
+    assert_eq!(box_.dimensions.height, 2 + 2 + 1 + 1 + 19);
+    let content = &boxes[2].content[0];
+    assert_eq!(content.x, box_.actual_dimensions.x + 6 + 1);
+    assert_eq!(content.y, box_.actual_dimensions.y + 2 + 1);
+
+    let rectangles = box_.border_rectangles();
+    let Dimensions { x, y, width, height } = box_.actual_dimensions.clone();
+    // top is solid
+    assert_eq!(rectangles[0], (Dimensions { x, y, width, height: 2 }, Color::new(255, 0, 0, 255)));
+    let side = |color: Color| -> Vec<Dimensions> {
+        rectangles.iter().filter(|(_, c)| *c == color).map(|(d, _)| d.clone()).collect()
+    };
+    let red: Vec<Dimensions> = side(Color::new(255, 0, 0, 255));
+    // right is dotted: 2px dots every 4px between the top and bottom borders
+    let dots: Vec<&Dimensions> = red.iter().filter(|d| d.x == x + width - 2 && d.width == 2).collect();
+    assert!(dots.iter().all(|d| d.height <= 2));
+    assert_eq!(dots[1].y - dots[0].y, 4);
+    // bottom is dashed with 4px dashes
+    let dashes: Vec<&Dimensions> = red.iter().filter(|d| d.y == y + height - 2).collect();
+    assert_eq!(dashes[0], &Dimensions { x, y: y + height - 2, width: 4, height: 2 });
+    assert_eq!(dashes[1].x, x + 8);
+    // left is double: two 2px lines
+    let blue = side(Color::new(0, 0, 255, 255));
+    assert_eq!(blue, vec![Dimensions { x, y: y + 2, width: 2, height: height - 4 },
+                          Dimensions { x: x + 4, y: y + 2, width: 2, height: height - 4 }]);
+}
+
+#[cfg(test)]
+fn layout_body(html: &str, css: &str) -> LayoutBox {
+    let mut body = HtmlParser::new(html).parse_nodes().remove(0);
+    body.add_styles(&CssParser::new(css).parse_stylesheet());
+    LayoutBox::build_layout_tree(&body).remove(0)
+}
+
+#[test]
+fn test_block_widths() {
+    let body = layout_body(
+        r#"<body><div class="fixed"></div><div class="centered"></div><div class="right"></div><div class="half"></div><div class="wide"></div></body>"#,
+        "body { margin: 0; padding: 0 50px; } div { border: 5px solid; padding: 0 10px; } \
+         .fixed { width: 100px; margin-left: 20px; } .centered { width: 100px; margin: 0 auto; } \
+         .right { width: 100px; margin-left: auto; } .half { width: 50%; margin-left: 10%; } \
+         .wide { width: 600px; margin: 0 auto; }");
+    // the content box of body is 500px wide, each div has 30px of border and padding
+    let widths: Vec<(i16, i16, i16)> = body.children.iter()
+        .map(|div| (div.margin.left, div.content_box().width, div.margin.right))
+        .collect();
+    assert_eq!(widths, vec![
+        (20, 100, 500 - 20 - 30 - 100),
+        (185, 100, 185),
+        (370, 100, 0),
+        (50, 250, 500 - 50 - 30 - 250),
+        // too wide: auto margins are zero and margin-right goes negative
+        (0, 600, -130),
+    ]);
+    let centered = &body.children[1];
+    assert_eq!(centered.actual_dimensions.x, 50 + 185);
+    assert_eq!(centered.content_box().x, 50 + 185 + 5 + 10);
+}
+
+#[test]
+fn test_block_heights() {
+    let body = layout_body(
+        r#"<body><div class="outer"><div class="inner">a</div><div class="half"></div></div><div class="fixed">b</div></body>"#,
+        "body { margin: 0; } .outer { padding: 5px; border-top: 3px solid; } .inner { margin: 4px 0; line-height: 20px; } \
+         .half { height: 50%; } .fixed { height: 100px; margin-top: 7px; line-height: 20px; }");
+    let outer = &body.children[0];
+    let (inner, half) = (&outer.children[0], &outer.children[1]);
+    assert_eq!(inner.actual_dimensions, Dimensions { x: 5, y: 3 + 5 + 4, width: 590, height: 20 });
+    // a percentage of a height that depends on the content is auto
+    assert_eq!(half.actual_dimensions.height, 0);
+    // outer shrink-wraps its children
+    assert_eq!(outer.content_box().height, 4 + 20 + 4);
+    assert_eq!(outer.actual_dimensions.height, 3 + 5 + 28 + 5);
+    let fixed = &body.children[1];
+    assert_eq!(fixed.actual_dimensions, Dimensions { x: 0, y: 41 + 7, width: 600, height: 100 });
+    assert_eq!(fixed.children[0].actual_dimensions.height, 20);
+    // body is as high as its content, not the viewport
+    assert_eq!(body.actual_dimensions.height, 41 + 7 + 100);
+}
+
+#[test]
+fn test_huge_lengths_saturate() {
+    // sizes and positions past the range of i16 stop at its limits
+    let body = layout_body("<body><div>x</div></body>", "body { margin: 0; } div { border: 30000px solid; }");
+    let div = &body.children[0];
+    assert_eq!(div.actual_dimensions.width, i16::MAX);
+    assert_eq!(div.children[0].content[0].x, 30000);
+    assert!(!div.border_rectangles().is_empty());
+
+    let body = layout_body(&format!("<body>{}</body>", "<p></p>".repeat(2000)), "body, p { margin: 0; } p { height: 20px; }");
+    assert_eq!(body.children[1000].actual_dimensions.y, 20000);
+    assert_eq!(body.children[1999].actual_dimensions.y, i16::MAX);
+    assert_eq!(body.actual_dimensions.height, i16::MAX);
 }
 
+#[test]
+fn test_margin_collapsing() {
+    let body = layout_body(
+        r#"<body><div class="parent"><div class="first">a</div><div class="last">b</div></div><div class="empty"></div>
+<div class="negative">c</div><div class="padded"><div class="first">d</div></div></body>"#,
+        "body { margin: 0; line-height: 20px; } .parent { margin: 10px 0; } .first { margin: 25px 0 5px; } \
+         .last { margin: 15px 0 30px; } .empty { margin: 40px 0 -20px; } .negative { margin: -10px 0 10px; } \
+         .padded { padding-top: 1px; margin-top: 5px; }");
+    let y = |box_: &LayoutBox| box_.actual_dimensions.y;
+    let parent = &body.children[0];
+    let (first, last) = (&parent.children[0], &parent.children[1]);
+    // body, parent and first child share their top margin: max(0, 10, 25)
+    assert_eq!((y(&body), y(parent), y(first)), (25, 25, 25));
+    // siblings: max(5, 15)
+    assert_eq!(y(last), 25 + 20 + 15);
+    // parent ends with its last child, the margins below them are max(30, 10)
+    assert_eq!(parent.actual_dimensions.height, 15 + 20 + 20);
+    // the empty block's margins collapse through it with both neighbours':
+    // max(30, 40) + min(-20, -10)
+    let (empty, negative) = (&body.children[1], &body.children[2]);
+    assert_eq!(empty.actual_dimensions.height, 0);
+    assert_eq!(y(negative), 80 + 40 - 20);
+    // padding keeps the child's margin inside the box
+    let padded = &body.children[3];
+    assert_eq!(y(padded), 120 + 10);
+    assert_eq!(y(&padded.children[0]), 130 + 1 + 25);
+    assert_eq!(padded.actual_dimensions.height, 1 + 25 + 20);
+    assert_eq!(body.actual_dimensions.height, 176 - 25);
+}
 
+#[test]
+fn test_line_breaking() {
+    let body = layout_body(r#"<body><div class="narrow">aaaa bbbb cccc</div></body>"#,
+        "body { margin: 0; font-size: 10px; line-height: 10px; } .narrow { width: 50px; }");
+    let narrow = &body.children[0];
+    let text = &narrow.children[0];
+    // "aaaa bbbb" is 47.26px wide at 10px, with " cccc" it is 70.04px
+    assert_eq!(text.content, vec![
+        Content { x: 0, y: 0, text: "aaaa bbbb".to_string() },
+        Content { x: 0, y: 10, text: "cccc".to_string() },
+    ]);
+    assert_eq!(text.actual_dimensions, Dimensions { x: 0, y: 0, width: 47, height: 20 });
+    assert_eq!(narrow.actual_dimensions.height, 20);
+}
+
+#[test]
+fn test_inline_boxes() {
+    let body = layout_body(
+        r#"<body><div class="para">one <b>two</b> and <span class="pad">three four</span></div><div class="mixed">small <span class="big">BIG</span></div></body>"#,
+        "body { margin: 0; font-size: 10px; line-height: 10px; } .para { width: 60px; } \
+         .pad { padding: 0 5px; border-right: 2px solid; } .big { font-size: 20px; line-height: 20px; } \
+         .mixed { text-align: center; }");
+    let para = &body.children[0];
+    let (one, bold, and, pad) = (&para.children[0], &para.children[1], &para.children[2], &para.children[3]);
+    fn run(box_: &LayoutBox, index: usize) -> (&str, i16, i16) {
+        let content = &box_.content[index];
+        (content.text.as_str(), content.x, content.y)
+    }
+    assert_eq!(run(one, 0), ("one", 0, 0));
+    // the space before <b> is outside of it
+    assert_eq!(run(&bold.children[0], 0), ("two", 19, 0));
+    assert_eq!(bold.fragments, vec![Dimensions { x: 19, y: 0, width: 16, height: 10 }]);
+    assert_eq!(run(and, 0), ("and", 38, 0));
+    // the span with its padding doesn't fit after "and" and starts the second line
+    assert_eq!(run(&pad.children[0], 0), ("three four", 5, 10));
+    assert_eq!(pad.fragments, vec![Dimensions { x: 0, y: 10, width: 55, height: 10 }]);
+    assert_eq!(pad.border_rectangles(), vec![(Dimensions { x: 53, y: 10, width: 2, height: 10 }, Color::new(0, 0, 0, 255))]);
+    assert_eq!(para.actual_dimensions.height, 20);
+
+    // text of different sizes shares the baseline, the line makes room for both
+    let mixed = &body.children[1];
+    assert_eq!(mixed.actual_dimensions.height, 16 + 4);
+    let small = &mixed.children[0].content[0];
+    let big = &mixed.children[1].children[0].content[0];
+    assert_eq!((small.y, big.y), (20 + 8, 20));
+    // "small BIG" is 23.33px + 2.78px + 34.46px wide, centered in 600px
+    assert_eq!(small.x, 270);
+    assert_eq!(big.x, 296);
+}
+
+#[test]
+fn test_huge_inline_boxes_saturate() {
+    // fragments reaching past the range of i16 bound their box at its limits
+    let body = layout_body("<body><p>some <span>text in a span</span></p></body>", "body { margin: 0; } p { font-size: 100000px; }");
+    let span = &body.children[0].children[1];
+    assert_eq!(span.fragments.len(), 4);
+    assert_eq!(span.actual_dimensions.height, i16::MAX);
+
+    let body = layout_body(&format!("<body><p>{}</p></body>", "word ".repeat(40000)), "");
+    let text = &body.children[0].children[0];
+    // 15 words a line
+    assert_eq!(text.content.len(), 40000 / 15 + 1);
+    assert_eq!(text.actual_dimensions.height, i16::MAX);
+}
+
+#[test]
+fn test_space_between_elements() {
+    let body = layout_body(
+        "<body><p><b>bold</b> <i>it</i></p>\n<span class=\"shrink\"><b>bold</b>\n <i>it</i></span>\n<div class=\"blocks\">\n<div class=\"h\"></div>\n<div class=\"h\"></div>\n</div></body>",
+        "body { margin: 0; font-size: 10px; line-height: 10px; } p { margin: 0; } \
+         .shrink { display: inline-block; } .h { height: 10px; }");
+    // "bold" is 18.9px wide and the space between the elements 2.78px
+    let p = &body.children[0];
+    assert_eq!(p.children[2].children[0].content[0].x, 22);
+    // an inline-block fits the space too, so "it" stays on the first line
+    let shrink = body.children[1].children.iter().find(|child| child.name == "span").unwrap();
+    assert_eq!(shrink.actual_dimensions.width, 27);
+    assert_eq!(shrink.children[2].children[0].content[0].y, shrink.children[0].children[0].content[0].y);
+    // white space between blocks makes no boxes and no lines
+    let blocks = &body.children[2];
+    assert_eq!(blocks.children.len(), 2);
+    assert_eq!(blocks.actual_dimensions.height, 20);
+}
+
+#[test]
+fn test_display_types() {
+    let body = layout_body(
+        r#"<body><div class="gone"><p>hidden</p></div><div>ab <span class="button">cd ef</span> gh</div>
+<ul><li>one</li><li class="roman">two</li></ul><ol><li>a</li><li>b</li><li>c</li></ol></body>"#,
+        "body { margin: 0; font-size: 10px; line-height: 10px; } .gone { display: none; } \
+         .button { display: inline-block; padding: 0 2px; margin-top: 4px; } .roman { list-style-type: upper-roman; }");
+    // nothing is generated for display: none
+    assert_eq!(body.children.len(), 3);
+
+    let line = &body.children[0];
+    let (ab, button, gh) = (&line.children[0], &line.children[1], &line.children[2]);
+    // "cd ef" is 21.68px wide, the inline-block shrinks to it
+    assert_eq!(button.box_type, BoxType::InlineBlock);
+    assert_eq!(button.actual_dimensions, Dimensions { x: 14, y: 4, width: 2 + 22 + 2, height: 10 });
+    // its text sits on the baseline of the line, which makes room for the margin above it
+    assert_eq!(button.children[0].content[0], Content { x: 16, y: 4, text: "cd ef".to_string() });
+    assert_eq!((ab.content[0].y, gh.content[0].y), (4, 4));
+    assert_eq!(gh.content[0].x, 43);
+    assert_eq!(line.actual_dimensions.height, 14);
+
+    // markers end left of the item, on the baseline of its first line
+    let (ul, ol) = (&body.children[1], &body.children[2]);
+    let marker = |item: &LayoutBox| item.children[0].content[0].clone();
+    let bullet = marker(&ul.children[0]);
+    assert_eq!(ul.children[0].children[1].content[0].x, 40);
+    assert_eq!((bullet.text.as_str(), bullet.x, bullet.y), ("\u{2022}", 32, 24));
+    assert_eq!(marker(&ul.children[1]).text, "II.");
+    let numbers: Vec<String> = ol.children.iter().map(|item| marker(item).text).collect();
+    assert_eq!(numbers, vec!["1.", "2.", "3."]);
+}
+
+#[test]
+fn test_anonymous_boxes() {
+    let body = layout_body(
+        r#"<body><div class="mixed">before<p>para</p>after <b>bold</b></div><div><span class="split">one<div>block</div>two</span></div></body>"#,
+        "body { margin: 0; font-size: 10px; line-height: 10px; } p { margin: 0; } \
+         .split { border: 1px solid; padding: 0 3px; }");
+    let mixed = &body.children[0];
+    let names: Vec<&str> = mixed.children.iter().map(|child| child.name.as_str()).collect();
+    assert_eq!(names, vec!["anonymous", "p", "anonymous"]);
+    // text and blocks stay in document order
+    let y = |box_: &LayoutBox| box_.actual_dimensions.y;
+    assert_eq!((y(&mixed.children[0]), y(&mixed.children[1]), y(&mixed.children[2])), (0, 10, 20));
+    assert_eq!(mixed.children[2].children[1].name, "b");
+
+    // the span is broken around the block, its left edge stays on the first part
+    let split = &body.children[1];
+    let names: Vec<&str> = split.children.iter().map(|child| child.name.as_str()).collect();
+    assert_eq!(names, vec!["anonymous", "div", "anonymous"]);
+    let (first, second) = (&split.children[0].children[0], &split.children[2].children[0]);
+    assert_eq!((first.name.as_str(), second.name.as_str()), ("span", "span"));
+    assert_eq!((first.border.left, first.border.right, second.border.left, second.border.right), (1, 0, 0, 1));
+    assert_eq!(first.children[0].content[0], Content { x: 4, y: 30, text: "one".to_string() });
+    assert_eq!(split.children[1].children[0].content[0].y, 40);
+    assert_eq!(second.children[0].content[0], Content { x: 0, y: 50, text: "two".to_string() });
+    // "two" is 15.6px wide
+    assert_eq!(second.fragments[0].width, 20);
+}
+
+#[cfg(test)]
+fn border_box(box_: &LayoutBox) -> (i16, i16, i16, i16) {
+    let dimensions = &box_.actual_dimensions;
+    (dimensions.x, dimensions.y, dimensions.width, dimensions.height)
+}
+
+#[test]
+fn test_flex_grow_and_shrink() {
+    let body = layout_body(
+        r#"<body><div class="grow"><div class="a"></div><div class="b"></div><div class="c"></div></div><div class="shrink"><div class="s"></div><div class="s more"></div><div class="s none"></div></div></body>"#,
+        "body { margin: 0; } .grow { display: flex; width: 500px; height: 50px; column-gap: 10px; } \
+         .a { width: 100px; flex-grow: 1; } .b { flex: 2 0 60px; } .c { width: 100px; padding: 0 5px; } \
+         .shrink { display: flex; width: 200px; } .s { width: 100px; height: 10px; } \
+         .more { flex-shrink: 3; } .none { flex-shrink: 0; }");
+    // 210px of free space, a takes a third and b two
+    let grow: Vec<_> = body.children[0].children.iter().map(border_box).collect();
+    assert_eq!(grow, vec![(0, 0, 170, 50), (180, 0, 200, 50), (390, 0, 110, 50)]);
+    // 100px too much, taken in proportion to flex-shrink times the base size
+    let shrink: Vec<_> = body.children[1].children.iter().map(border_box).collect();
+    assert_eq!(shrink, vec![(0, 50, 75, 10), (75, 50, 25, 10), (100, 50, 100, 10)]);
+    assert_eq!(body.children[1].actual_dimensions.height, 10);
+}
+
+#[test]
+fn test_flex_wrap_and_alignment() {
+    let body = layout_body(
+        r#"<body><div class="wrap"><div class="w120 h30"></div><div class="w120 h50"></div><div class="w120 h40"></div><div class="w120 h10"></div></div><div class="base"><div class="big">A</div><div class="small">a</div></div></body>"#,
+        "body { margin: 0; } .wrap { display: flex; flex-wrap: wrap; width: 300px; height: 200px; \
+         justify-content: space-between; align-content: flex-start; align-items: center; row-gap: 20px; } \
+         .w120 { width: 120px; } .h30 { height: 30px; } .h50 { height: 50px; } .h40 { height: 40px; } .h10 { height: 10px; } \
+         .base { display: flex; align-items: baseline; } .big { font-size: 20px; line-height: 20px; } \
+         .small { font-size: 10px; line-height: 10px; }");
+    // two lines 50px and 40px high, 20px apart, each item centered in its line
+    let wrap: Vec<_> = body.children[0].children.iter().map(border_box).collect();
+    assert_eq!(wrap, vec![(0, 10, 120, 30), (180, 0, 120, 50), (0, 70, 120, 40), (180, 85, 120, 10)]);
+    // baselines at 16px and 8px below the tops line up
+    let base = &body.children[1];
+    assert_eq!((base.children[0].actual_dimensions.y, base.children[1].actual_dimensions.y), (200, 208));
+    assert_eq!(base.actual_dimensions.height, 20);
+}
+
+#[test]
+fn test_flex_directions() {
+    let body = layout_body(
+        r#"<body><div class="col"><div class="one"></div><div class="two"></div><div class="three"></div></div><div class="reverse"><div class="r"></div><div class="r"></div></div><div class="row">one <b>two</b></div><span class="inline"><b class="w"></b><b class="w"></b></span></body>"#,
+        "body { margin: 0; font-size: 10px; line-height: 10px; } \
+         .col { display: flex; flex-direction: column; width: 200px; align-items: flex-end; } \
+         .one { order: 2; width: 50px; height: 30px; } .two { height: 40px; } \
+         .three { order: -1; width: 100px; height: 10px; margin: 0 auto; } \
+         .reverse { display: flex; flex-direction: row-reverse; width: 300px; } .r { width: 100px; height: 5px; } \
+         .row { display: flex; } .inline { display: inline-flex; column-gap: 5px; } \
+         .w { width: 50px; height: 10px; }");
+    // in `order`: three, two, one; an auto width fits the content unless stretched
+    let col = &body.children[0];
+    assert_eq!(border_box(&col.children[2]), (50, 0, 100, 10));
+    assert_eq!(border_box(&col.children[1]), (200, 10, 0, 40));
+    assert_eq!(border_box(&col.children[0]), (150, 50, 50, 30));
+    assert_eq!(col.actual_dimensions.height, 80);
+
+    let reverse: Vec<_> = body.children[1].children.iter().map(|item| item.actual_dimensions.x).collect();
+    assert_eq!(reverse, vec![200, 100]);
+
+    // text becomes an anonymous item and the inline box is blockified
+    let row = &body.children[2];
+    let names: Vec<&str> = row.children.iter().map(|item| item.name.as_str()).collect();
+    assert_eq!(names, vec!["anonymous", "b"]);
+    assert_eq!(row.children[1].box_type, BoxType::Block);
+    // "one" is 16.7px wide
+    assert_eq!(border_box(&row.children[1]).0, 17);
+
+    // an inline flex container shrinks to its items and the gap
+    let inline = &body.children[3].children[0];
+    assert_eq!(inline.actual_dimensions.width, 105);
+}
+
+#[test]
+fn test_huge_flex_items_saturate() {
+    let body = layout_body("<body><div><p>x</p><p>y</p></div></body>",
+        "body { margin: 0; } div { display: flex; } p { margin: 30000px; padding: 30000px; }");
+    let items = &body.children[0].children;
+    assert_eq!(items[0].actual_dimensions.x, 30000);
+    assert_eq!(items[1].actual_dimensions.x, i16::MAX);
+    assert_eq!(body.actual_dimensions.height, i16::MAX);
+}
+
+#[test]
+fn test_grid_track_sizing() {
+    let body = layout_body(
+        r#"<body><div class="fr"><div></div><div></div><div></div><div></div></div><div class="mix"><div></div><div></div><div>two</div></div><span class="inline"><b class="w"></b><b class="w"></b></span></body>"#,
+        "body { margin: 0; font-size: 10px; line-height: 10px; } \
+         .fr { display: grid; width: 500px; grid-template-columns: 100px 1fr 2fr; grid-auto-rows: 20px; gap: 5px 10px; } \
+         .mix { display: grid; width: 300px; grid-template-columns: repeat(2, minmax(50px, 100px)) auto; } \
+         .inline { display: inline-grid; grid-template-columns: auto 1fr; column-gap: 4px; } .w { width: 50px; }");
+    // 380px for the flexible columns, a third for 1fr
+    let fr: Vec<_> = body.children[0].children.iter().map(border_box).collect();
+    assert_eq!(fr, vec![(0, 0, 100, 20), (110, 0, 127, 20), (247, 0, 253, 20), (0, 25, 100, 20)]);
+    assert_eq!(body.children[0].actual_dimensions.height, 45);
+    // the minmax() columns grow to their limits, the auto one takes the rest
+    let mix: Vec<_> = body.children[1].children.iter().map(border_box).collect();
+    assert_eq!(mix, vec![(0, 45, 100, 10), (100, 45, 100, 10), (200, 45, 100, 10)]);
+    // an inline grid shrinks to its columns, 1fr as wide as its item
+    assert_eq!(body.children[2].children[0].actual_dimensions.width, 104);
+}
+
+#[test]
+fn test_huge_grid_items_saturate() {
+    let body = layout_body("<body><div><p>x</p><p>y</p><p>z</p></div></body>",
+        "body { margin: 0; } div { display: grid; grid-template-columns: 1fr 1fr; } \
+         p { margin: 30000px; padding: 30000px; } p + p + p { margin: auto; padding: 0; }");
+    let items = &body.children[0].children;
+    assert_eq!(items[0].actual_dimensions.x, 30000);
+    assert_eq!(items[1].actual_dimensions.x, i16::MAX);
+    assert_eq!(body.actual_dimensions.height, i16::MAX);
+}
+
+#[test]
+fn test_grid_placement() {
+    let body = layout_body(
+        r#"<body><div class="p"><div class="a"></div><div></div><div class="c"></div><div class="d"></div><div class="e"></div></div><div class="neg"><div class="before"></div><div></div></div><div class="flow"><div></div><div class="tall"></div><div></div></div><div class="flow dense"><div></div><div class="tall"></div><div></div></div></body>"#,
+        "body { margin: 0; } .p { display: grid; grid-template-columns: repeat(3, 50px); grid-auto-rows: 10px; } \
+         .a { grid-column: 2 / span 2; grid-row: 1; } .c { grid-row: 2 / 4; } .d { grid-column: -2; } .e { grid-column: span 2; } \
+         .neg { display: grid; grid-template-columns: 50px; grid-auto-columns: 20px; grid-auto-rows: 10px; } \
+         .before { grid-column: -3; } \
+         .flow { display: grid; grid-auto-flow: column; grid-template-rows: 10px 10px; grid-auto-columns: 30px; } \
+         .dense { grid-auto-flow: column dense; } .tall { grid-row: span 2; }");
+    // auto-placed items go around the ones placed by line
+    let p: Vec<_> = body.children[0].children.iter().map(border_box).collect();
+    assert_eq!(p, vec![(50, 0, 100, 10), (0, 0, 50, 10), (0, 10, 50, 20), (100, 10, 50, 10), (50, 20, 100, 10)]);
+    assert_eq!(body.children[0].actual_dimensions.height, 30);
+    // a line before the explicit grid adds an implicit column in front
+    let neg: Vec<_> = body.children[1].children.iter().map(border_box).collect();
+    assert_eq!(neg, vec![(0, 30, 20, 10), (20, 30, 50, 10)]);
+    // column flow fills the rows of a column first, dense packing goes back to the hole
+    let sparse: Vec<_> = body.children[2].children.iter().map(border_box).collect();
+    assert_eq!(sparse, vec![(0, 40, 30, 10), (30, 40, 30, 20), (60, 40, 30, 10)]);
+    let dense: Vec<_> = body.children[3].children.iter().map(border_box).collect();
+    assert_eq!(dense, vec![(0, 60, 30, 10), (30, 60, 30, 20), (0, 70, 30, 10)]);
+}
+
+#[test]
+fn test_grid_areas_and_alignment() {
+    let body = layout_body(
+        r#"<body><div class="t"><div class="f"></div><div class="m"></div><div class="n"></div><div class="h"></div></div><div class="al"><div class="x"></div><div class="y"></div><div class="z"></div></div></body>"#,
+        "body { margin: 0; } \
+         .t { display: grid; width: 400px; grid-template-columns: 100px 1fr; grid-template-rows: 20px auto 20px; \
+         grid-template-areas: 'head head' 'nav main' 'foot foot'; } \
+         .h { grid-area: head; } .n { grid-area: nav; } .m { grid-area: main; height: 50px; } .f { grid-area: foot; } \
+         .al { display: grid; width: 300px; height: 120px; grid-template-columns: 100px 100px; grid-auto-rows: 50px; \
+         row-gap: 10px; justify-content: center; align-content: end; justify-items: center; } \
+         .x { width: 20px; height: 10px; } .y { justify-self: stretch; align-self: end; height: 10px; } \
+         .z { width: 40px; height: 20px; margin: auto; }");
+    // items go to their named areas whatever their order, nav stretches to the row
+    let t: Vec<_> = body.children[0].children.iter().map(border_box).collect();
+    assert_eq!(t, vec![(0, 70, 400, 20), (100, 20, 300, 50), (0, 20, 100, 50), (0, 0, 400, 20)]);
+    assert_eq!(body.children[0].actual_dimensions.height, 90);
+    // the tracks are centered and at the bottom, the items aligned in their areas
+    let al: Vec<_> = body.children[1].children.iter().map(border_box).collect();
+    assert_eq!(al, vec![(90, 100, 20, 10), (150, 140, 100, 10), (80, 175, 40, 20)]);
+}
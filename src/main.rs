@@ -19,6 +19,11 @@ mod css_parser;
 mod css;
 mod js;
 mod browser;
+mod style;
+mod cssom;
+mod rule_index;
+#[cfg(test)]
+mod css_conformance;
 
 use render::render;
 use css_parser::CssParser;
@@ -1,4 +1,5 @@
 use std::any::Any;
+use std::collections::HashMap;
 use std::thread::sleep;
 use std::time::Duration;
 use gfx;
@@ -8,13 +9,14 @@ use gfx::traits::FactoryExt;
 use glutin::GlContext;
 use gfx::Factory;
 use gfx_text::Renderer;
+use crate::css::Viewport;
 use crate::css_parser::CssParser;
 
 use crate::layout;
 use crate::dom;
 use crate::dom::NodeType;
 use crate::html_parser::HtmlParser;
-use crate::layout::{Color, Content, LayoutBox};
+use crate::layout::{Color, Content, Dimensions, Font, LayoutBox};
 
 pub type ColorFormat = gfx::format::Rgba8;
 pub type DepthFormat = gfx::format::DepthStencil;
@@ -33,26 +35,39 @@ gfx_defines! {
 
     pipeline pipe {
         vbuf: gfx::VertexBuffer<Vertex> = (),
-        out: gfx::RenderTarget<ColorFormat> = "Target0",
+        // alpha blending, so that rgba() and transparent backgrounds show what is behind them
+        out: gfx::BlendTarget<ColorFormat> = ("Target0", gfx::state::MASK_ALL, gfx::preset::blend::ALPHA),
     }
 }
 
 
-fn transform_rectangle(box_: &LayoutBox) -> (f32, f32, f32, f32) {
-    let w = box_.actual_dimensions.width as f32 / (WIDTH / 2) as f32;
-    let h = box_.actual_dimensions.height as f32 / (HEIGHT / 2) as f32;
-    let x = box_.actual_dimensions.x as f32 / WIDTH as f32 * 2.0 - 1.0;
-    let y = -(box_.actual_dimensions.y as f32 / HEIGHT as f32 * 2.0 - 1.0);
+fn transform_rectangle(rectangle: &Dimensions, viewport: &Viewport) -> (f32, f32, f32, f32) {
+    let w = rectangle.width as f32 / (viewport.width / 2.0);
+    let h = rectangle.height as f32 / (viewport.height / 2.0);
+    let x = rectangle.x as f32 / viewport.width * 2.0 - 1.0;
+    let y = -(rectangle.y as f32 / viewport.height * 2.0 - 1.0);
 
     (w, h, x, y)
 }
 
-fn render_content(box_: &LayoutBox) -> Vec<Vertex> {
-    let (w, h, x, y) = transform_rectangle(box_);
-    vec![Vertex { pos: [x, y], color: box_.background_color.to_array() },
-         Vertex { pos: [x, y - h], color: box_.background_color.to_array() },
-         Vertex { pos: [x + w, y - h], color: box_.background_color.to_array() },
-         Vertex { pos: [x + w, y], color: box_.background_color.to_array() }]
+fn render_rectangle(rectangle: &Dimensions, color: &Color, viewport: &Viewport) -> Vec<Vertex> {
+    let (w, h, x, y) = transform_rectangle(rectangle, viewport);
+    vec![Vertex { pos: [x, y], color: color.to_array() },
+         Vertex { pos: [x, y - h], color: color.to_array() },
+         Vertex { pos: [x + w, y - h], color: color.to_array() },
+         Vertex { pos: [x + w, y], color: color.to_array() }]
+}
+
+/// The background, then the border on top of it.
+fn render_content(box_: &LayoutBox, viewport: &Viewport) -> Vec<Vertex> {
+    let mut vertices = Vec::new();
+    for area in box_.border_boxes() {
+        vertices.append(&mut render_rectangle(&area, &box_.background_color, viewport));
+    }
+    for (rectangle, color) in box_.border_rectangles() {
+        vertices.append(&mut render_rectangle(&rectangle, &color, viewport));
+    }
+    vertices
 }
 
 pub fn layout_box_tree_to_vector(boxes_tree: Vec<LayoutBox>) -> Vec<LayoutBox> {
@@ -65,39 +80,70 @@ pub fn layout_box_tree_to_vector(boxes_tree: Vec<LayoutBox>) -> Vec<LayoutBox> {
 }
 
 
-pub fn render(boxes: Vec<LayoutBox>, title: &String) {
+/// A run of text and the font it is drawn with.
+struct Text {
+    text: String,
+    position: [i32; 2],
+    color: [f32; 4],
+    font: Font,
+}
+
+impl Text {
+    /// gfx_text renders one pixel size per renderer.
+    fn pixel_size(&self) -> u8 {
+        self.font.size.round().max(1.0).min(u8::MAX as f32) as u8
+    }
+}
+
+/// Vertices, indices and text runs to draw for a laid out tree.
+fn build_scene(boxes: Vec<LayoutBox>, viewport: &Viewport) -> (Vec<Vertex>, Vec<u32>, Vec<Text>) {
     let boxes = layout_box_tree_to_vector(boxes);
     let mut vertices = Vec::new();
     let mut index_data = Vec::new();
     let mut text_vec = Vec::new();
-    for (rect_num, box_) in boxes.iter().enumerate() {
-            let mut v = render_content(box_);
+    for box_ in boxes.iter() {
+            let mut v = render_content(box_, viewport);
+            // two triangles for every rectangle; dotted borders add many, so indices are u32
+            for index_base in (vertices.len()..vertices.len() + v.len()).step_by(4) {
+                let index_base = index_base as u32;
+                index_data.append(&mut vec![
+                    index_base,
+                    index_base + 1,
+                    index_base + 2,
+                    index_base + 2,
+                    index_base + 3,
+                    index_base,
+                ]);
+            }
             vertices.append(&mut v);
-            let index_base: u16 = rect_num as u16 * 4;
-            index_data.append(&mut vec![
-                index_base,
-                index_base + 1,
-                index_base + 2,
-                index_base + 2,
-                index_base + 3,
-                index_base,
-            ]);
-            if let Some(content) = &box_.content{
-                text_vec.push((
-                    &content.text,
-                    [content.x as i32, content.y as i32],
-                    box_.color.to_array())
-                );
+            for content in &box_.content {
+                text_vec.push(Text {
+                    text: content.text.clone(),
+                    position: [content.x as i32, content.y as i32],
+                    color: box_.color.to_array(),
+                    font: box_.font.clone(),
+                });
         }
 
     }
+    (vertices, index_data, text_vec)
+}
+
+/// Opens a window showing `boxes`. When the window is resized, `relayout` is
+/// called with the new viewport so that media queries and percentages are
+/// evaluated again.
+pub fn render<F>(boxes: Vec<LayoutBox>, title: &String, mut relayout: F)
+    where F: FnMut(&Viewport) -> Vec<LayoutBox> {
+    let mut viewport = Viewport::default();
+    let (vertices, index_data, mut text_vec) = build_scene(boxes, &viewport);
     let builder = glutin::WindowBuilder::new()
         .with_title(title)
         .with_dimensions(WIDTH, HEIGHT)
         .with_vsync();
 
-    let (window, mut device, mut factory, main_color, _main_depth) =
+    let (window, mut device, mut factory, mut main_color, mut main_depth) =
         gfx_window_glutin::init::<ColorFormat, DepthFormat>(builder);
+    viewport.resolution = window.hidpi_factor();
 
 
     let mut encoder: gfx::Encoder<_, _> = factory.create_command_buffer().into();
@@ -109,12 +155,12 @@ pub fn render(boxes: Vec<LayoutBox>, title: &String) {
         )
         .unwrap();
 
-    let (vertex_buffer, slice) = factory.create_vertex_buffer_with_slice(&vertices, &index_data[..]);
+    let (vertex_buffer, mut slice) = factory.create_vertex_buffer_with_slice(&vertices, &index_data[..]);
 
-    let mut text_renderer = gfx_text::new(factory).build().unwrap();
-    let data = pipe::Data {
+    let mut text_renderers = HashMap::new();
+    let mut data = pipe::Data {
         vbuf: vertex_buffer,
-        out: main_color,
+        out: main_color.clone(),
     };
 
 
@@ -124,6 +170,17 @@ pub fn render(boxes: Vec<LayoutBox>, title: &String) {
             match event {
                 glutin::Event::KeyboardInput(_, _, Some(glutin::VirtualKeyCode::Escape)) |
                 glutin::Event::Closed => running = false,
+                glutin::Event::Resized(width, height) => {
+                    gfx_window_glutin::update_views(&window, &mut main_color, &mut main_depth);
+                    viewport.width = width as f32 / viewport.resolution;
+                    viewport.height = height as f32 / viewport.resolution;
+                    let (vertices, index_data, texts) = build_scene(relayout(&viewport), &viewport);
+                    let (vertex_buffer, new_slice) = factory.create_vertex_buffer_with_slice(&vertices, &index_data[..]);
+                    data.vbuf = vertex_buffer;
+                    data.out = main_color.clone();
+                    slice = new_slice;
+                    text_vec = texts;
+                }
                 _ => {}
             }
         }
@@ -132,15 +189,23 @@ pub fn render(boxes: Vec<LayoutBox>, title: &String) {
         encoder.draw(&slice, &pso, &data);
 
         for text in &text_vec {
-            text_renderer.add(text.0, text.1, text.2);
+            let size = text.pixel_size();
+            let text_renderer = text_renderers.entry(size)
+                .or_insert_with(|| gfx_text::new(factory.clone()).with_size(size).build().unwrap());
+            text_renderer.add(&text.text, text.position, text.color);
+            // there is only the one face, bold text is drawn twice a pixel apart
+            if text.font.is_bold() {
+                text_renderer.add(&text.text, [text.position[0] + 1, text.position[1]], text.color);
+            }
         }
 
         sleep(Duration::from_millis(10));
 
-        text_renderer.draw(&mut encoder, &data.out).unwrap();
+        for text_renderer in text_renderers.values_mut() {
+            text_renderer.draw(&mut encoder, &data.out).unwrap();
+        }
         encoder.flush(&mut device);
         window.swap_buffers().unwrap();
         device.cleanup();
     }
 }
-
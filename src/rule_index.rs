@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use crate::css::{Origin, Rule, Specificity, Stylesheet, Viewport};
+use crate::dom::{matches, ElementData};
+
+/// A rule that applies in the viewport, with what the cascade needs to
+/// order it.
+#[derive(Clone, Copy)]
+pub struct IndexedRule<'a> {
+    pub rule: &'a Rule,
+    pub origin: Origin,
+    pub specificity: Specificity,
+    pub sheet_index: usize,
+    pub rule_index: usize,
+}
+
+/// The rules of a set of stylesheets, bucketed by the most selective part
+/// of their selector: id, then the first class, then the tag. An element
+/// only has to be matched against the buckets of its own id, classes and
+/// tag, and the universal rules.
+pub struct RuleIndex<'a> {
+    by_id: HashMap<&'a str, Vec<IndexedRule<'a>>>,
+    by_class: HashMap<&'a str, Vec<IndexedRule<'a>>>,
+    by_tag: HashMap<&'a str, Vec<IndexedRule<'a>>>,
+    universal: Vec<IndexedRule<'a>>,
+    /// Number of stylesheets, `style` attributes cascade as if they came after all of them.
+    pub sheet_count: usize,
+}
+
+impl<'a> RuleIndex<'a> {
+    /// Indexes the rules of `stylesheets` whose media match `viewport`.
+    pub fn new(stylesheets: &[&'a Stylesheet], viewport: &Viewport) -> RuleIndex<'a> {
+        let mut index = RuleIndex {
+            by_id: HashMap::new(),
+            by_class: HashMap::new(),
+            by_tag: HashMap::new(),
+            universal: Vec::new(),
+            sheet_count: stylesheets.len(),
+        };
+        for (sheet_index, stylesheet) in stylesheets.iter().enumerate() {
+            for (rule_index, rule) in stylesheet.rules.iter().enumerate() {
+                if !rule.applies_to(viewport) {
+                    continue;
+                }
+                let indexed = IndexedRule {
+                    rule,
+                    origin: stylesheet.origin,
+                    specificity: rule.selector.specificity(),
+                    sheet_index,
+                    rule_index,
+                };
+                let selector = &rule.selector;
+                if let Some(id) = &selector.id {
+                    index.by_id.entry(id.as_str()).or_default().push(indexed);
+                } else if let Some(class) = selector.classes.first() {
+                    index.by_class.entry(class.as_str()).or_default().push(indexed);
+                } else if let Some(tag_name) = &selector.tag_name {
+                    index.by_tag.entry(tag_name.as_str()).or_default().push(indexed);
+                } else {
+                    index.universal.push(indexed);
+                }
+            }
+        }
+        index
+    }
+
+    /// Rules whose selector matches `element`, in no particular order.
+    ///
+    /// Selectors are compound only, the parser drops rules with
+    /// combinators, so matching never looks at ancestors. A bloom filter
+    /// of ancestor ids, classes and tags only pays off once descendant
+    /// selectors exist, it's a separate backlog item.
+    pub fn matching_rules(&self, element: &ElementData) -> Vec<IndexedRule<'a>> {
+        let mut candidates: Vec<&IndexedRule<'a>> = Vec::new();
+        if let Some(rules) = element.get_id().and_then(|id| self.by_id.get(id.as_str())) {
+            candidates.extend(rules);
+        }
+        for class in element.get_classes() {
+            if let Some(rules) = self.by_class.get(class) {
+                candidates.extend(rules);
+            }
+        }
+        if let Some(rules) = self.by_tag.get(element.tag_name.as_str()) {
+            candidates.extend(rules);
+        }
+        candidates.extend(&self.universal);
+        candidates.into_iter()
+            .filter(|indexed| matches(element, &indexed.rule.selector))
+            .copied()
+            .collect()
+    }
+}
+
+#[test]
+fn test_rule_index_buckets() {
+    use crate::css_parser::CssParser;
+
+    let css = "* { color: red; } div { color: blue; } .a { color: green; } div.b.a { color: black; } #x { color: white; } \
+               span { color: gray; } .c { color: pink; } @media (max-width: 10px) { div { color: navy; } }";
+    let stylesheet = CssParser::new(css).parse_stylesheet();
+    let index = RuleIndex::new(&[&stylesheet], &Viewport::default());
+    assert_eq!(index.universal.len(), 1);
+    // the rule in @media doesn't apply to a 600px wide viewport
+    assert_eq!(index.by_tag.get("div").map(Vec::len), Some(1));
+    assert_eq!(index.by_class.get("a").map(Vec::len), Some(1));
+    assert_eq!(index.by_class.get("b").map(Vec::len), Some(1));
+
+    let mut attributes = HashMap::new();
+    attributes.insert("id".to_string(), "x".to_string());
+    attributes.insert("class".to_string(), "a b".to_string());
+    let element = ElementData::new("div".to_string(), attributes);
+    let mut matched: Vec<usize> = index.matching_rules(&element).iter().map(|indexed| indexed.rule_index).collect();
+    matched.sort();
+    assert_eq!(matched, vec![0, 1, 2, 3, 4]);
+}
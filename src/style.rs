@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+use crate::css::{BorderStyle, Color, DisplayType, GridLine, Length, PropertyName, PropertyValue, Track, TrackBreadth, TrackList};
+
+/// Computed values of a node in the form layout uses them: lengths in px,
+/// colors without `currentColor` and keywords as enums. Percentages and `auto`
+/// need the containing block and are resolved by layout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComputedStyle {
+    pub display: DisplayType,
+    pub color: Color,
+    pub background_color: Color,
+    pub width: Size,
+    pub height: Size,
+    pub margin: Sides<Size>,
+    pub padding: Sides<Length>,
+    pub border_width: Sides<f32>,
+    pub border_style: Sides<BorderStyle>,
+    pub border_color: Sides<Color>,
+    pub font_style: FontStyle,
+    pub font_weight: f32,
+    pub font_size: f32,
+    pub line_height: LineHeight,
+    pub font_family: Vec<String>,
+    pub list_style_type: ListStyleType,
+    pub text_align: TextAlign,
+    pub visibility: Visibility,
+    pub white_space: WhiteSpace,
+    pub flex_direction: FlexDirection,
+    pub flex_wrap: FlexWrap,
+    pub flex_grow: f32,
+    pub flex_shrink: f32,
+    pub flex_basis: FlexBasis,
+    pub order: i32,
+    pub justify_content: Alignment,
+    pub align_items: Alignment,
+    pub align_self: Alignment,
+    pub align_content: Alignment,
+    pub row_gap: Length,
+    pub column_gap: Length,
+    pub justify_items: Alignment,
+    pub justify_self: Alignment,
+    pub grid_template_columns: TrackList,
+    pub grid_template_rows: TrackList,
+    /// the rows of `grid-template-areas`, `.` for cells outside any area
+    pub grid_template_areas: Vec<Vec<String>>,
+    pub grid_auto_columns: Vec<Track>,
+    pub grid_auto_rows: Vec<Track>,
+    pub grid_auto_flow: GridAutoFlow,
+    pub grid_row_start: GridLine,
+    pub grid_row_end: GridLine,
+    pub grid_column_start: GridLine,
+    pub grid_column_end: GridLine,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sides<T> {
+    pub top: T,
+    pub right: T,
+    pub bottom: T,
+    pub left: T,
+}
+
+/// A length, or `auto` which layout decides.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Size {
+    Auto,
+    Length(Length),
+}
+
+impl Size {
+    /// Used value in px, `None` for `auto`.
+    pub fn resolve(&self, percent_base: f32) -> Option<f32> {
+        match self {
+            Size::Auto => None,
+            Size::Length(length) => Some(length.resolve(percent_base)),
+        }
+    }
+
+    /// Used height in px, `None` for `auto`. Percentages of a containing
+    /// block whose height depends on its content behave as `auto`.
+    pub fn resolve_height(&self, containing_height: Option<f32>) -> Option<f32> {
+        match (self, containing_height) {
+            (Size::Length(Length::Px(px)), _) => Some(*px),
+            (Size::Length(length), Some(height)) => Some(length.resolve(height)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineHeight {
+    Normal,
+    /// multiple of the font size
+    Number(f32),
+    Px(f32),
+}
+
+impl LineHeight {
+    /// Used line height in px for a font of `font_size`.
+    pub fn resolve(&self, font_size: f32) -> f32 {
+        match self {
+            LineHeight::Normal => font_size * 1.2,
+            LineHeight::Number(number) => font_size * number,
+            LineHeight::Px(px) => *px,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListStyleType {
+    None,
+    Disc,
+    Circle,
+    Square,
+    Decimal,
+    LowerAlpha,
+    UpperAlpha,
+    LowerRoman,
+    UpperRoman,
+}
+
+impl ListStyleType {
+    /// Text of the marker of the `ordinal`th list item, `None` without one.
+    pub fn marker(&self, ordinal: usize) -> Option<String> {
+        let alphabetic = |first: u8| {
+            let (mut n, mut letters) = (ordinal, String::new());
+            while n > 0 {
+                n -= 1;
+                letters.insert(0, (first + (n % 26) as u8) as char);
+                n /= 26;
+            }
+            letters
+        };
+        match self {
+            ListStyleType::None => None,
+            ListStyleType::Disc => Some(String::from("\u{2022}")),
+            ListStyleType::Circle => Some(String::from("\u{25e6}")),
+            ListStyleType::Square => Some(String::from("\u{25aa}")),
+            ListStyleType::Decimal => Some(format!("{}.", ordinal)),
+            ListStyleType::LowerAlpha => Some(format!("{}.", alphabetic(b'a'))),
+            ListStyleType::UpperAlpha => Some(format!("{}.", alphabetic(b'A'))),
+            ListStyleType::LowerRoman => Some(format!("{}.", roman(ordinal).to_lowercase())),
+            ListStyleType::UpperRoman => Some(format!("{}.", roman(ordinal))),
+        }
+    }
+}
+
+/// Roman numeral of `n`, decimal digits outside of 1 to 3999.
+fn roman(mut n: usize) -> String {
+    if n == 0 || n > 3999 {
+        return n.to_string();
+    }
+    let numerals = [(1000, "M"), (900, "CM"), (500, "D"), (400, "CD"), (100, "C"), (90, "XC"),
+        (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I")];
+    let mut roman = String::new();
+    for (value, numeral) in numerals {
+        while n >= value {
+            roman.push_str(numeral);
+            n -= value;
+        }
+    }
+    roman
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAlign {
+    Start,
+    End,
+    Left,
+    Right,
+    Center,
+    Justify,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Visibility {
+    Visible,
+    Hidden,
+    Collapse,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WhiteSpace {
+    Normal,
+    Pre,
+    Nowrap,
+    PreWrap,
+    PreLine,
+    BreakSpaces,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlexDirection {
+    Row,
+    RowReverse,
+    Column,
+    ColumnReverse,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlexWrap {
+    NoWrap,
+    Wrap,
+    WrapReverse,
+}
+
+/// Initial main size of a flex item: `auto` takes `width` or `height`,
+/// `content` sizes the item to its content.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FlexBasis {
+    Auto,
+    Content,
+    Length(Length),
+}
+
+/// `grid-auto-flow`: which axis auto-placement fills first, and whether it
+/// goes back to fill holes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GridAutoFlow {
+    pub column: bool,
+    pub dense: bool,
+}
+
+/// A value of `justify-content` or of one of the `align-*` properties.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Alignment {
+    Auto,
+    Normal,
+    Stretch,
+    Start,
+    End,
+    FlexStart,
+    FlexEnd,
+    Center,
+    Left,
+    Right,
+    Baseline,
+    SpaceBetween,
+    SpaceAround,
+    SpaceEvenly,
+}
+
+impl Alignment {
+    fn from_keyword(keyword: &str) -> Alignment {
+        match keyword {
+            "auto" => Alignment::Auto,
+            "stretch" => Alignment::Stretch,
+            "start" | "self-start" => Alignment::Start,
+            "end" | "self-end" => Alignment::End,
+            "flex-start" => Alignment::FlexStart,
+            "flex-end" => Alignment::FlexEnd,
+            "center" => Alignment::Center,
+            "left" => Alignment::Left,
+            "right" => Alignment::Right,
+            "baseline" => Alignment::Baseline,
+            "space-between" => Alignment::SpaceBetween,
+            "space-around" => Alignment::SpaceAround,
+            "space-evenly" => Alignment::SpaceEvenly,
+            _ => Alignment::Normal,
+        }
+    }
+}
+
+impl Default for ComputedStyle {
+    fn default() -> Self {
+        ComputedStyle::from_styles(&HashMap::new())
+    }
+}
+
+impl ComputedStyle {
+    /// Style of an anonymous box inside a box with this style: the inherited
+    /// properties come from it, the others take their initial values.
+    pub fn anonymous_child(&self, display: DisplayType) -> ComputedStyle {
+        ComputedStyle {
+            display,
+            color: self.color.clone(),
+            font_style: self.font_style,
+            font_weight: self.font_weight,
+            font_size: self.font_size,
+            line_height: self.line_height,
+            font_family: self.font_family.clone(),
+            list_style_type: self.list_style_type,
+            text_align: self.text_align,
+            visibility: self.visibility,
+            white_space: self.white_space,
+            ..ComputedStyle::default()
+        }
+    }
+
+    /// Builds the struct from a node's computed values. Missing properties
+    /// take their initial value.
+    pub fn from_styles(styles: &HashMap<PropertyName, PropertyValue>) -> ComputedStyle {
+        let value = |name: PropertyName| styles.get(&name).cloned().unwrap_or_else(|| name.info().initial);
+        let keyword = |name: PropertyName| match value(name) {
+            PropertyValue::Keyword(keyword) => keyword,
+            _ => String::new(),
+        };
+        let px = |name: PropertyName| match value(name) {
+            PropertyValue::Length(length) => length.resolve(0.0),
+            _ => 0.0,
+        };
+        let number = |name: PropertyName| match value(name) {
+            PropertyValue::Number(number) => number,
+            _ => 0.0,
+        };
+        let size = |name: PropertyName| match value(name) {
+            PropertyValue::Length(length) => Size::Length(length),
+            _ => Size::Auto,
+        };
+        let length = |name: PropertyName| match value(name) {
+            PropertyValue::Length(length) => length,
+            _ => Length::Px(0.0),
+        };
+        let tracks = |name: PropertyName| match value(name) {
+            PropertyValue::Tracks(list) => list,
+            _ => TrackList::default(),
+        };
+        let auto_tracks = |name: PropertyName| match value(name) {
+            PropertyValue::Tracks(list) => list.tracks,
+            _ => vec![Track { min: TrackBreadth::Auto, max: TrackBreadth::Auto }],
+        };
+        let grid_line = |name: PropertyName| match value(name) {
+            PropertyValue::GridLine(line) => line,
+            _ => GridLine::Auto,
+        };
+        let border_style = |name: PropertyName| match value(name) {
+            PropertyValue::BorderStyle(style) => style,
+            _ => BorderStyle::None,
+        };
+        let current_color = match value(PropertyName::Color) {
+            PropertyValue::Color(color) => color,
+            _ => Color::Named("black".to_string()),
+        };
+        let color = |name: PropertyName| {
+            let color = match value(name) {
+                PropertyValue::Color(color) => color.resolve(&current_color),
+                _ => current_color.clone(),
+            };
+            let (r, g, b, a) = color.get_rgba();
+            Color::Rgba(r, g, b, a)
+        };
+
+        ComputedStyle {
+            display: match value(PropertyName::Display) {
+                PropertyValue::Display(display) => display,
+                _ => DisplayType::Inline,
+            },
+            color: color(PropertyName::Color),
+            background_color: color(PropertyName::BackgroundColor),
+            width: size(PropertyName::Width),
+            height: size(PropertyName::Height),
+            margin: Sides {
+                top: size(PropertyName::MarginTop),
+                right: size(PropertyName::MarginRight),
+                bottom: size(PropertyName::MarginBottom),
+                left: size(PropertyName::MarginLeft),
+            },
+            padding: Sides {
+                top: length(PropertyName::PaddingTop),
+                right: length(PropertyName::PaddingRight),
+                bottom: length(PropertyName::PaddingBottom),
+                left: length(PropertyName::PaddingLeft),
+            },
+            border_width: Sides {
+                top: px(PropertyName::BorderTopWidth),
+                right: px(PropertyName::BorderRightWidth),
+                bottom: px(PropertyName::BorderBottomWidth),
+                left: px(PropertyName::BorderLeftWidth),
+            },
+            border_style: Sides {
+                top: border_style(PropertyName::BorderTopStyle),
+                right: border_style(PropertyName::BorderRightStyle),
+                bottom: border_style(PropertyName::BorderBottomStyle),
+                left: border_style(PropertyName::BorderLeftStyle),
+            },
+            border_color: Sides {
+                top: color(PropertyName::BorderTopColor),
+                right: color(PropertyName::BorderRightColor),
+                bottom: color(PropertyName::BorderBottomColor),
+                left: color(PropertyName::BorderLeftColor),
+            },
+            font_style: match keyword(PropertyName::FontStyle).as_str() {
+                "italic" => FontStyle::Italic,
+                "oblique" => FontStyle::Oblique,
+                _ => FontStyle::Normal,
+            },
+            font_weight: match value(PropertyName::FontWeight) {
+                PropertyValue::Number(weight) => weight,
+                _ => 400.0,
+            },
+            font_size: px(PropertyName::FontSize),
+            line_height: match value(PropertyName::LineHeight) {
+                PropertyValue::Number(number) => LineHeight::Number(number),
+                PropertyValue::Length(length) => LineHeight::Px(length.resolve(0.0)),
+                _ => LineHeight::Normal,
+            },
+            font_family: match value(PropertyName::FontFamily) {
+                PropertyValue::FontFamily(families) => families,
+                _ => Vec::new(),
+            },
+            list_style_type: match keyword(PropertyName::ListStyleType).as_str() {
+                "none" => ListStyleType::None,
+                "circle" => ListStyleType::Circle,
+                "square" => ListStyleType::Square,
+                "decimal" => ListStyleType::Decimal,
+                "lower-alpha" => ListStyleType::LowerAlpha,
+                "upper-alpha" => ListStyleType::UpperAlpha,
+                "lower-roman" => ListStyleType::LowerRoman,
+                "upper-roman" => ListStyleType::UpperRoman,
+                _ => ListStyleType::Disc,
+            },
+            text_align: match keyword(PropertyName::TextAlign).as_str() {
+                "end" => TextAlign::End,
+                "left" => TextAlign::Left,
+                "right" => TextAlign::Right,
+                "center" => TextAlign::Center,
+                "justify" => TextAlign::Justify,
+                _ => TextAlign::Start,
+            },
+            visibility: match keyword(PropertyName::Visibility).as_str() {
+                "hidden" => Visibility::Hidden,
+                "collapse" => Visibility::Collapse,
+                _ => Visibility::Visible,
+            },
+            white_space: match keyword(PropertyName::WhiteSpace).as_str() {
+                "pre" => WhiteSpace::Pre,
+                "nowrap" => WhiteSpace::Nowrap,
+                "pre-wrap" => WhiteSpace::PreWrap,
+                "pre-line" => WhiteSpace::PreLine,
+                "break-spaces" => WhiteSpace::BreakSpaces,
+                _ => WhiteSpace::Normal,
+            },
+            flex_direction: match keyword(PropertyName::FlexDirection).as_str() {
+                "row-reverse" => FlexDirection::RowReverse,
+                "column" => FlexDirection::Column,
+                "column-reverse" => FlexDirection::ColumnReverse,
+                _ => FlexDirection::Row,
+            },
+            flex_wrap: match keyword(PropertyName::FlexWrap).as_str() {
+                "wrap" => FlexWrap::Wrap,
+                "wrap-reverse" => FlexWrap::WrapReverse,
+                _ => FlexWrap::NoWrap,
+            },
+            flex_grow: number(PropertyName::FlexGrow),
+            flex_shrink: number(PropertyName::FlexShrink),
+            flex_basis: match value(PropertyName::FlexBasis) {
+                PropertyValue::Length(length) => FlexBasis::Length(length),
+                PropertyValue::Keyword(keyword) if keyword == "content" => FlexBasis::Content,
+                _ => FlexBasis::Auto,
+            },
+            order: number(PropertyName::Order) as i32,
+            justify_content: Alignment::from_keyword(&keyword(PropertyName::JustifyContent)),
+            align_items: Alignment::from_keyword(&keyword(PropertyName::AlignItems)),
+            align_self: Alignment::from_keyword(&keyword(PropertyName::AlignSelf)),
+            align_content: Alignment::from_keyword(&keyword(PropertyName::AlignContent)),
+            // `normal` is no gap outside of multi-column layout
+            row_gap: length(PropertyName::RowGap),
+            column_gap: length(PropertyName::ColumnGap),
+            justify_items: Alignment::from_keyword(&keyword(PropertyName::JustifyItems)),
+            justify_self: Alignment::from_keyword(&keyword(PropertyName::JustifySelf)),
+            grid_template_columns: tracks(PropertyName::GridTemplateColumns),
+            grid_template_rows: tracks(PropertyName::GridTemplateRows),
+            grid_template_areas: match value(PropertyName::GridTemplateAreas) {
+                PropertyValue::Areas(rows) => rows,
+                _ => Vec::new(),
+            },
+            grid_auto_columns: auto_tracks(PropertyName::GridAutoColumns),
+            grid_auto_rows: auto_tracks(PropertyName::GridAutoRows),
+            grid_auto_flow: {
+                let flow = keyword(PropertyName::GridAutoFlow);
+                GridAutoFlow { column: flow.starts_with("column"), dense: flow.ends_with("dense") }
+            },
+            grid_row_start: grid_line(PropertyName::GridRowStart),
+            grid_row_end: grid_line(PropertyName::GridRowEnd),
+            grid_column_start: grid_line(PropertyName::GridColumnStart),
+            grid_column_end: grid_line(PropertyName::GridColumnEnd),
+        }
+    }
+}
+
+#[test]
+fn test_computed_style_from_styles() {
+    let mut styles = HashMap::new();
+    styles.insert(PropertyName::Color, PropertyValue::Color(Color::Named("red".to_string())));
+    styles.insert(PropertyName::BorderTopColor, PropertyValue::Color(Color::Current));
+    styles.insert(PropertyName::Width, PropertyValue::Length(Length::Percent(50.0)));
+    styles.insert(PropertyName::MarginLeft, PropertyValue::Keyword("auto".to_string()));
+    styles.insert(PropertyName::LineHeight, PropertyValue::Number(1.5));
+    styles.insert(PropertyName::TextAlign, PropertyValue::Keyword("center".to_string()));
+    let style = ComputedStyle::from_styles(&styles);
+
+    assert_eq!(style.color, Color::Rgba(255, 0, 0, 255));
+    // currentColor is resolved, the rest takes initial values
+    assert_eq!(style.border_color.top, Color::Rgba(255, 0, 0, 255));
+    assert_eq!(style.background_color, Color::Rgba(0, 0, 0, 0));
+    assert_eq!(style.width.resolve(200.0), Some(100.0));
+    assert_eq!(style.height, Size::Auto);
+    assert_eq!(style.margin.left, Size::Auto);
+    assert_eq!(style.margin.top.resolve(200.0), Some(0.0));
+    assert_eq!(style.line_height.resolve(style.font_size), 24.0);
+    assert_eq!(style.text_align, TextAlign::Center);
+    assert_eq!(style.display, DisplayType::Inline);
+}
+
+#[test]
+fn test_list_markers() {
+    assert_eq!(ListStyleType::Decimal.marker(3), Some(String::from("3.")));
+    assert_eq!(ListStyleType::LowerAlpha.marker(28), Some(String::from("ab.")));
+    assert_eq!(ListStyleType::UpperRoman.marker(1994), Some(String::from("MCMXCIV.")));
+    assert_eq!(ListStyleType::LowerRoman.marker(4000), Some(String::from("4000.")));
+    assert_eq!(ListStyleType::Disc.marker(1), Some(String::from("\u{2022}")));
+    assert_eq!(ListStyleType::None.marker(1), None);
+}